@@ -0,0 +1,73 @@
+use circular_buffer::CircularBuffer;
+
+const FIFO_SIZE: usize = 32;
+/// A DMA refill is requested once this many samples or fewer are left
+const REFILL_THRESHOLD: usize = FIFO_SIZE / 2;
+
+/// A DirectSound channel. The CPU or DMA fills it with signed 8-bit samples
+/// and the selected timer plays one on each overflow
+#[derive(Debug)]
+pub struct Fifo {
+    samples: CircularBuffer<FIFO_SIZE, i8>,
+    /// The sample being played. It is kept when the FIFO runs empty
+    pub sample: i8,
+}
+
+impl Default for Fifo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Self {
+            samples: CircularBuffer::new(),
+            sample: 0,
+        }
+    }
+
+    /// Writing to a full FIFO overwrites the oldest sample
+    pub fn push(&mut self, sample: u8) {
+        self.samples.push_back(sample as i8);
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.sample = 0;
+    }
+
+    /// Play the next sample. Returns whether DMA should refill the FIFO
+    pub fn pop(&mut self) -> bool {
+        if let Some(sample) = self.samples.pop_front() {
+            self.sample = sample;
+        }
+        self.samples.len() <= REFILL_THRESHOLD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::fifo::{FIFO_SIZE, Fifo};
+
+    #[test]
+    fn test_fifo() {
+        let mut fifo = Fifo::new();
+        for sample in 0..FIFO_SIZE as u8 + 1 {
+            fifo.push(sample.wrapping_sub(8));
+        }
+        // The first sample was overwritten
+        assert!(!fifo.pop());
+        assert_eq!(fifo.sample, -7);
+
+        for _ in 0..14 {
+            fifo.pop();
+        }
+        assert_eq!(fifo.sample, 7);
+        assert!(fifo.pop());
+
+        fifo.reset();
+        assert!(fifo.pop());
+        assert_eq!(fifo.sample, 0);
+    }
+}
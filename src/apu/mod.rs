@@ -0,0 +1,609 @@
+use crate::apu::fifo::Fifo;
+use crate::apu::noise::Noise;
+use crate::apu::square::Square;
+use crate::apu::wave::Wave;
+use crate::system_bus::REG_SOUNDBIAS;
+use circular_buffer::CircularBuffer;
+
+pub mod fifo;
+pub mod noise;
+pub mod resampler;
+pub mod sink;
+pub mod square;
+pub mod wave;
+
+/// Offsets of the sound registers from `IO_REGISTERS_START`
+pub const REG_SOUND1CNT_L: usize = 0x060;
+pub const REG_SOUND1CNT_H: usize = 0x062;
+pub const REG_SOUND1CNT_X: usize = 0x064;
+pub const REG_SOUND2CNT_L: usize = 0x068;
+pub const REG_SOUND2CNT_H: usize = 0x06C;
+pub const REG_SOUND3CNT_L: usize = 0x070;
+pub const REG_SOUND3CNT_H: usize = 0x072;
+pub const REG_SOUND3CNT_X: usize = 0x074;
+pub const REG_SOUND4CNT_L: usize = 0x078;
+pub const REG_SOUND4CNT_H: usize = 0x07C;
+pub const REG_SOUNDCNT_L: usize = 0x080;
+pub const REG_SOUNDCNT_H: usize = 0x082;
+pub const REG_SOUNDCNT_X: usize = 0x084;
+pub const REG_WAVE_RAM: usize = 0x090;
+pub const WAVE_RAM_SIZE: usize = 16;
+/// FIFO A, followed by FIFO B
+pub const REG_FIFO_A: usize = 0x0A0;
+pub const FIFO_REGISTER_SIZE: usize = 4;
+
+/// The registers from SOUND1CNT_L up to SOUNDBIAS, which is a plain register
+const SOUND_REGISTERS_SIZE: usize = REG_SOUNDBIAS - REG_SOUND1CNT_L;
+
+/// The bits of each sound register that can be read back. Lengths,
+/// frequencies and the trigger bits are write-only
+const READ_MASKS: [(usize, u16); 13] = [
+    (REG_SOUND1CNT_L, 0x007F),
+    (REG_SOUND1CNT_H, 0xFFC0),
+    (REG_SOUND1CNT_X, 0x4000),
+    (REG_SOUND2CNT_L, 0xFFC0),
+    (REG_SOUND2CNT_H, 0x4000),
+    (REG_SOUND3CNT_L, 0x00E0),
+    (REG_SOUND3CNT_H, 0xE000),
+    (REG_SOUND3CNT_X, 0x4000),
+    (REG_SOUND4CNT_L, 0xFF00),
+    (REG_SOUND4CNT_H, 0x40FF),
+    (REG_SOUNDCNT_L, 0xFF77),
+    (REG_SOUNDCNT_H, 0x770F),
+    (REG_SOUNDCNT_X, 0x0080),
+];
+
+const SOUNDCNT_X_ENABLE: u16 = 1 << 7;
+/// Bits 2-3 set FIFO A and B to full instead of half volume
+const SOUNDCNT_H_FIFO_FULL: u16 = 1 << 2;
+/// Bits 8-11 control FIFO A and bits 12-15 FIFO B
+const SOUNDCNT_H_FIFO_RIGHT: u16 = 1 << 8;
+const SOUNDCNT_H_FIFO_LEFT: u16 = 1 << 9;
+const SOUNDCNT_H_FIFO_TIMER: u16 = 1 << 10;
+const SOUNDCNT_H_FIFO_RESET: u16 = 1 << 11;
+
+/// The output is 10 bits, centered on the bias level
+const SOUNDBIAS_LEVEL: u16 = 0x3FE;
+/// Bits 14-15 trade bits of the output for a higher sample rate
+const SOUNDBIAS_RESOLUTION_SHIFT: u16 = 14;
+const OUTPUT_CENTER: i16 = 0x200;
+
+/// The frame sequencer clocks lengths, sweeps and envelopes at 512 Hz
+const FRAME_SEQUENCER_CYCLES: u32 = 32768;
+/// Samples are mixed at 32768 Hz unless SOUNDBIAS selects a higher rate
+const SAMPLE_CYCLES: u32 = 512;
+const SAMPLE_RATE: u32 = 32768;
+/// A quarter second of samples at the lowest rate. The oldest are dropped
+/// when nothing drains them
+const SAMPLE_BUFFER_SIZE: usize = 8192;
+
+/// Sets how many frame sequencer steps a channel is played for
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct LengthCounter {
+    max: u16,
+    counter: u16,
+    pub enabled: bool,
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// The register holds the length as `max - counter`
+    pub fn load(&mut self, length: u16) {
+        self.counter = self.max - length;
+    }
+
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Returns whether the channel should be stopped
+    pub fn tick(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+}
+
+/// The volume envelope of the square and noise channels, set by the upper
+/// byte of their control registers
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    pub volume: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, value: u8) {
+        self.period = value & 7;
+        self.increase = value & (1 << 3) != 0;
+        self.initial = value >> 4;
+    }
+
+    /// A volume of 0 that only decreases turns the channel off
+    pub fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    pub fn tick(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+/// Counts `cycles` down and returns how many times `period` elapsed
+pub(super) fn elapse(timer: &mut u32, period: u32, cycles: u32) -> u32 {
+    let mut steps = 0;
+    let mut cycles = cycles;
+    while cycles >= *timer {
+        cycles -= *timer;
+        *timer = period;
+        steps += 1;
+    }
+    *timer -= cycles;
+    steps
+}
+
+/// The sound controller. The four channels are the ones of the Game Boy,
+/// clocked four times as fast
+pub struct Apu {
+    /// Everything written to the sound registers, including write-only bits
+    registers: [u8; SOUND_REGISTERS_SIZE],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    fifos: [Fifo; 2],
+
+    frame_sequencer_timer: u32,
+    frame_sequencer_step: u8,
+    sample_timer: u32,
+    /// SOUNDBIAS bits 14-15 when the last sample was mixed
+    resolution: u16,
+    /// Stereo samples with the resolution they were mixed at
+    samples: CircularBuffer<SAMPLE_BUFFER_SIZE, ([i16; 2], u16)>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            registers: [0x00; SOUND_REGISTERS_SIZE],
+            square1: Square::new(),
+            square2: Square::new(),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            fifos: [Fifo::new(), Fifo::new()],
+            frame_sequencer_timer: FRAME_SEQUENCER_CYCLES,
+            frame_sequencer_step: 0,
+            sample_timer: SAMPLE_CYCLES,
+            resolution: 0,
+            samples: CircularBuffer::new(),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.register(REG_SOUNDCNT_X) & SOUNDCNT_X_ENABLE != 0
+    }
+
+    fn register(&self, offset: usize) -> u16 {
+        let index = offset - REG_SOUND1CNT_L;
+        u16::from_le_bytes([self.registers[index], self.registers[index + 1]])
+    }
+
+    /// Handle a write to the sound registers or wave RAM. `io_registers`
+    /// gets the value as it reads back
+    pub fn write_byte(&mut self, offset: usize, value: u8, io_registers: &mut [u8]) {
+        if (REG_WAVE_RAM..REG_WAVE_RAM + WAVE_RAM_SIZE).contains(&offset) {
+            self.wave.write_ram(offset - REG_WAVE_RAM, value);
+            io_registers[offset] = value;
+            return;
+        }
+        // The PSG registers are read-only while the sound is off
+        let register = offset & !1;
+        if !self.enabled() && register < REG_SOUNDCNT_H {
+            return;
+        }
+
+        self.registers[offset - REG_SOUND1CNT_L] = value;
+        let high = offset & 1 == 1;
+        let data = self.register(register);
+        match register {
+            REG_SOUND1CNT_L => self.square1.write_sweep(data as u8),
+            REG_SOUND1CNT_H => self.square1.write_control(data, high),
+            REG_SOUND1CNT_X => self.square1.write_frequency(data, high),
+            REG_SOUND2CNT_L => self.square2.write_control(data, high),
+            REG_SOUND2CNT_H => self.square2.write_frequency(data, high),
+            REG_SOUND3CNT_L => {
+                self.wave.write_select(data as u8);
+                self.wave.copy_ram(&mut io_registers[REG_WAVE_RAM..]);
+            }
+            REG_SOUND3CNT_H => self.wave.write_control(data, high),
+            REG_SOUND3CNT_X => self.wave.write_frequency(data, high),
+            REG_SOUND4CNT_L => self.noise.write_control(data, high),
+            REG_SOUND4CNT_H => self.noise.write_frequency(data, high),
+            REG_SOUNDCNT_H if high => self.reset_fifos(data),
+            REG_SOUNDCNT_X if !self.enabled() => self.power_off(),
+            _ => {}
+        }
+        self.update_io_registers(io_registers);
+    }
+
+    /// The reset bits clear the FIFOs and read back as 0
+    fn reset_fifos(&mut self, control: u16) {
+        for (fifo, shift) in [0, 4].into_iter().enumerate() {
+            if control & (SOUNDCNT_H_FIFO_RESET << shift) != 0 {
+                self.fifos[fifo].reset();
+            }
+        }
+        self.registers[REG_SOUNDCNT_H + 1 - REG_SOUND1CNT_L] &= 0x77;
+    }
+
+    /// Queue a sample written to FIFO A (0) or B (1)
+    pub fn write_fifo(&mut self, fifo: usize, value: u8) {
+        self.fifos[fifo].push(value);
+    }
+
+    /// Play the next sample of the FIFOs driven by `timer` for each of its
+    /// `overflows`. Returns which FIFOs need a DMA refill
+    pub fn timer_overflow(&mut self, timer: usize, overflows: u32) -> [bool; 2] {
+        let control = self.register(REG_SOUNDCNT_H);
+        let mut refill = [false; 2];
+        if !self.enabled() {
+            return refill;
+        }
+        for (fifo, shift) in [0, 4].into_iter().enumerate() {
+            let selected = (control & (SOUNDCNT_H_FIFO_TIMER << shift) != 0) as usize;
+            if selected == timer {
+                for _ in 0..overflows {
+                    refill[fifo] |= self.fifos[fifo].pop();
+                }
+            }
+        }
+        refill
+    }
+
+    /// Turning the sound off clears all of the PSG registers
+    fn power_off(&mut self) {
+        self.registers[..REG_SOUNDCNT_H - REG_SOUND1CNT_L].fill(0x00);
+        self.square1 = Square::new();
+        self.square2 = Square::new();
+        self.wave.power_off();
+        self.noise = Noise::new();
+        for fifo in &mut self.fifos {
+            fifo.reset();
+        }
+    }
+
+    fn update_io_registers(&self, io_registers: &mut [u8]) {
+        io_registers[REG_SOUND1CNT_L..REG_SOUNDBIAS].fill(0x00);
+        for (offset, mask) in READ_MASKS {
+            let value = self.register(offset) & mask;
+            io_registers[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        io_registers[REG_SOUNDCNT_X] |= self.channels_active();
+    }
+
+    /// Bits 0-3 of SOUNDCNT_X
+    fn channels_active(&self) -> u8 {
+        [
+            self.square1.enabled,
+            self.square2.enabled,
+            self.wave.enabled,
+            self.noise.enabled,
+        ]
+        .into_iter()
+        .enumerate()
+        .fold(0, |active, (channel, enabled)| {
+            active | ((enabled as u8) << channel)
+        })
+    }
+
+    /// Samples keep being mixed while the sound is off so the audio follows
+    /// the emulated time. They are silent at the bias level
+    pub fn tick(&mut self, cycles: u32, io_registers: &mut [u8]) {
+        let enabled = self.enabled();
+        let active = self.channels_active();
+
+        if enabled {
+            for _ in 0..elapse(
+                &mut self.frame_sequencer_timer,
+                FRAME_SEQUENCER_CYCLES,
+                cycles,
+            ) {
+                self.step_frame_sequencer();
+            }
+            self.square1.tick(cycles);
+            self.square2.tick(cycles);
+            self.wave.tick(cycles);
+            self.noise.tick(cycles);
+        }
+
+        let soundbias =
+            u16::from_le_bytes([io_registers[REG_SOUNDBIAS], io_registers[REG_SOUNDBIAS + 1]]);
+        let bias = soundbias & SOUNDBIAS_LEVEL;
+        self.resolution = soundbias >> SOUNDBIAS_RESOLUTION_SHIFT;
+        let sample_cycles = SAMPLE_CYCLES >> self.resolution;
+        self.sample_timer = self.sample_timer.min(sample_cycles);
+        for _ in 0..elapse(&mut self.sample_timer, sample_cycles, cycles) {
+            let sample = if enabled {
+                self.mix(bias)
+            } else {
+                [self.output_level(bias, 0); 2]
+            };
+            self.samples.push_back((sample, self.resolution));
+        }
+
+        if self.channels_active() != active {
+            self.update_io_registers(io_registers);
+        }
+    }
+
+    /// Lengths are clocked on even steps, the sweep on steps 2 and 6 and the
+    /// envelopes on step 7
+    fn step_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        self.frame_sequencer_step = (step + 1) % 8;
+
+        if step.is_multiple_of(2) {
+            self.square1.tick_length();
+            self.square2.tick_length();
+            self.wave.tick_length();
+            self.noise.tick_length();
+        }
+        if step == 2 || step == 6 {
+            self.square1.tick_sweep();
+        }
+        if step == 7 {
+            self.square1.envelope.tick();
+            self.square2.envelope.tick();
+            self.noise.envelope.tick();
+        }
+    }
+
+    /// Mix the PSG channels enabled for each side in SOUNDCNT_L with the
+    /// FIFOs enabled in SOUNDCNT_H. The sum is offset by `bias`, clipped to
+    /// 10 bits like the output of the GBA and cut to the selected resolution
+    fn mix(&self, bias: u16) -> [i16; 2] {
+        let control = self.register(REG_SOUNDCNT_L);
+        let direct = self.register(REG_SOUNDCNT_H);
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+        // 25%, 50% or 100%
+        let psg_shift = 2 - (direct & 3).min(2);
+
+        // Right is in the low bits
+        [(4, 12, SOUNDCNT_H_FIFO_LEFT), (0, 8, SOUNDCNT_H_FIFO_RIGHT)].map(
+            |(volume_shift, enable_shift, fifo_enable)| {
+                let volume = ((control >> volume_shift) & 7) as i16 + 1;
+                let psg: i16 = outputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(channel, _)| control & (1 << (enable_shift + channel)) != 0)
+                    .map(|(_, output)| *output as i16)
+                    .sum();
+                let mut level = (psg * volume) >> psg_shift;
+
+                for (fifo, shift) in [0, 4].into_iter().enumerate() {
+                    if direct & (fifo_enable << shift) != 0 {
+                        let scale = if direct & (SOUNDCNT_H_FIFO_FULL << fifo) != 0 {
+                            4
+                        } else {
+                            2
+                        };
+                        level += self.fifos[fifo].sample as i16 * scale;
+                    }
+                }
+
+                self.output_level(bias, level)
+            },
+        )
+    }
+
+    /// `level` offset by `bias`, clipped to 10 bits and cut to the selected
+    /// resolution, as a 16-bit sample
+    fn output_level(&self, bias: u16, level: i16) -> i16 {
+        let level = (bias as i16 + level).clamp(0, 0x3FF) & !((2 << self.resolution) - 1);
+        (level - OUTPUT_CENTER) * 64
+    }
+
+    /// Take the stereo samples mixed so far, left first, in runs of the same
+    /// rate in Hz: 32768, 65536, 131072 or 262144. The rate changes when
+    /// SOUNDBIAS is written between two samples
+    pub fn drain_runs(&mut self) -> Vec<(u32, Vec<[i16; 2]>)> {
+        let mut runs: Vec<(u32, Vec<[i16; 2]>)> = Vec::new();
+        for (sample, resolution) in self.samples.drain(..) {
+            let sample_rate = SAMPLE_RATE << resolution;
+            match runs.last_mut() {
+                Some((run_rate, run)) if *run_rate == sample_rate => run.push(sample),
+                _ => runs.push((sample_rate, vec![sample])),
+            }
+        }
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::{
+        Apu, Envelope, LengthCounter, REG_SOUND1CNT_H, REG_SOUND1CNT_X, REG_SOUNDCNT_H,
+        REG_SOUNDCNT_L, REG_SOUNDCNT_X,
+    };
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_SOUNDBIAS};
+
+    fn write(apu: &mut Apu, io_registers: &mut [u8], offset: usize, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        apu.write_byte(offset, low, io_registers);
+        apu.write_byte(offset + 1, high, io_registers);
+    }
+
+    /// The samples mixed so far, which all have to be at `sample_rate`
+    fn drain(apu: &mut Apu, sample_rate: u32) -> Vec<[i16; 2]> {
+        let runs = apu.drain_runs();
+        assert!(runs.iter().all(|(rate, _)| *rate == sample_rate));
+        runs.into_iter().flat_map(|(_, run)| run).collect()
+    }
+
+    #[test]
+    fn test_envelope_and_length() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x2A);
+        envelope.trigger();
+        let volumes: Vec<u8> = (0..4)
+            .map(|_| {
+                envelope.tick();
+                envelope.volume
+            })
+            .collect();
+        assert_eq!(volumes, [2, 3, 3, 4]);
+        envelope.write(0x00);
+        assert!(!envelope.dac_enabled());
+
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        length.enabled = true;
+        assert!(!length.tick());
+        assert!(length.tick());
+        assert!(!length.tick());
+        length.trigger();
+        assert_eq!(length.counter, 64);
+    }
+
+    #[test]
+    fn test_sound_registers() {
+        let mut apu = Apu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+
+        // Ignored while the sound is off
+        write(&mut apu, &mut io_registers, REG_SOUND1CNT_H, 0xF080);
+        assert_eq!(io_registers[REG_SOUND1CNT_H + 1], 0x00);
+
+        write(&mut apu, &mut io_registers, REG_SOUNDCNT_X, 0x0080);
+        write(&mut apu, &mut io_registers, REG_SOUNDCNT_L, 0x1177);
+        write(&mut apu, &mut io_registers, REG_SOUNDCNT_H, 0x0002);
+        io_registers[REG_SOUNDBIAS + 1] = 0x02;
+        // The length is write-only
+        write(&mut apu, &mut io_registers, REG_SOUND1CNT_H, 0xF0BF);
+        assert_eq!(io_registers[REG_SOUND1CNT_H], 0x80);
+        // Triggering starts the channel, which shows in SOUNDCNT_X
+        write(&mut apu, &mut io_registers, REG_SOUND1CNT_X, 0xC700);
+        assert_eq!(
+            io_registers[REG_SOUND1CNT_X..REG_SOUND1CNT_X + 2],
+            [0x00, 0x40]
+        );
+        assert_eq!(io_registers[REG_SOUNDCNT_X], 0x81);
+
+        // A length of 1 stops the channel at the next length clock
+        for _ in 0..128 {
+            apu.tick(512, &mut io_registers);
+        }
+        assert_eq!(io_registers[REG_SOUNDCNT_X], 0x80);
+        let samples = drain(&mut apu, 32768);
+        assert_eq!(samples.len(), 128);
+        assert_eq!(samples[0], [15 * 8 * 64, 15 * 8 * 64]);
+        assert_eq!(samples[127], [0, 0]);
+
+        // Turning the sound off clears the registers
+        write(&mut apu, &mut io_registers, REG_SOUNDCNT_X, 0x0000);
+        assert_eq!(
+            io_registers[REG_SOUNDCNT_L..REG_SOUNDCNT_L + 2],
+            [0x00, 0x00]
+        );
+        assert_eq!(io_registers[REG_SOUND1CNT_H + 1], 0x00);
+
+        // Samples at the bias level are still mixed
+        io_registers[REG_SOUNDBIAS + 1] = 0x01;
+        apu.tick(512 * 4, &mut io_registers);
+        let samples = drain(&mut apu, 32768);
+        assert_eq!(samples, [[-0x100 * 64; 2]; 4]);
+    }
+
+    #[test]
+    fn test_direct_sound() {
+        let mut apu = Apu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        io_registers[REG_SOUNDBIAS + 1] = 0x02;
+        write(&mut apu, &mut io_registers, REG_SOUNDCNT_X, 0x0080);
+        // FIFO A at full volume on both sides with timer 0 and FIFO B at half
+        // volume on the left with timer 1
+        write(&mut apu, &mut io_registers, REG_SOUNDCNT_H, 0x6304);
+        assert_eq!(io_registers[REG_SOUNDCNT_H + 1], 0x63);
+
+        for sample in [0x10, 0x20, 0xF0] {
+            apu.write_fifo(0, sample);
+        }
+        apu.write_fifo(1, 0x40);
+        assert_eq!(apu.timer_overflow(0, 2), [true, false]);
+        assert_eq!(apu.timer_overflow(1, 1), [false, true]);
+        apu.tick(512, &mut io_registers);
+        let samples = drain(&mut apu, 32768);
+        assert_eq!(samples, [[(0x20 * 4 + 0x40 * 2) * 64, 0x20 * 4 * 64]]);
+
+        // Clipped at the top of the 10-bit range
+        apu.write_fifo(0, 0x7F);
+        apu.write_fifo(1, 0x7F);
+        apu.timer_overflow(0, 2);
+        apu.timer_overflow(1, 1);
+        apu.tick(512, &mut io_registers);
+        let samples = drain(&mut apu, 32768);
+        assert_eq!(samples[0][0], 0x1FE * 64);
+
+        // The lowest resolution mixes 8 samples in the same time and drops
+        // the low 4 bits of the output
+        io_registers[REG_SOUNDBIAS + 1] = 0xC2;
+        apu.tick(512, &mut io_registers);
+        io_registers[REG_SOUNDBIAS + 1] = 0x02;
+        apu.tick(512, &mut io_registers);
+        let runs = apu.drain_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].0, 262144);
+        assert_eq!(runs[0].1.len(), 8);
+        assert_eq!(runs[0].1[0][1], 0x1F0 * 64);
+        assert_eq!(runs[1].0, 32768);
+        assert_eq!(runs[1].1.len(), 1);
+
+        // Resetting FIFO A silences it
+        write(&mut apu, &mut io_registers, REG_SOUNDCNT_H, 0x0B04);
+        apu.tick(512, &mut io_registers);
+        let samples = drain(&mut apu, 32768);
+        assert_eq!(samples, [[0, 0]]);
+    }
+}
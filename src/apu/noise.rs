@@ -0,0 +1,137 @@
+use crate::apu::{Envelope, LengthCounter, elapse};
+
+/// Channel 4, which plays the output of a linear-feedback shift register
+#[derive(Debug, Copy, Clone)]
+pub struct Noise {
+    length: LengthCounter,
+    pub(super) envelope: Envelope,
+    /// SOUND4CNT_H bits 0-7: divider ratio, 7-bit mode and shift
+    control: u8,
+    lfsr: u16,
+    timer: u32,
+    pub enabled: bool,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            control: 0,
+            lfsr: 0,
+            timer: Self::period(0),
+            enabled: false,
+        }
+    }
+
+    /// Cycles per shift. The ratio divides the clock by 8, 16, ... 112 and
+    /// the shift divides it further by powers of two
+    fn period(control: u8) -> u32 {
+        let ratio = match control & 7 {
+            0 => 8,
+            ratio => ratio as u32 * 16,
+        };
+        (ratio << (control >> 4)) * 4
+    }
+
+    fn short_mode(&self) -> bool {
+        self.control & (1 << 3) != 0
+    }
+
+    /// SOUND4CNT_L: length in the low byte and the envelope in the high byte
+    pub fn write_control(&mut self, value: u16, high: bool) {
+        if high {
+            self.envelope.write((value >> 8) as u8);
+            self.enabled &= self.envelope.dac_enabled();
+        } else {
+            self.length.load(value & 0x3F);
+        }
+    }
+
+    /// SOUND4CNT_H
+    pub fn write_frequency(&mut self, value: u16, high: bool) {
+        self.control = value as u8;
+        if high {
+            self.length.enabled = value & (1 << 14) != 0;
+            if value & (1 << 15) != 0 {
+                self.trigger();
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.timer = Self::period(self.control);
+        self.lfsr = if self.short_mode() { 0x7F } else { 0x7FFF };
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..elapse(&mut self.timer, Self::period(self.control), cycles) {
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr >>= 1;
+            if self.short_mode() {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+            } else {
+                self.lfsr |= feedback << 14;
+            }
+        }
+    }
+
+    pub fn tick_length(&mut self) {
+        if self.length.tick() {
+            self.enabled = false;
+        }
+    }
+
+    /// The current level, from -15 to 15
+    pub fn output(&self) -> i8 {
+        if !self.enabled {
+            0
+        } else if self.lfsr & 1 == 0 {
+            self.envelope.volume as i8
+        } else {
+            -(self.envelope.volume as i8)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::noise::Noise;
+
+    #[test]
+    fn test_noise() {
+        assert_eq!(Noise::period(0x00), 32);
+        assert_eq!(Noise::period(0x21), 256);
+
+        let mut noise = Noise::new();
+        noise.write_control(0xF000, true);
+        noise.write_frequency(0x8008, true);
+        assert_eq!(noise.output(), -15);
+
+        // The 7-bit register repeats every 127 shifts
+        let mut states = vec![noise.lfsr];
+        for _ in 0..127 {
+            noise.tick(32);
+            states.push(noise.lfsr);
+        }
+        assert_eq!(states[127], states[0]);
+        assert!(states[1..127].iter().all(|state| *state != states[0]));
+        assert!(states.iter().all(|state| *state < 0x80));
+
+        // Only a full period of the 15-bit register brings it back
+        noise.write_frequency(0x8000, true);
+        noise.tick(32 * 127);
+        assert_ne!(noise.lfsr, 0x7FFF);
+        noise.tick(32 * (0x7FFF - 127));
+        assert_eq!(noise.lfsr, 0x7FFF);
+    }
+}
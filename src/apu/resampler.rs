@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+/// How samples between the native ones are interpolated
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ResampleQuality {
+    /// Repeat the closest sample. Cheap, but aliases
+    Nearest,
+    #[default]
+    Linear,
+    /// Catmull-Rom spline through four samples
+    Cubic,
+}
+
+/// Converts stereo samples from the native rate to another one. The ratio can
+/// change between calls, which is how the rate control adjusts it
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    pub quality: ResampleQuality,
+    /// The last four input samples, oldest first. Output is interpolated
+    /// between the middle two
+    history: [[f32; 2]; 4],
+    /// Position between `history[1]` and `history[2]` of the next output
+    /// sample
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(quality: ResampleQuality) -> Self {
+        Self {
+            quality,
+            history: [[0.0; 2]; 4],
+            position: 0.0,
+        }
+    }
+
+    /// Resample `input` by `ratio`, the output rate over the input rate, and
+    /// append the result to `output`
+    pub fn process(&mut self, input: &[[i16; 2]], ratio: f64, output: &mut Vec<[f32; 2]>) {
+        let step = 1.0 / ratio;
+        for sample in input {
+            self.history.rotate_left(1);
+            self.history[3] = sample.map(|channel| channel as f32 / 32768.0);
+
+            while self.position < 1.0 {
+                output.push(self.interpolate(self.position as f32));
+                self.position += step;
+            }
+            self.position -= 1.0;
+        }
+    }
+
+    fn interpolate(&self, t: f32) -> [f32; 2] {
+        let [p0, p1, p2, p3] = self.history;
+        std::array::from_fn(|channel| {
+            let (p0, p1, p2, p3) = (p0[channel], p1[channel], p2[channel], p3[channel]);
+            match self.quality {
+                ResampleQuality::Nearest if t < 0.5 => p1,
+                ResampleQuality::Nearest => p2,
+                ResampleQuality::Linear => p1 + (p2 - p1) * t,
+                ResampleQuality::Cubic => {
+                    let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+                    let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+                    let c = -0.5 * p0 + 0.5 * p2;
+                    ((a * t + b) * t + c) * t + p1
+                }
+            }
+        })
+    }
+}
+
+/// Adjust `ratio` to keep an output buffer half full. `fill` is how full it
+/// is, from 0 to 1. The ratio changes by at most `max_deviation`, which is
+/// too little to hear
+pub fn dynamic_rate(ratio: f64, fill: f64, max_deviation: f64) -> f64 {
+    let fill = fill.clamp(0.0, 1.0);
+    ratio * (1.0 + max_deviation * (1.0 - 2.0 * fill))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::resampler::{ResampleQuality, Resampler, dynamic_rate};
+
+    #[test]
+    fn test_resampler() {
+        let input: Vec<[i16; 2]> = (0..64).map(|i| [i * 256, -i * 256]).collect();
+
+        // 1.6 output samples for each input one
+        for quality in [
+            ResampleQuality::Nearest,
+            ResampleQuality::Linear,
+            ResampleQuality::Cubic,
+        ] {
+            let mut resampler = Resampler::new(quality);
+            let mut output = Vec::new();
+            resampler.process(&input[..32], 1.6, &mut output);
+            resampler.process(&input[32..], 1.6, &mut output);
+            assert_eq!(output.len(), 103);
+            assert!(output.iter().all(|[left, right]| *left == -*right));
+        }
+
+        // A ramp stays a ramp with linear and cubic interpolation. Output
+        // lags the input by two samples
+        let mut resampler = Resampler::new(ResampleQuality::Cubic);
+        let mut output = Vec::new();
+        resampler.process(&input, 2.0, &mut output);
+        assert_eq!(output[20][0], 8.0 * 256.0 / 32768.0);
+        assert_eq!(output[21][0], 8.5 * 256.0 / 32768.0);
+
+        let mut resampler = Resampler::new(ResampleQuality::Nearest);
+        let mut output = Vec::new();
+        resampler.process(&input, 0.5, &mut output);
+        assert_eq!(output.len(), 32);
+        assert_eq!(output[10][0], 18.0 * 256.0 / 32768.0);
+
+        assert_eq!(dynamic_rate(1.5, 0.5, 0.005), 1.5);
+        assert_eq!(dynamic_rate(1.0, 1.0, 0.005), 1.0 - 0.005);
+        assert_eq!(dynamic_rate(1.0, -1.0, 0.005), 1.0 + 0.005);
+    }
+}
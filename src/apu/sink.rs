@@ -0,0 +1,90 @@
+use crate::apu::resampler::{ResampleQuality, Resampler};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Receives the audio of the emulator
+pub trait AudioSink: Send {
+    /// Stereo samples, left first, at `sample_rate`. The rate only changes
+    /// when the game selects another one in SOUNDBIAS
+    fn push(&mut self, samples: &[[i16; 2]], sample_rate: u32);
+}
+
+/// Discards all audio
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn push(&mut self, _samples: &[[i16; 2]], _sample_rate: u32) {}
+}
+
+/// Records the audio to a 16-bit stereo WAV file at a fixed rate. The file is
+/// completed when the sink is dropped
+pub struct WavSink {
+    writer: hound::WavWriter<BufWriter<File>>,
+    sample_rate: u32,
+    resampler: Resampler,
+    resampled: Vec<[f32; 2]>,
+}
+
+impl WavSink {
+    pub fn create(
+        path: &Path,
+        sample_rate: u32,
+        quality: ResampleQuality,
+    ) -> anyhow::Result<Self, String> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(path, spec)
+            .map_err(|err| format!("Cannot create {}: {err}", path.display()))?;
+        Ok(Self {
+            writer,
+            sample_rate,
+            resampler: Resampler::new(quality),
+            resampled: Vec::new(),
+        })
+    }
+}
+
+impl AudioSink for WavSink {
+    fn push(&mut self, samples: &[[i16; 2]], sample_rate: u32) {
+        let ratio = self.sample_rate as f64 / sample_rate as f64;
+        self.resampled.clear();
+        self.resampler.process(samples, ratio, &mut self.resampled);
+
+        for sample in self.resampled.iter().flatten() {
+            let sample = (sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            if let Err(err) = self.writer.write_sample(sample) {
+                log::error!("Cannot write audio: {err}");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::resampler::ResampleQuality;
+    use crate::apu::sink::{AudioSink, WavSink};
+
+    #[test]
+    fn test_wav_sink() {
+        let path = std::env::temp_dir().join(format!("gba-test-{}.wav", std::process::id()));
+        let mut sink = WavSink::create(&path, 65536, ResampleQuality::Nearest).unwrap();
+        sink.push(&[[0x1000, -0x1000]; 100], 32768);
+        drop(sink);
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 65536);
+        assert_eq!(reader.spec().channels, 2);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(samples.len(), 400);
+        assert_eq!(samples[396..], [0x1000, -0x1000, 0x1000, -0x1000]);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::apu::{Envelope, LengthCounter, elapse};
+
+/// The waveforms of the 12.5%, 25%, 50% and 75% duty cycles, played from the
+/// lowest bit
+const DUTY_CYCLES: [u8; 4] = [0b1000_0000, 0b1000_0001, 0b1110_0001, 0b0111_1110];
+
+/// The highest frequency value. The sweep stops the channel past it
+const MAX_FREQUENCY: u16 = 2047;
+
+/// The frequency sweep of channel 1, set by SOUND1CNT_L
+#[derive(Debug, Default, Copy, Clone)]
+struct Sweep {
+    shift: u8,
+    decrease: bool,
+    period: u8,
+    timer: u8,
+    enabled: bool,
+    shadow: u16,
+}
+
+impl Sweep {
+    fn write(&mut self, value: u8) {
+        self.shift = value & 7;
+        self.decrease = value & (1 << 3) != 0;
+        self.period = (value >> 4) & 7;
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 is treated as 8
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.decrease {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    /// Returns whether the first calculation already overflows
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.reload_timer();
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.shift != 0 && self.next_frequency() > MAX_FREQUENCY
+    }
+
+    /// The new frequency when the sweep updates it. Values past
+    /// `MAX_FREQUENCY` stop the channel
+    fn tick(&mut self) -> Option<u16> {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return None;
+        }
+        self.reload_timer();
+        if !self.enabled || self.period == 0 {
+            return None;
+        }
+
+        let frequency = self.next_frequency();
+        if frequency > MAX_FREQUENCY {
+            return Some(frequency);
+        }
+        if self.shift == 0 {
+            return None;
+        }
+        self.shadow = frequency;
+        // The next step is checked for overflow right away
+        let next = self.next_frequency();
+        Some(if next > MAX_FREQUENCY {
+            next
+        } else {
+            frequency
+        })
+    }
+}
+
+/// Channels 1 and 2. Only channel 1 has a sweep
+#[derive(Debug, Copy, Clone)]
+pub struct Square {
+    sweep: Sweep,
+    length: LengthCounter,
+    pub(super) envelope: Envelope,
+    duty: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    pub enabled: bool,
+}
+
+impl Default for Square {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Square {
+    pub fn new() -> Self {
+        Self {
+            sweep: Sweep::default(),
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            duty: 0,
+            frequency: 0,
+            timer: Self::period(0),
+            position: 0,
+            enabled: false,
+        }
+    }
+
+    /// Cycles per step of the waveform
+    fn period(frequency: u16) -> u32 {
+        (2048 - frequency as u32) * 16
+    }
+
+    pub fn write_sweep(&mut self, value: u8) {
+        self.sweep.write(value);
+    }
+
+    /// SOUND1CNT_H or SOUND2CNT_L: length and duty in the low byte and the
+    /// envelope in the high byte
+    pub fn write_control(&mut self, value: u16, high: bool) {
+        if high {
+            self.envelope.write((value >> 8) as u8);
+            self.enabled &= self.envelope.dac_enabled();
+        } else {
+            self.length.load(value & 0x3F);
+            self.duty = ((value >> 6) & 3) as u8;
+        }
+    }
+
+    /// SOUND1CNT_X or SOUND2CNT_H
+    pub fn write_frequency(&mut self, value: u16, high: bool) {
+        self.frequency = value & 0x7FF;
+        if high {
+            self.length.enabled = value & (1 << 14) != 0;
+            if value & (1 << 15) != 0 {
+                self.trigger();
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.timer = Self::period(self.frequency);
+        if self.sweep.trigger(self.frequency) {
+            self.enabled = false;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let steps = elapse(&mut self.timer, Self::period(self.frequency), cycles);
+        self.position = ((self.position as u32 + steps) % 8) as u8;
+    }
+
+    pub fn tick_length(&mut self) {
+        if self.length.tick() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn tick_sweep(&mut self) {
+        match self.sweep.tick() {
+            Some(frequency) if frequency > MAX_FREQUENCY => self.enabled = false,
+            Some(frequency) => self.frequency = frequency,
+            None => {}
+        }
+    }
+
+    /// The current level, from -15 to 15
+    pub fn output(&self) -> i8 {
+        if !self.enabled {
+            0
+        } else if DUTY_CYCLES[self.duty as usize] & (1 << self.position) != 0 {
+            self.envelope.volume as i8
+        } else {
+            -(self.envelope.volume as i8)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::square::Square;
+
+    #[test]
+    fn test_square() {
+        let mut square = Square::new();
+        // 50% duty at the highest volume with a period of 256 cycles
+        square.write_control(0xF080, false);
+        square.write_control(0xF080, true);
+        square.write_frequency(0x87F0, true);
+        let levels: Vec<i8> = (0..8)
+            .map(|_| {
+                let level = square.output();
+                square.tick(256);
+                level
+            })
+            .collect();
+        assert_eq!(levels, [15, -15, -15, -15, -15, 15, 15, 15]);
+
+        // Each sweep step adds a quarter of the frequency. The channel stops
+        // as soon as the step after the update would overflow
+        square.write_sweep(0x12);
+        square.write_frequency(0x8400, true);
+        square.tick_sweep();
+        assert_eq!(square.frequency, 0x500);
+        square.tick_sweep();
+        assert_eq!(square.frequency, 0x640);
+        assert!(square.enabled);
+        square.tick_sweep();
+        assert!(!square.enabled);
+
+        // The first step is checked when triggering
+        square.write_frequency(0x8700, true);
+        assert!(!square.enabled);
+    }
+}
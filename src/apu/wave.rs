@@ -0,0 +1,192 @@
+use crate::apu::{LengthCounter, WAVE_RAM_SIZE, elapse};
+
+/// Channel 3, which plays 4-bit samples from wave RAM. There are two banks of
+/// 32 samples. The CPU accesses the one not selected for playback
+#[derive(Debug, Copy, Clone)]
+pub struct Wave {
+    ram: [u8; WAVE_RAM_SIZE * 2],
+    /// Play both banks as 64 samples, starting with the selected one
+    two_banks: bool,
+    bank: usize,
+    dac_enabled: bool,
+    length: LengthCounter,
+    /// 0%, 100%, 50% and 25%
+    volume: u8,
+    force_75: bool,
+    frequency: u16,
+    timer: u32,
+    position: usize,
+    pub enabled: bool,
+}
+
+impl Default for Wave {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Self {
+            ram: [0x00; WAVE_RAM_SIZE * 2],
+            two_banks: false,
+            bank: 0,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            volume: 0,
+            force_75: false,
+            frequency: 0,
+            timer: Self::period(0),
+            position: 0,
+            enabled: false,
+        }
+    }
+
+    /// Cycles per sample
+    fn period(frequency: u16) -> u32 {
+        (2048 - frequency as u32) * 8
+    }
+
+    /// Everything but the wave RAM is cleared
+    pub fn power_off(&mut self) {
+        *self = Self {
+            ram: self.ram,
+            ..Self::new()
+        };
+    }
+
+    fn cpu_bank(&self) -> usize {
+        (self.bank ^ 1) * WAVE_RAM_SIZE
+    }
+
+    pub fn write_ram(&mut self, index: usize, value: u8) {
+        self.ram[self.cpu_bank() + index] = value;
+    }
+
+    /// Copy the bank the CPU sees to `ram`, e.g. after the banks were
+    /// swapped
+    pub fn copy_ram(&self, ram: &mut [u8]) {
+        let bank = self.cpu_bank();
+        ram[..WAVE_RAM_SIZE].copy_from_slice(&self.ram[bank..bank + WAVE_RAM_SIZE]);
+    }
+
+    /// SOUND3CNT_L
+    pub fn write_select(&mut self, value: u8) {
+        self.two_banks = value & (1 << 5) != 0;
+        self.bank = ((value >> 6) & 1) as usize;
+        self.dac_enabled = value & (1 << 7) != 0;
+        self.enabled &= self.dac_enabled;
+    }
+
+    /// SOUND3CNT_H: length in the low byte and volume in the high byte
+    pub fn write_control(&mut self, value: u16, high: bool) {
+        if high {
+            self.volume = ((value >> 13) & 3) as u8;
+            self.force_75 = value & (1 << 15) != 0;
+        } else {
+            self.length.load(value & 0xFF);
+        }
+    }
+
+    /// SOUND3CNT_X
+    pub fn write_frequency(&mut self, value: u16, high: bool) {
+        self.frequency = value & 0x7FF;
+        if high {
+            self.length.enabled = value & (1 << 14) != 0;
+            if value & (1 << 15) != 0 {
+                self.trigger();
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = Self::period(self.frequency);
+        self.position = 0;
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let steps = elapse(&mut self.timer, Self::period(self.frequency), cycles);
+        let samples = if self.two_banks { 64 } else { 32 };
+        self.position = (self.position + steps as usize) % samples;
+    }
+
+    pub fn tick_length(&mut self) {
+        if self.length.tick() {
+            self.enabled = false;
+        }
+    }
+
+    /// The current level, from -15 to 15
+    pub fn output(&self) -> i8 {
+        if !self.enabled {
+            return 0;
+        }
+        let index = (self.bank * 32 + self.position) % 64;
+        let byte = self.ram[index / 2];
+        // The high nibble is played first
+        let sample = if index.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0xF
+        };
+        let level = sample as i8 * 2 - 15;
+
+        match (self.force_75, self.volume) {
+            (true, _) => level * 3 / 4,
+            (false, 0) => 0,
+            (false, 1) => level,
+            (false, 2) => level >> 1,
+            (false, _) => level >> 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::wave::Wave;
+
+    #[test]
+    fn test_wave() {
+        let mut wave = Wave::new();
+        let mut ram = [0x00; 16];
+
+        // Bank 0 is written while bank 1 is selected for playback
+        wave.write_select(0xC0);
+        for index in 0..16 {
+            wave.write_ram(index, 0xF0);
+        }
+        wave.write_select(0x80);
+        wave.copy_ram(&mut ram);
+        assert_eq!(ram, [0x00; 16]);
+        for index in 0..16 {
+            wave.write_ram(index, 0x8F);
+        }
+
+        // Full volume with a period of 8 cycles
+        wave.write_control(0x2000, true);
+        wave.write_frequency(0x87FF, true);
+        let levels: Vec<i8> = (0..4)
+            .map(|_| {
+                let level = wave.output();
+                wave.tick(8);
+                level
+            })
+            .collect();
+        assert_eq!(levels, [15, -15, 15, -15]);
+
+        // Both banks are played one after the other
+        wave.write_select(0xA0);
+        wave.tick(8 * 27);
+        assert_eq!(wave.output(), -15);
+        wave.write_control(0xE000, true);
+        assert_eq!(wave.output(), -11);
+        wave.write_control(0x6000, true);
+        assert_eq!(wave.output(), -4);
+
+        wave.write_control(0x2000, true);
+        wave.tick(8);
+        assert_eq!(wave.output(), 1);
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use crate::checksum::crc32;
+
+pub const BIOS_SIZE: usize = 0x4000;
+
+/// Entry of the IRQ handler in the official BIOS. The stub uses the same
+/// address since some games check the return address of their handler
+pub const BIOS_IRQ_HANDLER: usize = 0x128;
+
+/// ARM opcodes placed at the exception vectors of the built-in BIOS
+#[rustfmt::skip]
+const STUB_VECTORS: [u32; 8] = [
+    0xE3A0F408, // 0x00 Reset:          mov pc, #0x08000000
+    0xE1B0F00E, // 0x04 Undefined:      movs pc, lr
+    0xE1B0F00E, // 0x08 SWI:            movs pc, lr
+    0xE25EF004, // 0x0C Prefetch abort: subs pc, lr, #4
+    0xE25EF008, // 0x10 Data abort:     subs pc, lr, #8
+    0xEAFFFFFE, // 0x14 Reserved:       b 0x14
+    0xEA000042, // 0x18 IRQ:            b 0x128
+    0xE25EF004, // 0x1C FIQ:            subs pc, lr, #4
+];
+
+/// The IRQ handler of the official BIOS which calls the user handler stored
+/// at `0x03007FFC`
+#[rustfmt::skip]
+const STUB_IRQ_HANDLER: [u32; 6] = [
+    0xE92D500F, // stmfd sp!, {r0-r3, r12, lr}
+    0xE3A00301, // mov r0, #0x04000000
+    0xE28FE000, // add lr, pc, #0
+    0xE510F004, // ldr pc, [r0, #-4]
+    0xE8BD500F, // ldmfd sp!, {r0-r3, r12, lr}
+    0xE25EF004, // subs pc, lr, #4
+];
+
+/// A minimal replacement BIOS for running without a BIOS dump. It only handles
+/// the exception vectors and IRQ dispatching. SWIs return immediately so BIOS
+/// calls need to be emulated by the CPU
+pub fn builtin_bios() -> Vec<u8> {
+    let mut bios = vec![0x00; BIOS_SIZE];
+
+    let mut place = |address: usize, opcodes: &[u32]| {
+        for (i, opcode) in opcodes.iter().enumerate() {
+            bios[address + i * 4..address + i * 4 + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+    };
+    place(0x00, &STUB_VECTORS);
+    place(BIOS_IRQ_HANDLER, &STUB_IRQ_HANDLER);
+
+    bios
+}
+
+/// CRC-32s of the BIOS images we know about
+const KNOWN_BIOSES: [(u32, BiosKind); 1] = [(0x81977335, BiosKind::Gba)];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BiosKind {
+    /// The BIOS of the GBA (and GBA SP/Micro)
+    Gba,
+    /// The GBA BIOS of the Nintendo DS. Only differs in the boot animation
+    /// and a few bytes of code
+    Nds,
+    /// An open-source BIOS written to replace the official one, by name
+    Replacement(&'static str),
+    /// `builtin_bios`
+    Builtin,
+    /// A bad dump or a BIOS missing from `KNOWN_BIOSES`, with its CRC-32
+    Unknown(u32),
+}
+
+impl BiosKind {
+    pub fn identify(bios: &[u8]) -> Self {
+        if bios == builtin_bios() {
+            return BiosKind::Builtin;
+        }
+
+        let digest = crc32(bios);
+        KNOWN_BIOSES
+            .iter()
+            .find(|(known, _)| *known == digest)
+            .map_or(BiosKind::Unknown(digest), |(_, kind)| *kind)
+    }
+}
+
+/// Read the BIOS dump at `path` or fall back to the built-in BIOS
+pub fn load_bios(path: Option<&Path>) -> anyhow::Result<Vec<u8>, String> {
+    let Some(path) = path else {
+        log::info!("No BIOS provided. Using the built-in BIOS");
+        return Ok(builtin_bios());
+    };
+
+    let mut bios = std::fs::read(path).map_err(|e| e.to_string())?;
+    if bios.len() != BIOS_SIZE {
+        log::warn!(
+            "Expected a {BIOS_SIZE} byte BIOS; got {} bytes from {}",
+            bios.len(),
+            path.display()
+        );
+        bios.resize(BIOS_SIZE, 0x00);
+    }
+    log::info!("Loaded BIOS from {}", path.display());
+
+    Ok(bios)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bios::{BIOS_IRQ_HANDLER, BIOS_SIZE, BiosKind, KNOWN_BIOSES, builtin_bios};
+    use crate::checksum::crc32;
+
+    #[test]
+    fn test_builtin_bios() {
+        let bios = builtin_bios();
+        let word =
+            |address: usize| u32::from_le_bytes(bios[address..address + 4].try_into().unwrap());
+
+        assert_eq!(bios.len(), BIOS_SIZE);
+        // The IRQ vector branches to the handler
+        let offset = (word(0x18) & 0xFFFFFF) as usize * 4 + 8;
+        assert_eq!(0x18 + offset, BIOS_IRQ_HANDLER);
+        assert_eq!(word(BIOS_IRQ_HANDLER), 0xE92D500F);
+    }
+
+    #[test]
+    fn test_identify_bios() {
+        assert_eq!(BiosKind::identify(&builtin_bios()), BiosKind::Builtin);
+
+        for (digest, kind) in KNOWN_BIOSES {
+            let bios = with_crc32(builtin_bios(), digest);
+            assert_eq!(crc32(&bios), digest);
+            assert_eq!(BiosKind::identify(&bios), kind);
+        }
+
+        // Swapping two words of a known BIOS is no longer a match
+        let mut bios = with_crc32(builtin_bios(), KNOWN_BIOSES[0].0);
+        bios.swap(0x00, 0x04);
+        bios.swap(0x01, 0x05);
+        bios.swap(0x02, 0x06);
+        bios.swap(0x03, 0x07);
+        assert!(matches!(BiosKind::identify(&bios), BiosKind::Unknown(_)));
+
+        assert_eq!(
+            BiosKind::identify(&[0x01; BIOS_SIZE]),
+            BiosKind::Unknown(crc32(&[0x01; BIOS_SIZE]))
+        );
+    }
+
+    /// Replace the last word of `bios` so that its CRC-32 is `digest`
+    fn with_crc32(mut bios: Vec<u8>, digest: u32) -> Vec<u8> {
+        let length = bios.len();
+        // Running the CRC backwards from the final state over the last four
+        // bytes gives the state those bytes have to move the CRC from
+        let mut state = !digest;
+        for _ in 0..32 {
+            state = if state & 0x80000000 != 0 {
+                ((state ^ 0xEDB88320) << 1) | 1
+            } else {
+                state << 1
+            };
+        }
+        let before = !crc32(&bios[..length - 4]);
+        bios[length - 4..].copy_from_slice(&(state ^ before).to_le_bytes());
+        bios
+    }
+}
@@ -0,0 +1,42 @@
+/// Lookup table for the reflected CRC-32 (IEEE 802.3) polynomial `0xEDB88320`
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 as used by zip, PNG and the UPS/BPS patch formats
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::crc32;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0x00000000);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414FA339
+        );
+    }
+}
@@ -0,0 +1,785 @@
+//! High-level emulation of the BIOS functions. SWIs are implemented natively
+//! instead of running the BIOS code so programs can run without a BIOS dump
+
+use crate::cpu::Arm7Cpu;
+use crate::cpu::opcodes::execute_swi;
+use crate::cpu::registers::{CpuState, PC_IDX};
+use crate::system_bus::{
+    ACCESS_CODE, ACCESS_NONSEQ, IO_REGISTERS_START, OAM_SIZE, OAM_START, ON_BOARD_WRAM_SIZE,
+    ON_BOARD_WRAM_START, ON_CHIP_WRAM_START, PALETTE_RAM_SIZE, PALETTE_RAM_START, REG_HALTCNT,
+    REG_IME, SystemBus, VRAM_SIZE, VRAM_START,
+};
+
+/// Interrupt flags set by the user IRQ handler for `IntrWait`
+const BIOS_IF: u32 = 0x03007FF8;
+/// Selects the `SoftReset` entry. Non-zero for on-board WRAM
+const SOFT_RESET_FLAG: u32 = 0x03007FFA;
+/// The top of IWRAM used by the BIOS and cleared by `SoftReset`
+const BIOS_RAM_START: u32 = 0x03007E00;
+
+/// The sine table of the BIOS. A full turn in 256 steps as 1.14 fixed point
+/// numbers
+#[rustfmt::skip]
+const SINE_TABLE: [i16; 256] = [
+    0x0000, 0x0192, 0x0323, 0x04B5, 0x0645, 0x07D5, 0x0964, 0x0AF1,
+    0x0C7C, 0x0E05, 0x0F8C, 0x1111, 0x1294, 0x1413, 0x158F, 0x1708,
+    0x187D, 0x19EF, 0x1B5D, 0x1CC6, 0x1E2B, 0x1F8B, 0x20E7, 0x223D,
+    0x238E, 0x24DA, 0x261F, 0x275F, 0x2899, 0x29CD, 0x2AFA, 0x2C21,
+    0x2D41, 0x2E5A, 0x2F6B, 0x3076, 0x3179, 0x3274, 0x3367, 0x3453,
+    0x3536, 0x3612, 0x36E5, 0x37AF, 0x3871, 0x392A, 0x39DA, 0x3A82,
+    0x3B20, 0x3BB6, 0x3C42, 0x3CC5, 0x3D3E, 0x3DAE, 0x3E14, 0x3E71,
+    0x3EC5, 0x3F0E, 0x3F4E, 0x3F84, 0x3FB1, 0x3FD3, 0x3FEC, 0x3FFB,
+    0x4000, 0x3FFB, 0x3FEC, 0x3FD3, 0x3FB1, 0x3F84, 0x3F4E, 0x3F0E,
+    0x3EC5, 0x3E71, 0x3E14, 0x3DAE, 0x3D3E, 0x3CC5, 0x3C42, 0x3BB6,
+    0x3B20, 0x3A82, 0x39DA, 0x392A, 0x3871, 0x37AF, 0x36E5, 0x3612,
+    0x3536, 0x3453, 0x3367, 0x3274, 0x3179, 0x3076, 0x2F6B, 0x2E5A,
+    0x2D41, 0x2C21, 0x2AFA, 0x29CD, 0x2899, 0x275F, 0x261F, 0x24DA,
+    0x238E, 0x223D, 0x20E7, 0x1F8B, 0x1E2B, 0x1CC6, 0x1B5D, 0x19EF,
+    0x187D, 0x1708, 0x158F, 0x1413, 0x1294, 0x1111, 0x0F8C, 0x0E05,
+    0x0C7C, 0x0AF1, 0x0964, 0x07D5, 0x0645, 0x04B5, 0x0323, 0x0192,
+    0x0000, -0x0192, -0x0323, -0x04B5, -0x0645, -0x07D5, -0x0964, -0x0AF1,
+    -0x0C7C, -0x0E05, -0x0F8C, -0x1111, -0x1294, -0x1413, -0x158F, -0x1708,
+    -0x187D, -0x19EF, -0x1B5D, -0x1CC6, -0x1E2B, -0x1F8B, -0x20E7, -0x223D,
+    -0x238E, -0x24DA, -0x261F, -0x275F, -0x2899, -0x29CD, -0x2AFA, -0x2C21,
+    -0x2D41, -0x2E5A, -0x2F6B, -0x3076, -0x3179, -0x3274, -0x3367, -0x3453,
+    -0x3536, -0x3612, -0x36E5, -0x37AF, -0x3871, -0x392A, -0x39DA, -0x3A82,
+    -0x3B20, -0x3BB6, -0x3C42, -0x3CC5, -0x3D3E, -0x3DAE, -0x3E14, -0x3E71,
+    -0x3EC5, -0x3F0E, -0x3F4E, -0x3F84, -0x3FB1, -0x3FD3, -0x3FEC, -0x3FFB,
+    -0x4000, -0x3FFB, -0x3FEC, -0x3FD3, -0x3FB1, -0x3F84, -0x3F4E, -0x3F0E,
+    -0x3EC5, -0x3E71, -0x3E14, -0x3DAE, -0x3D3E, -0x3CC5, -0x3C42, -0x3BB6,
+    -0x3B20, -0x3A82, -0x39DA, -0x392A, -0x3871, -0x37AF, -0x36E5, -0x3612,
+    -0x3536, -0x3453, -0x3367, -0x3274, -0x3179, -0x3076, -0x2F6B, -0x2E5A,
+    -0x2D41, -0x2C21, -0x2AFA, -0x29CD, -0x2899, -0x275F, -0x261F, -0x24DA,
+    -0x238E, -0x223D, -0x20E7, -0x1F8B, -0x1E2B, -0x1CC6, -0x1B5D, -0x19EF,
+    -0x187D, -0x1708, -0x158F, -0x1413, -0x1294, -0x1111, -0x0F8C, -0x0E05,
+    -0x0C7C, -0x0AF1, -0x0964, -0x07D5, -0x0645, -0x04B5, -0x0323, -0x0192,
+];
+
+/// The size of an opcode in the current state, i.e. the step from the SWI to
+/// the opcode it returns to
+fn opcode_size(cpu: &Arm7Cpu) -> u32 {
+    match cpu.registers.state() {
+        CpuState::Arm => 4,
+        CpuState::Thumb => 2,
+    }
+}
+
+/// Run the BIOS function called by the SWI being executed, in either state.
+/// Like the BIOS, the function number is read from the SWI opcode
+pub fn execute_hle_swi<BusType: SystemBus>(cpu: &mut Arm7Cpu, bus: &mut BusType) {
+    let swi_address = cpu.next_address();
+    let function = match cpu.registers.state() {
+        CpuState::Arm => bus.read_byte(swi_address + 2, ACCESS_NONSEQ),
+        CpuState::Thumb => bus.read_byte(swi_address, ACCESS_NONSEQ),
+    };
+    let [r0, r1, r2, r3] = [0, 1, 2, 3].map(|i| cpu.registers[i]);
+
+    match function {
+        0x00 => return soft_reset(cpu, bus),
+        0x01 => register_ram_reset(bus, r0),
+        0x02 => halt(bus),
+        0x04 => return intr_wait(cpu, bus, swi_address, r0 != 0, r1 as u16),
+        0x05 => {
+            // The BIOS loads r0 = r1 = 1 and continues with `IntrWait`
+            cpu.registers[0] = 1;
+            cpu.registers[1] = 0x0001;
+            return intr_wait(cpu, bus, swi_address, true, 0x0001);
+        }
+        0x06 => div(cpu, r0 as i32, r1 as i32),
+        0x07 => div(cpu, r1 as i32, r0 as i32),
+        0x08 => cpu.registers[0] = r0.isqrt(),
+        0x09 => cpu.registers[0] = arctan(r0 as i32) as u32,
+        0x0A => cpu.registers[0] = arctan2(r0 as i32, r1 as i32) as u32,
+        0x0B => cpu_set(bus, r0, r1, r2),
+        0x0C => cpu_fast_set(bus, r0, r1, r2),
+        0x0E => bg_affine_set(bus, r0, r1, r2),
+        0x0F => obj_affine_set(bus, r0, r1, r2, r3),
+        0x11..=0x18 => decompress(bus, function, r0, r1),
+        _ => {
+            log::warn!("BIOS function {function:#04X} is not implemented. Calling the BIOS");
+            return execute_swi(cpu, bus);
+        }
+    }
+
+    cpu.registers.get_and_incr_pc(opcode_size(cpu));
+    cpu.next_access = ACCESS_CODE;
+}
+
+fn soft_reset<BusType: SystemBus>(cpu: &mut Arm7Cpu, bus: &mut BusType) {
+    let entry = if bus.read_byte(SOFT_RESET_FLAG, ACCESS_NONSEQ) != 0 {
+        ON_BOARD_WRAM_START as u32
+    } else {
+        0x08000000
+    };
+    for address in (BIOS_RAM_START..ON_CHIP_WRAM_START as u32 + 0x8000).step_by(4) {
+        bus.write_word(address, 0x00000000, ACCESS_NONSEQ);
+    }
+
+    cpu.registers.user_bank = [0x00; 16];
+    cpu.registers.r14_svc = 0;
+    cpu.registers.spsr_svc = 0;
+    cpu.registers.r14_irq = 0;
+    cpu.registers.spsr_irq = 0;
+    cpu.skip_bios(entry);
+    cpu.reload_pipeline(bus);
+}
+
+fn register_ram_reset<BusType: SystemBus>(bus: &mut BusType, flags: u32) {
+    let mut clear = |start: usize, size: usize| {
+        for address in (start..start + size).step_by(4) {
+            bus.write_word(address as u32, 0x00000000, ACCESS_NONSEQ);
+        }
+    };
+
+    if flags & 0x01 != 0 {
+        clear(ON_BOARD_WRAM_START, ON_BOARD_WRAM_SIZE);
+    }
+    // The top 512 bytes of IWRAM hold the stacks and are left alone
+    if flags & 0x02 != 0 {
+        clear(
+            ON_CHIP_WRAM_START,
+            BIOS_RAM_START as usize - ON_CHIP_WRAM_START,
+        );
+    }
+    if flags & 0x04 != 0 {
+        clear(PALETTE_RAM_START, PALETTE_RAM_SIZE);
+    }
+    if flags & 0x08 != 0 {
+        clear(VRAM_START, VRAM_SIZE);
+    }
+    if flags & 0x10 != 0 {
+        clear(OAM_START, OAM_SIZE);
+    }
+    if flags & 0x20 != 0 {
+        clear(IO_REGISTERS_START + 0x120, 0x40);
+    }
+    if flags & 0x40 != 0 {
+        clear(IO_REGISTERS_START + 0x060, 0x50);
+    }
+    if flags & 0x80 != 0 {
+        clear(IO_REGISTERS_START, 0x60);
+        clear(IO_REGISTERS_START + 0x0B0, 0x70);
+        clear(IO_REGISTERS_START + 0x200, 0x0C);
+    }
+
+    // The display is always left in forced blank
+    bus.write_half_word(IO_REGISTERS_START as u32, 0x0080, ACCESS_NONSEQ);
+}
+
+fn halt<BusType: SystemBus>(bus: &mut BusType) {
+    bus.write_byte(
+        (IO_REGISTERS_START + REG_HALTCNT) as u32,
+        0x00,
+        ACCESS_NONSEQ,
+    );
+}
+
+/// Halt until one of `flags` is set in `BIOS_IF` by the user IRQ handler. The
+/// BIOS does this in a loop around `Halt`. Here the SWI is executed again
+/// after each interrupt until it returns. Only the first call discards flags
+fn intr_wait<BusType: SystemBus>(
+    cpu: &mut Arm7Cpu,
+    bus: &mut BusType,
+    swi_address: u32,
+    discard: bool,
+    flags: u16,
+) {
+    bus.write_half_word((IO_REGISTERS_START + REG_IME) as u32, 0x0001, ACCESS_NONSEQ);
+
+    let discard = discard && cpu.intr_wait != Some(swi_address);
+    let bios_flags = bus.read_half_word(BIOS_IF, ACCESS_NONSEQ);
+    let done = !discard && bios_flags & flags != 0;
+    if discard || done {
+        bus.write_half_word(BIOS_IF, bios_flags & !flags, ACCESS_NONSEQ);
+    }
+
+    if done {
+        cpu.intr_wait = None;
+        cpu.registers.get_and_incr_pc(opcode_size(cpu));
+    } else {
+        cpu.intr_wait = Some(swi_address);
+        halt(bus);
+        cpu.registers[PC_IDX] = swi_address;
+        cpu.reload_pipeline(bus);
+    }
+    cpu.next_access = ACCESS_CODE;
+}
+
+fn div(cpu: &mut Arm7Cpu, numerator: i32, denominator: i32) {
+    if denominator == 0 {
+        // The BIOS never returns
+        log::warn!("Division of {numerator} by zero");
+        cpu.registers[0] = if numerator < 0 { -1i32 as u32 } else { 1 };
+        cpu.registers[1] = numerator as u32;
+        cpu.registers[3] = 1;
+        return;
+    }
+
+    let quotient = numerator.wrapping_div(denominator);
+    cpu.registers[0] = quotient as u32;
+    cpu.registers[1] = numerator.wrapping_rem(denominator) as u32;
+    cpu.registers[3] = quotient.unsigned_abs();
+}
+
+/// The polynomial approximation used by the BIOS. `tan` is a 1.14 fixed point
+/// number and the result is in the range -0x4000..=0x4000 (-pi/2..=pi/2)
+fn arctan(tan: i32) -> i32 {
+    let a = -(tan.wrapping_mul(tan) >> 14);
+    let mut b = (0xA9i32.wrapping_mul(a) >> 14) + 0x390;
+    for constant in [0x91C, 0xFB6, 0x16AA, 0x2081, 0x3651, 0xA2F9] {
+        b = (b.wrapping_mul(a) >> 14) + constant;
+    }
+
+    (tan.wrapping_mul(b) >> 16) as i16 as i32
+}
+
+/// The angle of the vector (`x`, `y`) in the range 0..=0xFFFF (0..2pi)
+fn arctan2(x: i32, y: i32) -> u16 {
+    let angle = match (x, y) {
+        (x, 0) if x >= 0 => 0,
+        (_, 0) => 0x8000,
+        (0, y) if y >= 0 => 0x4000,
+        (0, _) => 0xC000,
+        (x, y) if y >= 0 && x >= 0 && x >= y => arctan((y << 14).wrapping_div(x)),
+        (x, y) if y >= 0 && x < 0 && x.wrapping_neg() >= y => {
+            arctan((y << 14).wrapping_div(x)) + 0x8000
+        }
+        (x, y) if y >= 0 => 0x4000 - arctan((x << 14).wrapping_div(y)),
+        (x, y) if x <= 0 && x.wrapping_neg() > y.wrapping_neg() => {
+            arctan((y << 14).wrapping_div(x)) + 0x8000
+        }
+        (x, y) if x > 0 && x >= y.wrapping_neg() => arctan((y << 14).wrapping_div(x)) + 0x10000,
+        (x, y) => 0xC000 - arctan((x << 14).wrapping_div(y)),
+    };
+
+    angle as u16
+}
+
+fn cpu_set<BusType: SystemBus>(bus: &mut BusType, source: u32, destination: u32, control: u32) {
+    let count = control & 0x1FFFFF;
+    let fill = control & (1 << 24) != 0;
+
+    if control & (1 << 26) != 0 {
+        let (source, destination) = (source & !3, destination & !3);
+        let value = bus.read_word(source, ACCESS_NONSEQ);
+        for i in 0..count {
+            let value = if fill {
+                value
+            } else {
+                bus.read_word(source.wrapping_add(i * 4), ACCESS_NONSEQ)
+            };
+            bus.write_word(destination.wrapping_add(i * 4), value, ACCESS_NONSEQ);
+        }
+    } else {
+        let (source, destination) = (source & !1, destination & !1);
+        let value = bus.read_half_word(source, ACCESS_NONSEQ);
+        for i in 0..count {
+            let value = if fill {
+                value
+            } else {
+                bus.read_half_word(source.wrapping_add(i * 2), ACCESS_NONSEQ)
+            };
+            bus.write_half_word(destination.wrapping_add(i * 2), value, ACCESS_NONSEQ);
+        }
+    }
+}
+
+/// Like `CpuSet` with 32-bit units but the count is rounded up to a multiple
+/// of 8 words
+fn cpu_fast_set<BusType: SystemBus>(
+    bus: &mut BusType,
+    source: u32,
+    destination: u32,
+    control: u32,
+) {
+    let count = ((control & 0x1FFFFF) + 7) & !7;
+    cpu_set(
+        bus,
+        source,
+        destination,
+        (control & (1 << 24)) | (1 << 26) | count,
+    );
+}
+
+/// The sine and cosine of the angle at `address`. Only the upper 8 bits of the
+/// angle are used
+fn read_angle<BusType: SystemBus>(bus: &mut BusType, address: u32) -> (i32, i32) {
+    let angle = (bus.read_half_word(address, ACCESS_NONSEQ) >> 8) as usize;
+    (
+        SINE_TABLE[angle] as i32,
+        SINE_TABLE[(angle + 64) & 0xFF] as i32,
+    )
+}
+
+/// The 8.8 fixed point matrix of the scales at `address` rotated by the
+/// angle after them. Computed with the same truncations as the BIOS
+fn read_matrix<BusType: SystemBus>(bus: &mut BusType, address: u32) -> [i16; 4] {
+    let scale_x = bus.read_half_word(address, ACCESS_NONSEQ) as i16 as i32;
+    let scale_y = bus.read_half_word(address.wrapping_add(2), ACCESS_NONSEQ) as i16 as i32;
+    let (sin, cos) = read_angle(bus, address.wrapping_add(4));
+
+    [
+        (scale_x * cos) >> 14,
+        -((scale_x * sin) >> 14),
+        (scale_y * sin) >> 14,
+        (scale_y * cos) >> 14,
+    ]
+    .map(|value| value as i16)
+}
+
+fn write_matrix<BusType: SystemBus>(
+    bus: &mut BusType,
+    destination: u32,
+    stride: u32,
+    matrix: [i16; 4],
+) {
+    for (i, value) in matrix.into_iter().enumerate() {
+        let address = destination.wrapping_add(i as u32 * stride);
+        bus.write_half_word(address, value as u16, ACCESS_NONSEQ);
+    }
+}
+
+fn bg_affine_set<BusType: SystemBus>(bus: &mut BusType, source: u32, destination: u32, count: u32) {
+    for i in 0..count {
+        let source = source.wrapping_add(i.wrapping_mul(20));
+        let destination = destination.wrapping_add(i.wrapping_mul(16));
+
+        let origin_x = bus.read_word(source, ACCESS_NONSEQ) as i32;
+        let origin_y = bus.read_word(source.wrapping_add(4), ACCESS_NONSEQ) as i32;
+        let display_x = bus.read_half_word(source.wrapping_add(8), ACCESS_NONSEQ) as i16 as i32;
+        let display_y = bus.read_half_word(source.wrapping_add(10), ACCESS_NONSEQ) as i16 as i32;
+        let matrix = read_matrix(bus, source.wrapping_add(12));
+
+        let [pa, pb, pc, pd] = matrix.map(|value| value as i32);
+        let x = origin_x.wrapping_sub(
+            pa.wrapping_mul(display_x)
+                .wrapping_add(pb.wrapping_mul(display_y)),
+        );
+        let y = origin_y.wrapping_sub(
+            pc.wrapping_mul(display_x)
+                .wrapping_add(pd.wrapping_mul(display_y)),
+        );
+
+        write_matrix(bus, destination, 2, matrix);
+        bus.write_word(destination.wrapping_add(8), x as u32, ACCESS_NONSEQ);
+        bus.write_word(destination.wrapping_add(12), y as u32, ACCESS_NONSEQ);
+    }
+}
+
+/// The parameters are written `stride` bytes apart so the result can go
+/// straight into OAM (stride 8)
+fn obj_affine_set<BusType: SystemBus>(
+    bus: &mut BusType,
+    source: u32,
+    destination: u32,
+    count: u32,
+    stride: u32,
+) {
+    for i in 0..count {
+        let source = source.wrapping_add(i.wrapping_mul(8));
+        let destination = destination.wrapping_add(i.wrapping_mul(stride * 4));
+        let matrix = read_matrix(bus, source);
+        write_matrix(bus, destination, stride, matrix);
+    }
+}
+
+/// The `Wram` variants write bytes while the `Vram` ones write 16-bit units
+fn decompress<BusType: SystemBus>(bus: &mut BusType, function: u8, source: u32, destination: u32) {
+    let (data, unit) = match function {
+        0x11 => (lz77(bus, source), 1),
+        0x12 => (lz77(bus, source), 2),
+        0x13 => (huffman(bus, source), 4),
+        0x14 => (run_length(bus, source), 1),
+        0x15 => (run_length(bus, source), 2),
+        0x16 => (diff_8bit(bus, source), 1),
+        0x17 => (diff_8bit(bus, source), 2),
+        _ => (diff_16bit(bus, source), 2),
+    };
+    write_output(bus, destination, &data, unit);
+}
+
+/// The decompressed size from the header of compressed data
+fn decompressed_size<BusType: SystemBus>(bus: &mut BusType, source: u32) -> usize {
+    (bus.read_word(source & !3, ACCESS_NONSEQ) >> 8) as usize
+}
+
+fn write_output<BusType: SystemBus>(bus: &mut BusType, destination: u32, data: &[u8], unit: u32) {
+    for (i, chunk) in data.chunks(unit as usize).enumerate() {
+        let address = destination + i as u32 * unit;
+        let mut bytes = [0x00; 4];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        match unit {
+            1 => bus.write_byte(address, bytes[0], ACCESS_NONSEQ),
+            2 => bus.write_half_word(
+                address,
+                u16::from_le_bytes([bytes[0], bytes[1]]),
+                ACCESS_NONSEQ,
+            ),
+            _ => bus.write_word(address, u32::from_le_bytes(bytes), ACCESS_NONSEQ),
+        }
+    }
+}
+
+fn lz77<BusType: SystemBus>(bus: &mut BusType, source: u32) -> Vec<u8> {
+    let size = decompressed_size(bus, source);
+    let mut data = Vec::with_capacity(size);
+    let mut address = (source & !3) + 4;
+    let mut next_byte = |bus: &mut BusType| {
+        address += 1;
+        bus.read_byte(address - 1, ACCESS_NONSEQ)
+    };
+
+    while data.len() < size {
+        let flags = next_byte(bus);
+        for bit in (0..8).rev() {
+            if data.len() >= size {
+                break;
+            }
+            if flags & (1 << bit) == 0 {
+                data.push(next_byte(bus));
+                continue;
+            }
+
+            let [high, low] = [next_byte(bus), next_byte(bus)];
+            let length = (high >> 4) as usize + 3;
+            let displacement = (((high as usize & 0xF) << 8) | low as usize) + 1;
+            if displacement > data.len() {
+                log::warn!("LZ77 data at {source:#010X} refers to data before the start");
+                return data;
+            }
+            for _ in 0..length {
+                data.push(data[data.len() - displacement]);
+            }
+        }
+    }
+
+    data.truncate(size);
+    data
+}
+
+fn huffman<BusType: SystemBus>(bus: &mut BusType, source: u32) -> Vec<u8> {
+    let source = source & !3;
+    let size = decompressed_size(bus, source);
+    let bits = match bus.read_byte(source, ACCESS_NONSEQ) & 0xF {
+        4 => 4,
+        _ => 8,
+    };
+    let tree_size = (bus.read_byte(source + 4, ACCESS_NONSEQ) as u32 + 1) * 2;
+    let root = source + 5;
+
+    let mut data = Vec::with_capacity(size);
+    let mut stream = source + 4 + tree_size;
+    let mut word = 0u32;
+    let mut filled = 0;
+    let mut node_address = root;
+    let mut node = bus.read_byte(root, ACCESS_NONSEQ);
+
+    while data.len() < size {
+        let bitstream = bus.read_word(stream, ACCESS_NONSEQ);
+        stream += 4;
+
+        for bit in (0..32).rev() {
+            let right = (bitstream >> bit) & 1 == 1;
+            let child = (node_address & !1) + (node as u32 & 0x3F) * 2 + 2 + right as u32;
+            let is_data = node & if right { 0x40 } else { 0x80 } != 0;
+            if !is_data {
+                node_address = child;
+                node = bus.read_byte(child, ACCESS_NONSEQ);
+                continue;
+            }
+
+            word |= (bus.read_byte(child, ACCESS_NONSEQ) as u32 & ((1 << bits) - 1)) << filled;
+            filled += bits;
+            if filled == 32 {
+                data.extend(word.to_le_bytes());
+                word = 0;
+                filled = 0;
+                if data.len() >= size {
+                    break;
+                }
+            }
+            node_address = root;
+            node = bus.read_byte(root, ACCESS_NONSEQ);
+        }
+    }
+
+    data.truncate(size);
+    data
+}
+
+fn run_length<BusType: SystemBus>(bus: &mut BusType, source: u32) -> Vec<u8> {
+    let size = decompressed_size(bus, source);
+    let mut data = Vec::with_capacity(size);
+    let mut address = (source & !3) + 4;
+
+    while data.len() < size {
+        let flag = bus.read_byte(address, ACCESS_NONSEQ);
+        address += 1;
+        if flag & 0x80 != 0 {
+            let value = bus.read_byte(address, ACCESS_NONSEQ);
+            address += 1;
+            data.extend(std::iter::repeat_n(value, (flag & 0x7F) as usize + 3));
+        } else {
+            for _ in 0..(flag & 0x7F) as usize + 1 {
+                data.push(bus.read_byte(address, ACCESS_NONSEQ));
+                address += 1;
+            }
+        }
+    }
+
+    data.truncate(size);
+    data
+}
+
+fn diff_8bit<BusType: SystemBus>(bus: &mut BusType, source: u32) -> Vec<u8> {
+    let size = decompressed_size(bus, source);
+    let address = (source & !3) + 4;
+
+    let mut value = 0u8;
+    (0..size as u32)
+        .map(|i| {
+            value = value.wrapping_add(bus.read_byte(address + i, ACCESS_NONSEQ));
+            value
+        })
+        .collect()
+}
+
+fn diff_16bit<BusType: SystemBus>(bus: &mut BusType, source: u32) -> Vec<u8> {
+    let size = decompressed_size(bus, source);
+    let address = (source & !3) + 4;
+
+    let mut value = 0u16;
+    (0..size as u32 / 2)
+        .flat_map(|i| {
+            value = value.wrapping_add(bus.read_half_word(address + i * 2, ACCESS_NONSEQ));
+            value.to_le_bytes()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bios::builtin_bios;
+    use crate::cpu::Arm7Cpu;
+    use crate::cpu::hle::{BIOS_IF, arctan2, execute_hle_swi};
+    use crate::cpu::registers::{CpuState, PC_IDX};
+    use crate::interrupts::Interrupt;
+    use crate::system_bus::{ACCESS_NONSEQ, Bus, SystemBus};
+
+    fn call(cpu: &mut Arm7Cpu, bus: &mut Bus, function: u8, args: &[u32]) {
+        for (i, arg) in args.iter().enumerate() {
+            cpu.registers[i] = *arg;
+        }
+        // The SWI opcode being executed
+        let address = cpu.next_address();
+        match cpu.registers.state() {
+            CpuState::Arm => {
+                bus.write_word(address, 0xEF000000 | (function as u32) << 16, ACCESS_NONSEQ)
+            }
+            CpuState::Thumb => {
+                bus.write_half_word(address, 0xDF00 | function as u16, ACCESS_NONSEQ)
+            }
+        }
+        execute_hle_swi(cpu, bus);
+    }
+
+    /// The code runs past the test data at the start of on-board WRAM
+    fn setup() -> (Arm7Cpu, Bus) {
+        setup_at(0x02030000)
+    }
+
+    /// Thumb code for an odd `entry`
+    fn setup_at(entry: u32) -> (Arm7Cpu, Bus) {
+        let mut bus = Bus::new(None, builtin_bios());
+        bus.skip_bios();
+        let mut cpu = Arm7Cpu::new();
+        cpu.skip_bios(entry);
+        cpu.start(&mut bus);
+
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_math() {
+        let (mut cpu, mut bus) = setup();
+
+        call(&mut cpu, &mut bus, 0x06, &[-7i32 as u32, 2]);
+        assert_eq!(cpu.registers[0], -3i32 as u32);
+        assert_eq!(cpu.registers[1], -1i32 as u32);
+        assert_eq!(cpu.registers[3], 3);
+
+        call(&mut cpu, &mut bus, 0x07, &[3, 10]);
+        assert_eq!(cpu.registers[0], 3);
+        assert_eq!(cpu.registers[1], 1);
+
+        call(&mut cpu, &mut bus, 0x08, &[0xFFFFFFFF]);
+        assert_eq!(cpu.registers[0], 0xFFFF);
+
+        assert_eq!(arctan2(0x100, 0), 0x0000);
+        assert_eq!(arctan2(0, 0x100), 0x4000);
+        assert_eq!(arctan2(-0x100, 0), 0x8000);
+        assert_eq!(arctan2(0x100, 0x100), 0x2000);
+        assert_eq!(arctan2(0x100, -0x100), 0xE000);
+        // Negating the most negative coordinate does not overflow
+        assert_eq!(arctan2(i32::MIN, 0x100), 0x4000);
+    }
+
+    #[test]
+    fn test_cpu_set() {
+        let (mut cpu, mut bus) = setup();
+
+        bus.write_word(0x02000000, 0x11223344, ACCESS_NONSEQ);
+        bus.write_word(0x02000004, 0x55667788, ACCESS_NONSEQ);
+        call(
+            &mut cpu,
+            &mut bus,
+            0x0B,
+            &[0x02000000, 0x02000100, 2 | (1 << 26)],
+        );
+        assert_eq!(bus.read_word(0x02000104, ACCESS_NONSEQ), 0x55667788);
+
+        // Fill with a half word
+        call(
+            &mut cpu,
+            &mut bus,
+            0x0B,
+            &[0x02000000, 0x02000200, 3 | (1 << 24)],
+        );
+        assert_eq!(bus.read_word(0x02000200, ACCESS_NONSEQ), 0x33443344);
+        assert_eq!(bus.read_word(0x02000204, ACCESS_NONSEQ), 0x00003344);
+
+        // Rounded up to 8 words
+        call(
+            &mut cpu,
+            &mut bus,
+            0x0C,
+            &[0x02000000, 0x02000300, 1 | (1 << 24)],
+        );
+        assert_eq!(bus.read_word(0x0200031C, ACCESS_NONSEQ), 0x11223344);
+        assert_eq!(bus.read_word(0x02000320, ACCESS_NONSEQ), 0x00000000);
+    }
+
+    #[test]
+    fn test_affine_set() {
+        let (mut cpu, mut bus) = setup();
+
+        // Scale 1 and an angle just below a full turn, where the sine is
+        // negative and rounds down like in the BIOS
+        bus.load(0x02000000, &[0x00, 0x01, 0x00, 0x01, 0xFF, 0xFF]);
+        call(&mut cpu, &mut bus, 0x0F, &[0x02000000, 0x02000100, 1, 2]);
+        let matrix: Vec<u16> = (0..4)
+            .map(|i| bus.read_half_word(0x02000100 + i * 2, ACCESS_NONSEQ))
+            .collect();
+        assert_eq!(matrix, [0x00FF, 0x0007, 0xFFF9, 0x00FF]);
+
+        // The same matrix with the origin at (256, 0) shown at (8, 0)
+        bus.load(0x02000200, &0x00010000u32.to_le_bytes());
+        bus.load(0x02000204, &0u32.to_le_bytes());
+        bus.load(0x02000208, &[0x08, 0x00, 0x00, 0x00]);
+        bus.load(0x0200020C, &[0x00, 0x01, 0x00, 0x01, 0xFF, 0xFF]);
+        call(&mut cpu, &mut bus, 0x0E, &[0x02000200, 0x02000300, 1]);
+        assert_eq!(bus.read_half_word(0x02000304, ACCESS_NONSEQ), 0xFFF9);
+        assert_eq!(bus.read_word(0x02000308, ACCESS_NONSEQ), 0x10000 - 0xFF * 8);
+        assert_eq!(bus.read_word(0x0200030C, ACCESS_NONSEQ), 7 * 8);
+    }
+
+    #[test]
+    fn test_decompression() {
+        let (mut cpu, mut bus) = setup();
+
+        // LZ77: "ABC" followed by a 6 byte reference 3 bytes back
+        bus.load(
+            0x02000000,
+            &[0x10, 9, 0, 0, 0x10, b'A', b'B', b'C', 0x30, 0x02],
+        );
+        call(&mut cpu, &mut bus, 0x11, &[0x02000000, 0x02001000]);
+        let mut output = [0x00; 9];
+        for (i, byte) in output.iter_mut().enumerate() {
+            *byte = bus.read_byte(0x02001000 + i as u32, ACCESS_NONSEQ);
+        }
+        assert_eq!(&output, b"ABCABCABC");
+
+        // Huffman: A tree with 'A' and 'B' as the only leaves
+        bus.load(0x02000100, &[0x28, 4, 0, 0, 0x01, 0xC0, b'A', b'B']);
+        bus.load(0x02000108, &0x50000000u32.to_le_bytes());
+        call(&mut cpu, &mut bus, 0x13, &[0x02000100, 0x02002000]);
+        assert_eq!(bus.read_word(0x02002000, ACCESS_NONSEQ), 0x42414241);
+
+        // Run length: a run of 3 'x' followed by 2 literals
+        bus.load(0x02000200, &[0x30, 5, 0, 0, 0x80, b'x', 0x01, b'y', b'z']);
+        call(&mut cpu, &mut bus, 0x14, &[0x02000200, 0x02003000]);
+        assert_eq!(bus.read_word(0x02003000, ACCESS_NONSEQ), 0x79787878);
+        assert_eq!(bus.read_byte(0x02003004, ACCESS_NONSEQ), b'z');
+
+        // Differences
+        bus.load(0x02000300, &[0x81, 4, 0, 0, 1, 1, 2, 0xFF]);
+        call(&mut cpu, &mut bus, 0x16, &[0x02000300, 0x02004000]);
+        assert_eq!(bus.read_word(0x02004000, ACCESS_NONSEQ), 0x03040201);
+    }
+
+    #[test]
+    fn test_intr_wait() {
+        let (mut cpu, mut bus) = setup();
+        let swi_address = cpu.registers[PC_IDX] - 8;
+
+        // Waits until the IRQ handler sets the flag
+        call(&mut cpu, &mut bus, 0x05, &[]);
+        assert!(bus.halted());
+        assert_eq!(cpu.registers[PC_IDX] - 8, swi_address);
+
+        bus.wake();
+        bus.write_half_word(BIOS_IF, 0x0001, ACCESS_NONSEQ);
+        call(&mut cpu, &mut bus, 0x04, &[0, 1]);
+        assert!(!bus.halted());
+        assert_eq!(cpu.registers[PC_IDX] - 8, swi_address + 4);
+        assert_eq!(bus.read_half_word(BIOS_IF, ACCESS_NONSEQ), 0x0000);
+    }
+
+    #[test]
+    fn test_vblank_intr_wait() {
+        let (mut cpu, mut bus) = setup();
+        let swi_address = cpu.registers[PC_IDX] - 8;
+
+        // A VBlank from before the call is discarded
+        bus.write_half_word(BIOS_IF, 0x0001, ACCESS_NONSEQ);
+        call(&mut cpu, &mut bus, 0x05, &[]);
+        assert!(bus.halted());
+        assert_eq!(bus.read_half_word(BIOS_IF, ACCESS_NONSEQ), 0x0000);
+
+        // Woken by another interrupt, so the SWI halts again
+        bus.wake();
+        call(&mut cpu, &mut bus, 0x05, &[]);
+        assert!(bus.halted());
+        assert_eq!(cpu.registers[PC_IDX] - 8, swi_address);
+
+        // The VBlank IRQ handler sets the flag and the retry returns
+        bus.request_interrupt(Interrupt::VBlank);
+        bus.wake();
+        bus.write_half_word(BIOS_IF, 0x0001, ACCESS_NONSEQ);
+        call(&mut cpu, &mut bus, 0x05, &[]);
+        assert!(!bus.halted());
+        assert_eq!(cpu.registers[PC_IDX] - 8, swi_address + 4);
+        assert_eq!(bus.read_half_word(BIOS_IF, ACCESS_NONSEQ), 0x0000);
+    }
+
+    #[test]
+    fn test_thumb_swi() {
+        let (mut cpu, mut bus) = setup_at(0x02030001);
+        let swi_address = cpu.next_address();
+
+        call(&mut cpu, &mut bus, 0x06, &[7, 2]);
+        assert_eq!(cpu.registers[0], 3);
+        assert_eq!(cpu.next_address(), swi_address + 2);
+
+        // IntrWait runs the Thumb SWI again until the flag is set
+        call(&mut cpu, &mut bus, 0x04, &[0, 1]);
+        assert!(bus.halted());
+        assert_eq!(cpu.next_address(), swi_address + 2);
+
+        bus.wake();
+        bus.write_half_word(BIOS_IF, 0x0001, ACCESS_NONSEQ);
+        call(&mut cpu, &mut bus, 0x04, &[0, 1]);
+        assert!(!bus.halted());
+        assert_eq!(cpu.next_address(), swi_address + 4);
+    }
+}
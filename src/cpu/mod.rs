@@ -1,3 +1,4 @@
+use crate::cpu::hle::execute_hle_swi;
 use crate::cpu::opcodes::{
     Condition, DecodedArmOpcode, DecodedThumbOpcode, Opcode, check_condition,
     condition_from_arm_opcode, decode_arm_opcode, decode_thumb_opcode, execute_arm_to_thumb_bx,
@@ -12,6 +13,7 @@ use circular_buffer::CircularBuffer;
 use registers::RegisterFile;
 use std::ops::BitAnd;
 
+pub mod hle;
 pub mod opcodes;
 pub mod registers;
 
@@ -26,6 +28,11 @@ pub struct Arm7Cpu {
     /// - Execute - 0 pre-fetch
     pipeline: [u32; 2],
     next_access: u8,
+    /// Implement SWIs natively instead of jumping to the BIOS
+    hle_bios: bool,
+    /// The address of the emulated `IntrWait` SWI that halted and is executed
+    /// again after each interrupt
+    intr_wait: Option<u32>,
 
     // TODO: Move this to the UI and give it a higher limit
     pub opcode_traces: CircularBuffer<25, OpcodeTraceLog>,
@@ -37,15 +44,68 @@ impl Arm7Cpu {
             registers: RegisterFile::default(),
             pipeline: [0; 2],
             next_access: ACCESS_CODE,
+            hle_bios: false,
+            intr_wait: None,
 
             opcode_traces: CircularBuffer::new(),
         }
     }
 
+    pub fn set_hle_bios(&mut self, enabled: bool) {
+        self.hle_bios = enabled;
+    }
+
+    /// The address of the opcode the next `step` executes
+    pub fn next_address(&self) -> u32 {
+        match self.registers.state() {
+            CpuState::Arm => self.registers[PC_IDX].wrapping_sub(8),
+            CpuState::Thumb => self.registers[PC_IDX].wrapping_sub(4),
+        }
+    }
+
     pub fn start<BusType: SystemBus>(&mut self, bus: &mut BusType) {
         self.reload_pipeline(bus);
     }
 
+    /// Set up the stack pointers and CPSR the way the BIOS leaves them before
+    /// jumping to `entry`. Must be called before `start`
+    pub fn skip_bios(&mut self, entry: u32) {
+        self.registers.r13_svc = 0x03007FE0;
+        self.registers.r13_irq = 0x03007FA0;
+        self.registers.user_bank[13] = 0x03007F00;
+        // System mode, ARM state and interrupts enabled
+        self.registers.cpsr = CpuMode::System as u32;
+        // ELF entry points have bit 0 set for Thumb code
+        if entry & 1 == 1 {
+            self.toggle_cpu_state();
+        }
+        self.registers.user_bank[PC_IDX] = entry & !1;
+        self.next_access = ACCESS_CODE;
+    }
+
+    /// Take the IRQ exception unless IRQs are disabled in the CPSR. Must be
+    /// called between instructions
+    pub fn handle_irq<BusType: SystemBus>(&mut self, bus: &mut BusType) {
+        if self.registers.cpsr & CondFlag::IrqDisable as u32 != 0 {
+            return;
+        }
+
+        // The handler returns with `subs pc, lr, #4` to the instruction which
+        // was about to be executed
+        let return_address = match self.registers.state() {
+            CpuState::Arm => self.registers[PC_IDX] - 4,
+            CpuState::Thumb => self.registers[PC_IDX],
+        };
+        self.registers.spsr_irq = self.registers.cpsr;
+        self.switch_cpu_mode(CpuMode::Irq);
+        self.registers.cpsr |= CondFlag::IrqDisable as u32;
+        self.registers.cpsr &= !(CondFlag::State as u32);
+        self.registers.r14_irq = return_address;
+        self.registers.user_bank[PC_IDX] = 0x00000018;
+        self.next_access = ACCESS_CODE;
+        self.reload_pipeline(bus);
+    }
+
     fn toggle_cpu_state(&mut self) {
         self.registers.cpsr ^= CondFlag::State as u32;
     }
@@ -118,7 +178,8 @@ impl Arm7Cpu {
                 self.execute_arm_opcode(opcode, bus);
                 execution_log.did_execute = true;
             } else {
-                bus.read_word(self.registers.get_and_incr_pc(4), ACCESS_CODE);
+                // A skipped opcode takes a single cycle, the fetch above
+                self.registers.get_and_incr_pc(4);
                 self.next_access = ACCESS_CODE | ACCESS_SEQ;
                 execution_log.did_execute = false;
             }
@@ -248,6 +309,7 @@ impl Arm7Cpu {
                 dest_register,
                 word,
             } => execute_swp(self, bus, base_register, src_register, dest_register, word),
+            DecodedArmOpcode::Swi { .. } if self.hle_bios => execute_hle_swi(self, bus),
             DecodedArmOpcode::Swi { .. } => execute_swi(self, bus),
             DecodedArmOpcode::PsrTransfer {
                 transfer_spsr,
@@ -339,6 +401,18 @@ mod tests {
         assert_eq!(cpu.registers.cpsr, 0x000000DF); // IRQ and FIQ disabled
     }
 
+    #[test]
+    fn test_cpu_skip_bios() {
+        let mut cpu = Arm7Cpu::new();
+        cpu.skip_bios(0x08000000);
+
+        assert_eq!(cpu.registers[PC_IDX], 0x08000000);
+        assert_eq!(cpu.registers[13], 0x03007F00);
+        assert_eq!(cpu.registers.r13_irq, 0x03007FA0);
+        assert_eq!(cpu.registers.r13_svc, 0x03007FE0);
+        assert_eq!(cpu.registers.cpsr, 0x0000001F);
+    }
+
     #[test]
     fn test_cpu_mode_change() {
         let mut cpu = Arm7Cpu::new();
@@ -522,6 +596,8 @@ mod tests {
             // The actual addresses do not matter for tests
             pipeline: [state.pipeline[0], state.pipeline[1]],
             next_access: state.access,
+            hle_bios: false,
+            intr_wait: None,
 
             opcode_traces: CircularBuffer::new(),
         }
@@ -814,10 +890,33 @@ mod tests {
     #[test_case("arm_mull_mlal")]
     fn test_opcode(name: &'static str) {
         let test_state = read_test_data(name);
+        run_test_cases(name, test_state.iter());
+    }
+
+    // The cases with a misaligned data access. Run separately to track the
+    // load rotation quirks
+    #[test_case("arm_swp")]
+    #[test_case("arm_ldrh_strh")]
+    #[test_case("arm_ldrsb_ldrsh")]
+    #[test_case("arm_ldr_str_immediate_offset")]
+    #[test_case("arm_ldr_str_register_offset")]
+    fn test_misaligned_load(name: &'static str) {
+        let test_state = read_test_data(name);
+        run_test_cases(
+            name,
+            test_state.iter().filter(|test_case| {
+                test_case.transactions.iter().any(|transaction| {
+                    transaction.kind == 1
+                        && !(transaction.addr as usize).is_multiple_of(transaction.size)
+                })
+            }),
+        );
+    }
 
+    fn run_test_cases<'a>(name: &str, test_cases: impl Iterator<Item = &'a TestState>) {
         let mut opcode_failures: Vec<(u32, OpcodeExecFailure)> = vec![];
 
-        for test_case in test_state.iter() {
+        for test_case in test_cases {
             let mut bus = TransactionSystemBus {
                 test_state: test_case,
                 opcode: test_case.opcode,
@@ -1069,39 +1069,14 @@ pub fn execute_half_word_signed_transfer<BusType: SystemBus>(
         },
         RegisterTransferType::Load => match transfer_size {
             DataTransferSize::Byte => {
-                let mut value = bus.read_byte(address, ACCESS_NONSEQ) as u32;
-                if test_bit!(value, 7) {
-                    value |= 0xFFFFFF00; // Sign extend
-                }
-                cpu.registers[target_register] = value;
-            }
-            DataTransferSize::HalfWord(false) if address & 1 == 1 => {
-                cpu.registers[target_register] = ror(
-                    bus.read_half_word(address, ACCESS_NONSEQ) as u32,
-                    (address & 1) * 8,
-                );
-            }
-            DataTransferSize::HalfWord(false) if address & 1 == 0 => {
-                cpu.registers[target_register] = bus.read_half_word(address, ACCESS_NONSEQ) as u32;
+                cpu.registers[target_register] = bus.read_byte_signed(address, ACCESS_NONSEQ);
             }
-            DataTransferSize::HalfWord(true) if address & 1 == 1 => {
-                // A half word LDRSH does a rotated by 8 of the read value but only
-                // results in the lower 8 bits (like a LDRB). It then sign-extends based on the LDRB
-                // result
-                // https://rust-console.github.io/gbatek-gbaonly/#mis-aligned-ldrhldrsh-does-or-does-not-do-strange-things
-                let mut value =
-                    bus.read_half_word(address, ACCESS_NONSEQ).rotate_right(8) as u8 as u32;
-                if test_bit!(value, 7) {
-                    value |= 0xFFFFFF00;
-                }
-                cpu.registers[target_register] = value;
+            DataTransferSize::HalfWord(false) => {
+                cpu.registers[target_register] = bus.read_half_word_rotated(address, ACCESS_NONSEQ);
             }
-            DataTransferSize::HalfWord(true) if address & 1 == 0 => {
-                let mut value = bus.read_half_word(address, ACCESS_NONSEQ) as u32;
-                if test_bit!(value, 15) {
-                    value |= 0xFFFF0000;
-                }
-                cpu.registers[target_register] = value;
+            // https://rust-console.github.io/gbatek-gbaonly/#mis-aligned-ldrhldrsh-does-or-does-not-do-strange-things
+            DataTransferSize::HalfWord(true) => {
+                cpu.registers[target_register] = bus.read_half_word_signed(address, ACCESS_NONSEQ);
             }
             DataTransferSize::DoubleWord => todo!("LDRD not supported on ARMv4"),
             _ => panic!("Word size transfer not supported in this opcode"),
@@ -1236,11 +1211,7 @@ pub fn execute_single_data_transfer<BusType: SystemBus>(
                 cpu.registers[target_register] = bus.read_byte(address, ACCESS_NONSEQ) as u32;
             }
             DataTransferSize::Word => {
-                let mut value = bus.read_word(address, ACCESS_NONSEQ);
-                if address != (address & !3) {
-                    value = ror(value, (address & 0b11) * 8);
-                }
-                cpu.registers[target_register] = value;
+                cpu.registers[target_register] = bus.read_word_rotated(address, ACCESS_NONSEQ);
             }
             _ => panic!("Impossible word size for LDR"),
         },
@@ -1313,13 +1284,7 @@ pub fn execute_swp<BusType: SystemBus>(
     let src_value = cpu.registers[src_register as usize];
 
     if word {
-        cpu.registers[dest_register as usize] = bus.read_word(base_address, ACCESS_NONSEQ);
-        if base_address != (base_address & !3) {
-            cpu.registers[dest_register as usize] = ror(
-                cpu.registers[dest_register as usize],
-                (base_address & 3) * 8,
-            );
-        }
+        cpu.registers[dest_register as usize] = bus.read_word_rotated(base_address, ACCESS_NONSEQ);
         bus.write_word(base_address, src_value, ACCESS_NONSEQ | ACCESS_LOCK);
     } else {
         cpu.registers[dest_register as usize] = bus.read_byte(base_address, ACCESS_NONSEQ) as u32;
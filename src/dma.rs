@@ -0,0 +1,195 @@
+use crate::interrupts::Interrupt;
+
+/// Offset of the first DMA channel's registers from `IO_REGISTERS_START`.
+/// Each channel has SAD, DAD, CNT_L and CNT_H in 12 bytes
+pub const REG_DMA0SAD: usize = 0x0B0;
+pub const DMA_CHANNEL_SIZE: usize = 12;
+
+const CONTROL_REPEAT: u16 = 1 << 9;
+const CONTROL_WORD: u16 = 1 << 10;
+const CONTROL_IRQ: u16 = 1 << 14;
+pub const CONTROL_ENABLE: u16 = 1 << 15;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DmaTiming {
+    Immediate,
+    VBlank,
+    HBlank,
+    /// Sound FIFO refills for DMA1/2 and video capture for DMA3
+    Special,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum AddressControl {
+    Increment,
+    Decrement,
+    Fixed,
+    /// Increment and reload the destination when the DMA repeats
+    IncrementReload,
+}
+
+impl AddressControl {
+    fn step(self, address: u32, unit: u32) -> u32 {
+        match self {
+            AddressControl::Increment | AddressControl::IncrementReload => {
+                address.wrapping_add(unit)
+            }
+            AddressControl::Decrement => address.wrapping_sub(unit),
+            AddressControl::Fixed => address,
+        }
+    }
+}
+
+/// The internal registers of a channel. They are loaded from the IO registers
+/// when the channel is enabled
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DmaChannel {
+    pub source: u32,
+    pub destination: u32,
+    pub count: u32,
+}
+
+/// A transfer to be carried out by the bus
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DmaTransfer {
+    pub channel: usize,
+    pub source: u32,
+    pub destination: u32,
+    pub count: u32,
+    pub word: bool,
+    source_control: AddressControl,
+    destination_control: AddressControl,
+}
+
+impl DmaTransfer {
+    pub fn unit(&self) -> u32 {
+        if self.word { 4 } else { 2 }
+    }
+
+    /// The source and destination address of the unit after the one at
+    /// `source` and `destination`
+    pub fn step(&self, source: u32, destination: u32) -> (u32, u32) {
+        (
+            self.source_control.step(source, self.unit()),
+            self.destination_control.step(destination, self.unit()),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Dma {
+    pub channels: [DmaChannel; 4],
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn timing(control: u16) -> DmaTiming {
+        match (control >> 12) & 3 {
+            0 => DmaTiming::Immediate,
+            1 => DmaTiming::VBlank,
+            2 => DmaTiming::HBlank,
+            _ => DmaTiming::Special,
+        }
+    }
+
+    /// Latch the channel registers after the enable bit was set
+    pub fn enable(&mut self, channel: usize, source: u32, destination: u32, count: u16) {
+        let source_mask = if channel == 0 { 0x07FFFFFF } else { 0x0FFFFFFF };
+
+        self.channels[channel] = DmaChannel {
+            source: source & source_mask,
+            destination: Self::destination(channel, destination),
+            count: Self::count(channel, count),
+        };
+    }
+
+    /// Only DMA3 can write to the GamePak
+    fn destination(channel: usize, destination: u32) -> u32 {
+        let mask = if channel == 3 { 0x0FFFFFFF } else { 0x07FFFFFF };
+        destination & mask
+    }
+
+    /// A count of 0 is the maximum. DMA3 has a 16-bit count and the others 14
+    fn count(channel: usize, count: u16) -> u32 {
+        let max = if channel == 3 { 0x10000 } else { 0x4000 };
+        match count as u32 & (max - 1) {
+            0 => max,
+            count => count,
+        }
+    }
+
+    /// The transfer of `channel` as configured by `control`
+    pub fn transfer(&self, channel: usize, control: u16) -> DmaTransfer {
+        let registers = self.channels[channel];
+        let word = control & CONTROL_WORD != 0;
+        let align = if word { !3 } else { !1 };
+
+        DmaTransfer {
+            channel,
+            source: registers.source & align,
+            destination: registers.destination & align,
+            count: registers.count,
+            word,
+            source_control: match (control >> 7) & 3 {
+                0 | 3 => AddressControl::Increment,
+                1 => AddressControl::Decrement,
+                _ => AddressControl::Fixed,
+            },
+            destination_control: match (control >> 5) & 3 {
+                0 => AddressControl::Increment,
+                1 => AddressControl::Decrement,
+                2 => AddressControl::Fixed,
+                _ => AddressControl::IncrementReload,
+            },
+        }
+    }
+
+    /// Sound FIFO refills of DMA1 and DMA2 always copy 4 words to the FIFO,
+    /// whatever the count, width and destination control are
+    pub fn sound_transfer(&self, channel: usize, control: u16) -> DmaTransfer {
+        DmaTransfer {
+            count: 4,
+            word: true,
+            destination_control: AddressControl::Fixed,
+            ..self.transfer(channel, control)
+        }
+    }
+
+    /// Update the internal registers after `transfer` finished at `source`
+    /// and `destination`. The `reload_` values are the DAD and CNT_L
+    /// registers. Returns the new control value and the interrupt to request
+    pub fn finish(
+        &mut self,
+        transfer: &DmaTransfer,
+        (source, destination): (u32, u32),
+        control: u16,
+        (reload_destination, reload_count): (u32, u16),
+    ) -> (u16, Option<Interrupt>) {
+        let channel = &mut self.channels[transfer.channel];
+        channel.source = source;
+        channel.destination = destination;
+
+        let control =
+            if control & CONTROL_REPEAT != 0 && Self::timing(control) != DmaTiming::Immediate {
+                channel.count = Self::count(transfer.channel, reload_count);
+                if transfer.destination_control == AddressControl::IncrementReload {
+                    channel.destination = Self::destination(transfer.channel, reload_destination);
+                }
+                control
+            } else {
+                control & !CONTROL_ENABLE
+            };
+
+        let interrupt = [
+            Interrupt::Dma0,
+            Interrupt::Dma1,
+            Interrupt::Dma2,
+            Interrupt::Dma3,
+        ][transfer.channel];
+
+        (control, (control & CONTROL_IRQ != 0).then_some(interrupt))
+    }
+}
@@ -0,0 +1,354 @@
+use std::path::Path;
+
+use thiserror::Error;
+
+const ELF_MAGIC: &[u8] = b"\x7FELF";
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_MACHINE_ARM: u16 = 40;
+
+const PROGRAM_HEADER_LOAD: u32 = 1;
+const SECTION_HEADER_SYMTAB: u32 = 2;
+
+const SYMBOL_TYPE_OBJECT: u8 = 1;
+const SYMBOL_TYPE_FUNC: u8 = 2;
+
+/// Bytes of a `PT_LOAD` segment and the address they are placed at
+#[derive(Debug, Clone)]
+pub struct ElfSegment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// Address of the symbol with the Thumb bit cleared
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Function and object symbols sorted by address
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|symbol| symbol.address);
+        Self { symbols }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// The symbol containing `address`, if any
+    pub fn symbol_at(&self, address: u32) -> Option<&Symbol> {
+        let idx = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols[..idx].last()?;
+        (address < symbol.address + symbol.size.max(1)).then_some(symbol)
+    }
+}
+
+/// An ARM ELF executable as produced by devkitARM or the Rust GBA crates
+#[derive(Debug, Clone)]
+pub struct ElfImage {
+    pub entry: u32,
+    pub segments: Vec<ElfSegment>,
+    pub symbols: SymbolTable,
+}
+
+impl ElfImage {
+    pub fn new(path: &Path) -> anyhow::Result<ElfImage, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        ElfImage::parse(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<ElfImage, ElfError> {
+        if !data.starts_with(ELF_MAGIC) {
+            return Err(ElfError::Header("ELF magic".to_string()));
+        }
+        if data.get(4) != Some(&ELF_CLASS_32) || data.get(5) != Some(&ELF_DATA_LITTLE_ENDIAN) {
+            return Err(ElfError::Header("32-bit little endian ELF".to_string()));
+        }
+        if read_u16(data, 0x12)? != ELF_MACHINE_ARM {
+            return Err(ElfError::Header("ARM machine type".to_string()));
+        }
+
+        let entry = read_u32(data, 0x18)?;
+        let program_header_offset = read_u32(data, 0x1C)? as usize;
+        let section_header_offset = read_u32(data, 0x20)? as usize;
+        let program_header_size = read_u16(data, 0x2A)? as usize;
+        let program_header_count = read_u16(data, 0x2C)? as usize;
+        let section_header_size = read_u16(data, 0x2E)? as usize;
+        let section_header_count = read_u16(data, 0x30)? as usize;
+
+        let mut segments = vec![];
+        for i in 0..program_header_count {
+            let header = program_header_offset + i * program_header_size;
+            if read_u32(data, header)? != PROGRAM_HEADER_LOAD {
+                continue;
+            }
+
+            let offset = read_u32(data, header + 0x04)? as usize;
+            // Place segments at their load (physical) address like objcopy does.
+            // Initialised data is linked for RAM but stored in ROM for the
+            // start-up code to copy
+            let address = read_u32(data, header + 0x0C)?;
+            let file_size = read_u32(data, header + 0x10)? as usize;
+            if file_size == 0 {
+                continue;
+            }
+
+            segments.push(ElfSegment {
+                address,
+                data: read_bytes(data, offset, file_size)?.to_vec(),
+            });
+        }
+
+        let symbols = if section_header_offset != 0 {
+            parse_symbols(
+                data,
+                section_header_offset,
+                section_header_size,
+                section_header_count,
+            )?
+        } else {
+            SymbolTable::default()
+        };
+
+        Ok(ElfImage {
+            entry,
+            segments,
+            symbols,
+        })
+    }
+}
+
+fn parse_symbols(
+    data: &[u8],
+    section_header_offset: usize,
+    section_header_size: usize,
+    section_header_count: usize,
+) -> anyhow::Result<SymbolTable, ElfError> {
+    let section = |idx: usize| section_header_offset + idx * section_header_size;
+
+    let mut symbols = vec![];
+    for i in 0..section_header_count {
+        if read_u32(data, section(i) + 0x04)? != SECTION_HEADER_SYMTAB {
+            continue;
+        }
+
+        let offset = read_u32(data, section(i) + 0x10)? as usize;
+        let size = read_u32(data, section(i) + 0x14)? as usize;
+        let string_table = section(read_u32(data, section(i) + 0x18)? as usize);
+        let string_table_offset = read_u32(data, string_table + 0x10)? as usize;
+        let entry_size = (read_u32(data, section(i) + 0x24)? as usize).max(16);
+
+        for entry in (offset..offset + size).step_by(entry_size) {
+            let info = read_bytes(data, entry + 0x0C, 1)?[0];
+            if !matches!(info & 0xF, SYMBOL_TYPE_OBJECT | SYMBOL_TYPE_FUNC) {
+                continue;
+            }
+
+            let name = read_string(data, string_table_offset + read_u32(data, entry)? as usize)?;
+            // Skip the `$a`, `$t` and `$d` mapping symbols
+            if name.is_empty() || name.starts_with('$') {
+                continue;
+            }
+
+            symbols.push(Symbol {
+                name,
+                address: read_u32(data, entry + 0x04)? & !1,
+                size: read_u32(data, entry + 0x08)?,
+            });
+        }
+    }
+
+    Ok(SymbolTable::new(symbols))
+}
+
+fn read_bytes(data: &[u8], offset: usize, size: usize) -> anyhow::Result<&[u8], ElfError> {
+    data.get(offset..offset + size)
+        .ok_or(ElfError::Truncated(offset))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> anyhow::Result<u16, ElfError> {
+    Ok(u16::from_le_bytes(
+        read_bytes(data, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> anyhow::Result<u32, ElfError> {
+    Ok(u32::from_le_bytes(
+        read_bytes(data, offset, 4)?.try_into().unwrap(),
+    ))
+}
+
+fn read_string(data: &[u8], offset: usize) -> anyhow::Result<String, ElfError> {
+    let bytes = data.get(offset..).ok_or(ElfError::Truncated(offset))?;
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == 0x00)
+        .ok_or(ElfError::Truncated(offset))?;
+    Ok(String::from_utf8_lossy(&bytes[..end]).to_string())
+}
+
+#[derive(Error, Debug)]
+pub enum ElfError {
+    #[error("Invalid ELF header (expected {0})")]
+    Header(String),
+    #[error("ELF file ends unexpectedly (reading offset {0:#X})")]
+    Truncated(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::{ElfError, ElfImage};
+
+    fn push_u16(data: &mut Vec<u8>, value: u16) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_u32(data: &mut Vec<u8>, value: u32) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_symbol(data: &mut Vec<u8>, name: u32, value: u32, size: u32, info: u8) {
+        push_u32(data, name);
+        push_u32(data, value);
+        push_u32(data, size);
+        data.extend_from_slice(&[info, 0x00, 0x01, 0x00]);
+    }
+
+    /// ELF with one ROM segment, one IWRAM data segment and a symbol table
+    fn gen_elf() -> Vec<u8> {
+        const PROGRAM_HEADERS: u32 = 0x34;
+        const SEGMENT_DATA: u32 = 0x74;
+        const STRING_TABLE: u32 = 0x80;
+        const SYMBOL_TABLE: u32 = 0x94;
+        const SECTION_HEADERS: u32 = SYMBOL_TABLE + 4 * 0x10;
+
+        let mut data = vec![];
+        data.extend_from_slice(b"\x7FELF\x01\x01\x01");
+        data.resize(0x10, 0x00);
+        push_u16(&mut data, 2); // Executable
+        push_u16(&mut data, 40); // ARM
+        push_u32(&mut data, 1);
+        push_u32(&mut data, 0x08000000); // Entry
+        push_u32(&mut data, PROGRAM_HEADERS);
+        push_u32(&mut data, SECTION_HEADERS);
+        push_u32(&mut data, 0x05000000);
+        push_u16(&mut data, 0x34);
+        push_u16(&mut data, 0x20);
+        push_u16(&mut data, 2);
+        push_u16(&mut data, 0x28);
+        push_u16(&mut data, 3);
+        push_u16(&mut data, 0);
+
+        for (offset, vaddr, paddr, size) in [
+            (SEGMENT_DATA, 0x08000000, 0x08000000, 8),
+            (SEGMENT_DATA + 8, 0x03000000, 0x08000008, 4),
+        ] {
+            push_u32(&mut data, 1);
+            push_u32(&mut data, offset);
+            push_u32(&mut data, vaddr);
+            push_u32(&mut data, paddr);
+            push_u32(&mut data, size);
+            push_u32(&mut data, size);
+            push_u32(&mut data, 7);
+            push_u32(&mut data, 4);
+        }
+
+        data.extend_from_slice(&[0x2E, 0x00, 0x00, 0xEA, 0x11, 0x22, 0x33, 0x44]);
+        data.extend_from_slice(&[0xAA, 0xBB, 0xCC, 0xDD]);
+
+        data.extend_from_slice(b"\0main\0$a\0counter\0");
+        data.resize(SYMBOL_TABLE as usize, 0x00);
+
+        push_symbol(&mut data, 0, 0, 0, 0);
+        push_symbol(&mut data, 1, 0x08000001, 8, 0x12); // Global Thumb function
+        push_symbol(&mut data, 6, 0x08000000, 0, 0x00); // Mapping symbol
+        push_symbol(&mut data, 9, 0x03000000, 4, 0x11); // Global object
+
+        data.resize(SECTION_HEADERS as usize + 0x28, 0x00);
+        for (kind, offset, size, link, entry_size) in [
+            (2, SYMBOL_TABLE, 4 * 0x10, 2, 0x10),
+            (3, STRING_TABLE, 0x11, 0, 0),
+        ] {
+            push_u32(&mut data, 0);
+            push_u32(&mut data, kind);
+            push_u32(&mut data, 0);
+            push_u32(&mut data, 0);
+            push_u32(&mut data, offset);
+            push_u32(&mut data, size);
+            push_u32(&mut data, link);
+            push_u32(&mut data, 0);
+            push_u32(&mut data, 4);
+            push_u32(&mut data, entry_size);
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_parse_segments() -> Result<(), ElfError> {
+        let elf = ElfImage::parse(&gen_elf())?;
+
+        assert_eq!(elf.entry, 0x08000000);
+        assert_eq!(elf.segments.len(), 2);
+        assert_eq!(elf.segments[0].address, 0x08000000);
+        assert_eq!(elf.segments[0].data.len(), 8);
+        // Placed at the ROM load address rather than the IWRAM address
+        assert_eq!(elf.segments[1].address, 0x08000008);
+        assert_eq!(elf.segments[1].data, [0xAA, 0xBB, 0xCC, 0xDD]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_symbols() -> Result<(), ElfError> {
+        let elf = ElfImage::parse(&gen_elf())?;
+
+        assert_eq!(elf.symbols.iter().count(), 2);
+        let main = elf.symbols.find("main").unwrap();
+        assert_eq!(main.address, 0x08000000);
+        assert_eq!(elf.symbols.symbol_at(0x08000006), Some(main));
+        assert_eq!(elf.symbols.symbol_at(0x08000008), None);
+        assert_eq!(
+            elf.symbols
+                .symbol_at(0x03000002)
+                .map(|symbol| &symbol.name[..]),
+            Some("counter")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_elf() {
+        assert!(matches!(
+            ElfImage::parse(b"\x7FELF\x02\x01"),
+            Err(ElfError::Header(_))
+        ));
+
+        let mut data = gen_elf();
+        data.truncate(0x60);
+        assert!(matches!(
+            ElfImage::parse(&data),
+            Err(ElfError::Truncated(_))
+        ));
+    }
+}
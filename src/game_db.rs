@@ -0,0 +1,327 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Name of the user override file looked up next to a ROM
+pub const OVERRIDE_FILE_NAME: &str = "gamedb.json";
+
+/// Backup memory present on the cartridge. The size of the memory is implied
+/// by the type
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SaveType {
+    #[default]
+    None,
+    /// 32KB battery backed SRAM
+    Sram,
+    /// 64KB Flash
+    Flash64K,
+    /// 128KB Flash, banked in two 64KB halves
+    Flash128K,
+    /// 512 byte EEPROM (6-bit addressing)
+    Eeprom512,
+    /// 8KB EEPROM (14-bit addressing)
+    Eeprom8K,
+}
+
+impl SaveType {
+    pub fn size(&self) -> usize {
+        match self {
+            SaveType::None => 0,
+            SaveType::Sram => 0x8000,
+            SaveType::Flash64K => 0x10000,
+            SaveType::Flash128K => 0x20000,
+            SaveType::Eeprom512 => 0x200,
+            SaveType::Eeprom8K => 0x2000,
+        }
+    }
+}
+
+/// Sensors wired to the cartridge GPIO port
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Sensor {
+    /// Boktai series
+    Solar,
+    /// Yoshi's Universal Gravitation, Koro Koro Puzzle
+    Tilt,
+    /// WarioWare: Twisted!
+    Gyro,
+}
+
+/// The hardware configuration of a cartridge after applying heuristics and the
+/// game database
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CartridgeConfig {
+    pub save_type: SaveType,
+    pub rtc: bool,
+    pub sensor: Option<Sensor>,
+    /// Address of a busy loop waiting for an interrupt which can be skipped
+    pub idle_loop: Option<u32>,
+    /// Mirror the ROM across the whole GamePak address space. Needed by the
+    /// Classic NES Series which checks for it as copy protection
+    pub rom_mirroring: bool,
+}
+
+impl CartridgeConfig {
+    /// Guess the save type from the library ID strings the Nintendo SDK leaves
+    /// in the ROM. EEPROM size cannot be detected this way so 512 bytes is
+    /// assumed
+    pub fn detect(rom: &[u8]) -> Self {
+        let contains = |needle: &[u8]| rom.windows(needle.len()).any(|window| window == needle);
+
+        let save_type = if contains(b"EEPROM_V") {
+            SaveType::Eeprom512
+        } else if contains(b"SRAM_V") || contains(b"SRAM_F_V") {
+            SaveType::Sram
+        } else if contains(b"FLASH1M_V") {
+            SaveType::Flash128K
+        } else if contains(b"FLASH_V") || contains(b"FLASH512_V") {
+            SaveType::Flash64K
+        } else {
+            SaveType::None
+        };
+
+        Self {
+            save_type,
+            rtc: contains(b"SIIRTC_V"),
+            ..Default::default()
+        }
+    }
+
+    fn apply(&mut self, entry: &GameOverride) {
+        if let Some(save_type) = entry.save_type {
+            self.save_type = save_type;
+        }
+        if let Some(rtc) = entry.rtc {
+            self.rtc = rtc;
+        }
+        if entry.sensor.is_some() {
+            self.sensor = entry.sensor;
+        }
+        if entry.idle_loop.is_some() {
+            self.idle_loop = entry.idle_loop;
+        }
+        if let Some(rom_mirroring) = entry.rom_mirroring {
+            self.rom_mirroring = rom_mirroring;
+        }
+    }
+}
+
+/// A single database entry. Fields left as `None` keep whatever the heuristics
+/// or an earlier entry selected
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameOverride {
+    /// The 4 character game code from the header at offset `0xAC`
+    pub game_code: String,
+    /// Only match a specific dump (CRC-32 of the full ROM file)
+    pub crc32: Option<u32>,
+    pub save_type: Option<SaveType>,
+    pub rtc: Option<bool>,
+    pub sensor: Option<Sensor>,
+    pub idle_loop: Option<u32>,
+    pub rom_mirroring: Option<bool>,
+}
+
+impl GameOverride {
+    fn matches(&self, game_code: &str, crc32: u32) -> bool {
+        self.game_code == game_code && self.crc32.is_none_or(|crc| crc == crc32)
+    }
+}
+
+/// (game code, save type, RTC, sensor, ROM mirroring)
+type BuiltinEntry = (&'static str, Option<SaveType>, bool, Option<Sensor>, bool);
+
+#[rustfmt::skip]
+const BUILTIN_ENTRIES: &[BuiltinEntry] = &[
+    // Pokémon Ruby/Sapphire/Emerald
+    ("AXVE", Some(SaveType::Flash128K), true, None, false),
+    ("AXVJ", Some(SaveType::Flash128K), true, None, false),
+    ("AXVP", Some(SaveType::Flash128K), true, None, false),
+    ("AXPE", Some(SaveType::Flash128K), true, None, false),
+    ("AXPJ", Some(SaveType::Flash128K), true, None, false),
+    ("AXPP", Some(SaveType::Flash128K), true, None, false),
+    ("BPEE", Some(SaveType::Flash128K), true, None, false),
+    ("BPEJ", Some(SaveType::Flash128K), true, None, false),
+    ("BPEP", Some(SaveType::Flash128K), true, None, false),
+    // Pokémon FireRed/LeafGreen
+    ("BPRE", Some(SaveType::Flash128K), false, None, false),
+    ("BPRJ", Some(SaveType::Flash128K), false, None, false),
+    ("BPRP", Some(SaveType::Flash128K), false, None, false),
+    ("BPGE", Some(SaveType::Flash128K), false, None, false),
+    ("BPGJ", Some(SaveType::Flash128K), false, None, false),
+    ("BPGP", Some(SaveType::Flash128K), false, None, false),
+    // Super Mario Advance 4
+    ("AX4E", Some(SaveType::Flash128K), false, None, false),
+    ("AX4J", Some(SaveType::Flash128K), false, None, false),
+    ("AX4P", Some(SaveType::Flash128K), false, None, false),
+    // Dragon Ball Z: The Legacy of Goku II
+    ("ALFE", Some(SaveType::Eeprom512), false, None, false),
+    ("ALFJ", Some(SaveType::Eeprom512), false, None, false),
+    ("ALFP", Some(SaveType::Eeprom512), false, None, false),
+    // Boktai 1, 2 and 3
+    ("U3IE", None, true, Some(Sensor::Solar), false),
+    ("U3IJ", None, true, Some(Sensor::Solar), false),
+    ("U3IP", None, true, Some(Sensor::Solar), false),
+    ("U32E", None, true, Some(Sensor::Solar), false),
+    ("U32J", None, true, Some(Sensor::Solar), false),
+    ("U32P", None, true, Some(Sensor::Solar), false),
+    ("U33J", None, true, Some(Sensor::Solar), false),
+    // WarioWare: Twisted!
+    ("RZWE", Some(SaveType::Sram), false, Some(Sensor::Gyro), false),
+    ("RZWJ", Some(SaveType::Sram), false, Some(Sensor::Gyro), false),
+    ("RZWP", Some(SaveType::Sram), false, Some(Sensor::Gyro), false),
+    // Yoshi's Universal Gravitation / Topsy-Turvy
+    ("KYGE", Some(SaveType::Eeprom512), false, Some(Sensor::Tilt), false),
+    ("KYGJ", Some(SaveType::Eeprom512), false, Some(Sensor::Tilt), false),
+    ("KYGP", Some(SaveType::Eeprom512), false, Some(Sensor::Tilt), false),
+    // Koro Koro Puzzle: Happy Panechu!
+    ("KHPJ", Some(SaveType::Eeprom512), false, Some(Sensor::Tilt), false),
+    // Classic NES Series
+    ("FBME", Some(SaveType::Eeprom512), false, None, true),
+    ("FADE", Some(SaveType::Eeprom512), false, None, true),
+    ("FDKE", Some(SaveType::Eeprom512), false, None, true),
+    ("FDME", Some(SaveType::Eeprom512), false, None, true),
+    ("FEBE", Some(SaveType::Eeprom512), false, None, true),
+    ("FICE", Some(SaveType::Eeprom512), false, None, true),
+    ("FLBE", Some(SaveType::Eeprom512), false, None, true),
+    ("FMRE", Some(SaveType::Eeprom512), false, None, true),
+    ("FP7E", Some(SaveType::Eeprom512), false, None, true),
+    ("FSME", Some(SaveType::Eeprom512), false, None, true),
+    ("FXVE", Some(SaveType::Eeprom512), false, None, true),
+    ("FZLE", Some(SaveType::Eeprom512), false, None, true),
+];
+
+/// Table of per-title overrides for cartridges the heuristics get wrong.
+/// Entries are applied in order so later (user) entries win over the built-in
+/// ones
+#[derive(Debug, Clone, Default)]
+pub struct GameDb {
+    entries: Vec<GameOverride>,
+}
+
+impl GameDb {
+    pub fn builtin() -> Self {
+        let entries = BUILTIN_ENTRIES
+            .iter()
+            .map(
+                |(game_code, save_type, rtc, sensor, rom_mirroring)| GameOverride {
+                    game_code: game_code.to_string(),
+                    crc32: None,
+                    save_type: *save_type,
+                    rtc: Some(*rtc),
+                    sensor: *sensor,
+                    idle_loop: None,
+                    rom_mirroring: Some(*rom_mirroring),
+                },
+            )
+            .collect();
+
+        Self { entries }
+    }
+
+    /// Extend the database with entries from a JSON file containing a list of
+    /// `GameOverride` objects
+    pub fn load_overrides(&mut self, path: &Path) -> anyhow::Result<(), GameDbError> {
+        let file = std::fs::read(path).map_err(|e| GameDbError::Io(e.to_string()))?;
+        let entries: Vec<GameOverride> =
+            serde_json::from_slice(&file).map_err(|e| GameDbError::Parse(e.to_string()))?;
+
+        log::info!(
+            "Loaded {} game database overrides from {}",
+            entries.len(),
+            path.display()
+        );
+        self.entries.extend(entries);
+        Ok(())
+    }
+
+    pub fn add(&mut self, entry: GameOverride) {
+        self.entries.push(entry);
+    }
+
+    /// Detect the cartridge hardware of `rom` and apply all matching entries
+    pub fn config_for(&self, game_code: &str, rom: &[u8]) -> CartridgeConfig {
+        let crc32 = crate::checksum::crc32(rom);
+        let mut config = CartridgeConfig::detect(rom);
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.matches(game_code, crc32))
+            .for_each(|entry| config.apply(entry));
+
+        config
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum GameDbError {
+    #[error("Failed to read game database ({0})")]
+    Io(String),
+    #[error("Failed to parse game database ({0})")]
+    Parse(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game_db::{CartridgeConfig, GameDb, GameOverride, SaveType, Sensor};
+
+    fn rom_with(id: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0x00; 0x400];
+        rom[0x200..0x200 + id.len()].copy_from_slice(id);
+        rom
+    }
+
+    #[test]
+    fn test_detect_save_type() {
+        let config = CartridgeConfig::detect(&rom_with(b"FLASH1M_V103"));
+        assert_eq!(config.save_type, SaveType::Flash128K);
+
+        let config = CartridgeConfig::detect(&rom_with(b"FLASH512_V131"));
+        assert_eq!(config.save_type, SaveType::Flash64K);
+
+        let config = CartridgeConfig::detect(&rom_with(b"SRAM_F_V100"));
+        assert_eq!(config.save_type, SaveType::Sram);
+
+        let config = CartridgeConfig::detect(&rom_with(b""));
+        assert_eq!(config, CartridgeConfig::default());
+    }
+
+    #[test]
+    fn test_builtin_override() {
+        let db = GameDb::builtin();
+
+        // Heuristics see SRAM but the database knows better
+        let config = db.config_for("KYGE", &rom_with(b"SRAM_V113"));
+        assert_eq!(config.save_type, SaveType::Eeprom512);
+        assert_eq!(config.sensor, Some(Sensor::Tilt));
+
+        // Partial entries keep the detected save type
+        let config = db.config_for("U3IE", &rom_with(b"EEPROM_V122"));
+        assert_eq!(config.save_type, SaveType::Eeprom512);
+        assert!(config.rtc);
+    }
+
+    #[test]
+    fn test_user_override() {
+        let rom = rom_with(b"FLASH_V126");
+        let mut db = GameDb::builtin();
+        db.add(GameOverride {
+            game_code: "BPEE".to_string(),
+            crc32: Some(crate::checksum::crc32(&rom) ^ 1),
+            idle_loop: Some(0x080008C6),
+            ..Default::default()
+        });
+        db.add(GameOverride {
+            game_code: "BPEE".to_string(),
+            rtc: Some(false),
+            ..Default::default()
+        });
+
+        let config = db.config_for("BPEE", &rom);
+        assert_eq!(config.save_type, SaveType::Flash128K);
+        assert!(!config.rtc);
+        // CRC did not match
+        assert_eq!(config.idle_loop, None);
+    }
+}
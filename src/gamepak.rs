@@ -2,9 +2,12 @@ use std::path::Path;
 
 use thiserror::Error;
 
+use crate::game_db::{CartridgeConfig, GameDb, OVERRIDE_FILE_NAME};
+use crate::patch::{apply_patch, find_patch_for};
+
 /// The GBA GamePak is extracted from 192 bytes region at the start of a ROM
 /// file (Mapped to `0x80000000`-`0x800000BF` in the memory space
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct GamePakHeader {
     /// The `title` is a 12 byte uppercase ASCII string located at offset `0xA0`
     pub title: String,
@@ -20,18 +23,60 @@ pub struct GamePakHeader {
 pub struct Gamepak {
     pub header: GamePakHeader,
     pub rom: Vec<u8>,
+    /// Save type and extra hardware selected by heuristics and the game
+    /// database
+    pub config: CartridgeConfig,
 }
 
 impl Gamepak {
-    /// Extract out the header and init a `Gamepak` from the given ROM bytes
-    pub fn new(path: &Path) -> anyhow::Result<Gamepak, String> {
-        let rom = std::fs::read(path).map_err(|e| e.to_string())?;
-        Gamepak::build_rom(rom).map_err(|e| e.to_string())
+    /// Extract out the header and init a `Gamepak` from the given ROM bytes.
+    /// A `gamedb.json` next to the ROM extends the built-in game database.
+    ///
+    /// The IPS/UPS/BPS `patch_path` is applied in memory before parsing the
+    /// header. Without one a `<rom>.ips`, `<rom>.ups` or `<rom>.bps` next to
+    /// the ROM is used if it exists
+    pub fn new(path: &Path, patch_path: Option<&Path>) -> anyhow::Result<Gamepak, String> {
+        let mut rom = std::fs::read(path).map_err(|e| e.to_string())?;
+
+        if let Some(patch_path) = patch_path
+            .map(Path::to_path_buf)
+            .or_else(|| find_patch_for(path))
+        {
+            let patch = std::fs::read(&patch_path).map_err(|e| e.to_string())?;
+            rom = apply_patch(&rom, &patch).map_err(|e| e.to_string())?;
+            log::info!("Applied patch {}", patch_path.display());
+        }
+
+        let mut game_db = GameDb::builtin();
+        if let Some(override_path) = path.parent().map(|dir| dir.join(OVERRIDE_FILE_NAME))
+            && override_path.is_file()
+        {
+            game_db
+                .load_overrides(&override_path)
+                .map_err(|e| e.to_string())?;
+        }
+
+        Gamepak::build_rom(rom, &game_db).map_err(|e| e.to_string())
+    }
+
+    /// Init a `Gamepak` from ROM bytes which are already in memory e.g. built
+    /// from the segments of an ELF file
+    pub fn from_rom(rom: Vec<u8>) -> anyhow::Result<Gamepak, String> {
+        Gamepak::build_rom(rom, &GameDb::builtin()).map_err(|e| e.to_string())
     }
 
-    fn build_rom(rom: Vec<u8>) -> anyhow::Result<Gamepak, GamePakError> {
+    fn build_rom(rom: Vec<u8>, game_db: &GameDb) -> anyhow::Result<Gamepak, GamePakError> {
+        if rom.len() < 0xC0 {
+            return Err(GamePakError::Size {
+                expected: 0xC0,
+                got: rom.len(),
+            });
+        }
         let header = Gamepak::parse_header(&rom[..0xC0])?;
-        let mut rom_data = rom[0xC0..].to_vec();
+        let config = game_db.config_for(&header.game_code, &rom);
+        // The header is part of the ROM as the CPU starts executing from its
+        // first word
+        let mut rom_data = rom;
 
         if !rom_data.len().is_power_of_two() {
             rom_data.resize(rom_data.len().next_power_of_two(), 0x00);
@@ -40,12 +85,13 @@ impl Gamepak {
         Ok(Gamepak {
             header,
             rom: rom_data,
+            config,
         })
     }
 
     /// Extract out fields from the header and also check the expected bytes
     /// and checksum
-    fn parse_header(header: &[u8]) -> anyhow::Result<GamePakHeader, GamePakError> {
+    pub(crate) fn parse_header(header: &[u8]) -> anyhow::Result<GamePakHeader, GamePakError> {
         if header.len() != 0xC0 {
             return Err(GamePakError::Size {
                 expected: 0xC0,
@@ -116,6 +162,7 @@ pub enum GamePakError {
 
 #[cfg(test)]
 mod tests {
+    use crate::game_db::{GameDb, SaveType};
     use crate::gamepak::{GamePakError, GamePakHeader, Gamepak};
 
     fn gen_header() -> Vec<u8> {
@@ -225,10 +272,25 @@ mod tests {
     fn test_rom_size() -> Result<(), GamePakError> {
         let mut rom = gen_header(); // Len 0xC0
         rom.resize(0x3FFA, 0x00);
-        let gamepak = Gamepak::build_rom(rom)?;
+        let gamepak = Gamepak::build_rom(rom, &GameDb::builtin())?;
         assert_eq!(gamepak.rom.len(), 0x4000);
         assert!(gamepak.rom.len().is_power_of_two());
 
+        // The header is mapped along with the rest of the ROM
+        assert_eq!(&gamepak.rom[0xAC..0xB0], "BMXE".as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_game_db_config() -> Result<(), GamePakError> {
+        let mut rom = gen_header();
+        rom[0xAC..0xB0].copy_from_slice("AXVE".as_bytes());
+        rom.extend_from_slice(b"SRAM_V110");
+        let gamepak = Gamepak::build_rom(rom, &GameDb::builtin())?;
+        assert_eq!(gamepak.config.save_type, SaveType::Flash128K);
+        assert!(gamepak.config.rtc);
+
         Ok(())
     }
 }
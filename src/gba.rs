@@ -1,20 +1,33 @@
+use crate::apu::sink::AudioSink;
+use crate::bios::{BiosKind, load_bios};
 use crate::cpu::Arm7Cpu;
+use crate::elf::{ElfImage, SymbolTable};
 use crate::gamepak::{GamePakHeader, Gamepak};
-use crate::system_bus::Bus;
+use crate::multiboot::{MULTIBOOT_ENTRY, MULTIBOOT_LOAD_ADDRESS, MultibootImage};
+use crate::ppu::FrameBuffer;
+use crate::system_bus::{Bus, GAMEPAK_ROM_MAX_SIZE, GAMEPAK_ROM_START, SystemBus};
 use std::path::Path;
 
+/// Where the BIOS jumps to once it is done with the boot animation
+const GAMEPAK_ENTRY: u32 = 0x08000000;
+
 pub struct Gba {
     system_bus: Bus,
     pub cpu: Arm7Cpu,
     pub header: GamePakHeader,
+    pub bios: BiosKind,
+    /// Symbols of the running program. Only available when loaded from an ELF
+    pub symbols: SymbolTable,
 }
 
 impl Gba {
+    /// Without a `bios_path` the built-in BIOS is used, the boot sequence is
+    /// skipped and BIOS calls are emulated
     pub fn new(
         rom_path: impl AsRef<Path>,
-        bios_path: impl AsRef<Path>,
+        bios_path: Option<&Path>,
     ) -> anyhow::Result<Self, String> {
-        let gamepak = Gamepak::new(rom_path.as_ref())?;
+        let gamepak = Gamepak::new(rom_path.as_ref(), None)?;
 
         log::info!(
             "Loaded GamePak from {}",
@@ -24,26 +37,286 @@ impl Gba {
         log::info!("Game Code: {}", gamepak.header.game_code);
         log::info!("Maker Code: {}", gamepak.header.maker_code);
         log::info!("ROM size: {} bytes", gamepak.rom.len());
+        log::info!("Cartridge: {:?}", gamepak.config);
 
         let header = gamepak.header.clone();
-        let bios = std::fs::read(bios_path).map_err(|e| e.to_string())?;
-        let system_bus = Bus::new(gamepak, bios);
-        let cpu = Arm7Cpu::new();
+        let bios = load_bios(bios_path)?;
+        let bios_kind = BiosKind::identify(&bios);
+        log::info!("BIOS: {bios_kind:?}");
+        let system_bus = Bus::new(Some(gamepak), bios);
+        let mut cpu = Arm7Cpu::new();
+        cpu.set_hle_bios(bios_path.is_none());
+        log::debug!("Initialized CPU");
+
+        let mut gba = Self {
+            system_bus,
+            cpu,
+            header,
+            bios: bios_kind,
+            symbols: SymbolTable::default(),
+        };
+        if bios_path.is_none() {
+            gba.skip_bios();
+        }
+
+        Ok(gba)
+    }
+
+    /// Run a multiboot (`.mb`) program without a cartridge. The program is
+    /// placed in on-board WRAM and started in the state the BIOS leaves after
+    /// a link cable transfer
+    pub fn new_multiboot(
+        image_path: impl AsRef<Path>,
+        bios_path: Option<&Path>,
+    ) -> anyhow::Result<Self, String> {
+        let image = MultibootImage::new(image_path.as_ref())?;
+
+        log::info!(
+            "Loaded multiboot image from {}",
+            image_path.as_ref().to_str().unwrap()
+        );
+        log::info!("Title: {}", image.header.title);
+        log::info!("Game Code: {}", image.header.game_code);
+        log::info!("Image size: {} bytes", image.data.len());
+
+        let bios = load_bios(bios_path)?;
+        let bios_kind = BiosKind::identify(&bios);
+        log::info!("BIOS: {bios_kind:?}");
+        let mut system_bus = Bus::new(None, bios);
+        system_bus.load(MULTIBOOT_LOAD_ADDRESS, &image.data);
+        system_bus.skip_bios();
+
+        let mut cpu = Arm7Cpu::new();
+        cpu.set_hle_bios(bios_path.is_none());
+        cpu.skip_bios(MULTIBOOT_ENTRY);
         log::debug!("Initialized CPU");
 
+        Ok(Self {
+            system_bus,
+            cpu,
+            header: image.header,
+            bios: bios_kind,
+            symbols: SymbolTable::default(),
+        })
+    }
+
+    /// Run an ELF executable directly. Segments loaded into the GamePak ROM
+    /// area make up the ROM while the rest are copied into RAM. Execution
+    /// starts at the ELF entry point
+    pub fn new_elf(
+        elf_path: impl AsRef<Path>,
+        bios_path: Option<&Path>,
+    ) -> anyhow::Result<Self, String> {
+        let elf = ElfImage::new(elf_path.as_ref())?;
+
+        log::info!("Loaded ELF from {}", elf_path.as_ref().to_str().unwrap());
+        log::info!("Entry point: {:#010X}", elf.entry);
+
+        let rom_area = GAMEPAK_ROM_START..GAMEPAK_ROM_START + GAMEPAK_ROM_MAX_SIZE;
+        let (rom_segments, ram_segments): (Vec<_>, Vec<_>) = elf
+            .segments
+            .iter()
+            .partition(|segment| rom_area.contains(&(segment.address as usize)));
+
+        let mut rom = vec![];
+        for segment in rom_segments {
+            let offset = segment.address as usize - GAMEPAK_ROM_START;
+            if rom.len() < offset + segment.data.len() {
+                rom.resize(offset + segment.data.len(), 0x00);
+            }
+            rom[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+
+        let gamepak = if rom.is_empty() {
+            None
+        } else {
+            Some(Gamepak::from_rom(rom)?)
+        };
+        let header = match gamepak.as_ref() {
+            Some(gamepak) => gamepak.header.clone(),
+            // Multiboot builds carry the header at the start of on-board WRAM
+            None => ram_segments
+                .iter()
+                .find(|segment| segment.address == MULTIBOOT_LOAD_ADDRESS)
+                .and_then(|segment| Gamepak::parse_header(segment.data.get(..0xC0)?).ok())
+                .unwrap_or_default(),
+        };
+        log::info!("Title: {}", header.title);
+        log::info!("Game Code: {}", header.game_code);
+
+        let bios = load_bios(bios_path)?;
+        let bios_kind = BiosKind::identify(&bios);
+        log::info!("BIOS: {bios_kind:?}");
+        let mut system_bus = Bus::new(gamepak, bios);
+        for segment in ram_segments {
+            system_bus.load(segment.address, &segment.data);
+        }
+        system_bus.skip_bios();
+
+        let mut cpu = Arm7Cpu::new();
+        cpu.set_hle_bios(bios_path.is_none());
+        cpu.skip_bios(elf.entry);
+        log::debug!("Initialized CPU");
+        log::info!("Loaded {} symbols", elf.symbols.iter().count());
+
         Ok(Self {
             system_bus,
             cpu,
             header,
+            bios: bios_kind,
+            symbols: elf.symbols,
         })
     }
 
+    /// Direct boot: skip the BIOS intro and start the GamePak in the state the
+    /// BIOS leaves the system in. Must be called before `start`
+    pub fn skip_bios(&mut self) {
+        self.cpu.skip_bios(GAMEPAK_ENTRY);
+        self.system_bus.skip_bios();
+    }
+
+    /// Emulate BIOS calls instead of running the BIOS code. Always enabled
+    /// without a BIOS dump
+    pub fn set_hle_bios(&mut self, enabled: bool) {
+        self.cpu.set_hle_bios(enabled);
+    }
+
     pub fn start(&mut self) {
         //! Start all subcomponents of the system
         self.cpu.start(&mut self.system_bus);
     }
 
     pub fn step(&mut self) {
+        if self.system_bus.halted() {
+            if !self.system_bus.interrupt_pending() {
+                self.system_bus.idle();
+                return;
+            }
+            self.system_bus.wake();
+        }
+
+        if self.system_bus.irq_line() {
+            self.cpu.handle_irq(&mut self.system_bus);
+        }
         self.cpu.step(&mut self.system_bus);
     }
+
+    /// Run at least `cycles` cycles. Returns the cycles actually run, which
+    /// can be a few more since instructions are not interrupted
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let start = self.system_bus.cycles();
+        while self.system_bus.cycles() - start < cycles {
+            self.step();
+        }
+        self.system_bus.cycles() - start
+    }
+
+    /// Run until the next VBlank starts, when the frame buffer holds a
+    /// complete frame
+    pub fn run_until_vblank(&mut self) {
+        let frames = self.system_bus.frames();
+        while self.system_bus.frames() == frames {
+            self.step();
+        }
+    }
+
+    /// The number of frames completed since power on
+    pub fn frames(&self) -> u64 {
+        self.system_bus.frames()
+    }
+
+    /// Set the buttons held down. See `Bus::set_pressed_keys`
+    pub fn set_pressed_keys(&mut self, pressed: u16) {
+        self.system_bus.set_pressed_keys(pressed);
+    }
+
+    /// Push the audio mixed since the last call into `sink`. The APU buffers
+    /// about two frames at the highest sample rate, so this should be called
+    /// after every frame
+    pub fn push_audio(&mut self, sink: &mut dyn AudioSink) {
+        for (sample_rate, samples) in self.system_bus.apu.drain_runs() {
+            sink.push(&samples, sample_rate);
+        }
+    }
+
+    /// Run until the next scanline starts
+    pub fn run_scanline(&mut self) {
+        let line = self.system_bus.vcount();
+        while self.system_bus.vcount() == line {
+            self.step();
+        }
+    }
+
+    /// Run a frame and return it
+    pub fn run_frame(&mut self) -> &FrameBuffer {
+        self.run_until_vblank();
+        self.frame_buffer()
+    }
+
+    /// The last rendered screen. Lines are drawn as the frame progresses
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.system_bus.ppu.frame_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bios::{BiosKind, load_bios};
+    use crate::cpu::Arm7Cpu;
+    use crate::elf::SymbolTable;
+    use crate::gamepak::Gamepak;
+    use crate::gba::Gba;
+    use crate::system_bus::Bus;
+
+    /// A GamePak with `code` at its entry point
+    fn test_gba(code: &[u32]) -> Gba {
+        let mut rom = vec![0x00; 0x200];
+        for (i, opcode) in code.iter().enumerate() {
+            rom[i * 4..i * 4 + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+        // The fixed header value
+        rom[0xB2] = 0x96;
+        let gamepak = Gamepak::from_rom(rom).unwrap();
+
+        let mut gba = Gba {
+            header: gamepak.header.clone(),
+            system_bus: Bus::new(Some(gamepak), load_bios(None).unwrap()),
+            cpu: Arm7Cpu::new(),
+            bios: BiosKind::Builtin,
+            symbols: SymbolTable::default(),
+        };
+        gba.set_hle_bios(true);
+        gba.skip_bios();
+        gba.start();
+
+        gba
+    }
+
+    #[test]
+    fn test_run_frame() {
+        // An endless loop
+        let mut gba = test_gba(&[0xEAFFFFFE]);
+
+        gba.run_frame();
+        assert_eq!(gba.frames(), 1);
+        assert_eq!(gba.system_bus.vcount(), 160);
+
+        gba.run_frame();
+        assert_eq!(gba.frames(), 2);
+        assert_eq!(gba.system_bus.vcount(), 160);
+
+        assert!(gba.run_cycles(1000) >= 1000);
+    }
+
+    #[test]
+    fn test_failed_condition_timing() {
+        // moveq r0, r0 with Z clear
+        let mut gba = test_gba(&[0x01A00000; 4]);
+        gba.step();
+
+        // Only the sequential fetch of the next opcode: 1 + 2 wait states for
+        // each half of the word
+        assert_eq!(gba.run_cycles(1), 6);
+        assert_eq!(gba.run_cycles(1), 6);
+    }
 }
@@ -0,0 +1,27 @@
+/// Interrupt sources in the order of their bits in IE and IF
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Interrupt {
+    VBlank = 0,
+    HBlank = 1,
+    VCount = 2,
+    Timer0 = 3,
+    Timer1 = 4,
+    Timer2 = 5,
+    Timer3 = 6,
+    Serial = 7,
+    Dma0 = 8,
+    Dma1 = 9,
+    Dma2 = 10,
+    Dma3 = 11,
+    Keypad = 12,
+    GamePak = 13,
+}
+
+impl Interrupt {
+    pub fn mask(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// The 14 interrupt sources which can be enabled and requested
+pub const INTERRUPT_MASK: u16 = 0x3FFF;
@@ -1,10 +1,21 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
+pub mod apu;
+pub mod bios;
+pub mod checksum;
 pub mod cpu;
+pub mod dma;
+pub mod elf;
+pub mod game_db;
 pub mod gamepak;
 pub mod gba;
+pub mod interrupts;
+pub mod multiboot;
+pub mod patch;
+pub mod ppu;
 pub mod system_bus;
+pub mod timers;
 
 #[macro_export]
 macro_rules! test_mask {
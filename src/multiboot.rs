@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use crate::gamepak::{GamePakHeader, Gamepak};
+
+/// Multiboot programs are transferred to and run from on-board WRAM
+pub const MULTIBOOT_LOAD_ADDRESS: u32 = 0x02000000;
+/// The BIOS jumps past the header after a multiboot transfer
+pub const MULTIBOOT_ENTRY: u32 = 0x020000C0;
+/// Programs are limited to the 256KB of on-board WRAM
+pub const MULTIBOOT_MAX_SIZE: usize = 0x40000;
+
+/// Offset of the boot mode byte the BIOS fills in after the transfer
+const BOOT_MODE_OFFSET: usize = 0xC4;
+/// Offset of the slave ID byte the BIOS fills in after the transfer
+const SLAVE_ID_OFFSET: usize = 0xC5;
+/// Boot mode the BIOS reports for a transfer over the multi-play link cable
+const BOOT_MODE_MULTIPLAY: u8 = 0x03;
+
+/// A `.mb` image as received over the link cable. It uses the same header as a
+/// `Gamepak` ROM
+#[derive(Debug, Clone)]
+pub struct MultibootImage {
+    pub header: GamePakHeader,
+    pub data: Vec<u8>,
+}
+
+impl MultibootImage {
+    pub fn new(path: &Path) -> anyhow::Result<MultibootImage, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        MultibootImage::build_image(data)
+    }
+
+    fn build_image(mut data: Vec<u8>) -> anyhow::Result<MultibootImage, String> {
+        if data.len() < SLAVE_ID_OFFSET + 1 || data.len() > MULTIBOOT_MAX_SIZE {
+            return Err(format!(
+                "Invalid multiboot image size (expected {:#X}-{MULTIBOOT_MAX_SIZE:#X}; got {:#X})",
+                SLAVE_ID_OFFSET + 1,
+                data.len()
+            ));
+        }
+
+        let header = Gamepak::parse_header(&data[..0xC0]).map_err(|e| e.to_string())?;
+
+        // Transferred as the first (and only) slave
+        data[BOOT_MODE_OFFSET] = BOOT_MODE_MULTIPLAY;
+        data[SLAVE_ID_OFFSET] = 0x01;
+
+        Ok(MultibootImage { header, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::multiboot::MultibootImage;
+
+    #[test]
+    fn test_build_image() {
+        let mut data = vec![0x00; 0x200];
+        data[0xA0..0xAC].copy_from_slice("MULTIBOOTMB ".as_bytes());
+        data[0xAC..0xB0].copy_from_slice("MBTE".as_bytes());
+        data[0xB0..0xB2].copy_from_slice("01".as_bytes());
+        data[0xB2] = 0x96;
+
+        let image = MultibootImage::build_image(data.clone()).unwrap();
+        assert_eq!(image.header.game_code, "MBTE");
+        assert_eq!(image.data[0xC4], 0x03);
+        assert_eq!(image.data[0xC5], 0x01);
+
+        // Does not fit in on-board WRAM
+        data.resize(0x40001, 0x00);
+        assert!(MultibootImage::build_image(data).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::checksum::crc32;
+
+/// Extensions checked (in order) when looking for a patch next to a ROM
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+/// Return the first `<rom>.ips`, `<rom>.ups` or `<rom>.bps` found next to `rom_path`
+pub fn find_patch_for(rom_path: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// Apply an IPS, UPS or BPS patch to `rom`. The format is detected from the
+/// magic bytes of the patch
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+/// Cursor over the patch bytes which turns running out of data into an error
+struct PatchReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], offset: usize) -> Self {
+        Self { data, offset }
+    }
+
+    fn read_bytes(&mut self, count: usize) -> anyhow::Result<&'a [u8], PatchError> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + count)
+            .ok_or(PatchError::Truncated)?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn read_byte(&mut self) -> anyhow::Result<u8, PatchError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_be(&mut self, count: usize) -> anyhow::Result<usize, PatchError> {
+        Ok(self
+            .read_bytes(count)?
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as usize))
+    }
+
+    /// The variable length integer encoding shared by UPS and BPS
+    fn read_varint(&mut self) -> anyhow::Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.read_byte()?;
+            value = value
+                .checked_add((byte & 0x7F) as usize * shift)
+                .ok_or(PatchError::Malformed("Integer overflow".to_string()))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>, PatchError> {
+    let mut target = rom.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+
+    loop {
+        if reader.data[reader.offset..].starts_with(IPS_EOF) {
+            reader.offset += IPS_EOF.len();
+            break;
+        }
+
+        let offset = reader.read_be(3)?;
+        let size = reader.read_be(2)?;
+        let (size, fill) = if size == 0 {
+            // RLE record
+            let size = reader.read_be(2)?;
+            (size, Some(reader.read_byte()?))
+        } else {
+            (size, None)
+        };
+
+        if target.len() < offset + size {
+            target.resize(offset + size, 0x00);
+        }
+        match fill {
+            Some(value) => target[offset..offset + size].fill(value),
+            None => target[offset..offset + size].copy_from_slice(reader.read_bytes(size)?),
+        }
+    }
+
+    // Optional truncation extension
+    if let Ok(size) = reader.read_be(3) {
+        target.truncate(size);
+    }
+
+    Ok(target)
+}
+
+/// Split off and check the `source`, `target`, `patch` CRC-32 footer used by UPS and BPS
+fn verify_footer<'a>(
+    source: &[u8],
+    patch: &'a [u8],
+) -> anyhow::Result<(u32, &'a [u8]), PatchError> {
+    if patch.len() < 4 + 12 {
+        return Err(PatchError::Truncated);
+    }
+    let (body, footer) = patch.split_at(patch.len() - 12);
+    let footer_value = |idx: usize| u32::from_le_bytes(footer[idx..idx + 4].try_into().unwrap());
+
+    let patch_crc = crc32(&patch[..patch.len() - 4]);
+    if patch_crc != footer_value(8) {
+        return Err(PatchError::Checksum {
+            what: "patch",
+            expected: footer_value(8),
+            got: patch_crc,
+        });
+    }
+
+    let source_crc = crc32(source);
+    if source_crc != footer_value(0) {
+        return Err(PatchError::Checksum {
+            what: "source ROM",
+            expected: footer_value(0),
+            got: source_crc,
+        });
+    }
+
+    Ok((footer_value(4), body))
+}
+
+fn verify_target(target: &[u8], expected: u32) -> anyhow::Result<(), PatchError> {
+    let got = crc32(target);
+    if got != expected {
+        return Err(PatchError::Checksum {
+            what: "patched ROM",
+            expected,
+            got,
+        });
+    }
+    Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>, PatchError> {
+    let (target_crc, body) = verify_footer(rom, patch)?;
+    let mut reader = PatchReader::new(body, UPS_MAGIC.len());
+
+    let source_size = reader.read_varint()?;
+    let target_size = reader.read_varint()?;
+    if source_size != rom.len() {
+        return Err(PatchError::Malformed(format!(
+            "Expected a {source_size} byte source ROM; got {} bytes",
+            rom.len()
+        )));
+    }
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0x00);
+
+    let mut output_offset = 0usize;
+    while reader.offset < body.len() {
+        output_offset += reader.read_varint()?;
+        loop {
+            let value = reader.read_byte()?;
+            if let Some(byte) = target.get_mut(output_offset) {
+                *byte ^= value;
+            }
+            output_offset += 1;
+            if value == 0x00 {
+                break;
+            }
+        }
+    }
+
+    verify_target(&target, target_crc)?;
+    Ok(target)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>, PatchError> {
+    let (target_crc, body) = verify_footer(rom, patch)?;
+    let mut reader = PatchReader::new(body, BPS_MAGIC.len());
+
+    let source_size = reader.read_varint()?;
+    let target_size = reader.read_varint()?;
+    let metadata_size = reader.read_varint()?;
+    reader.read_bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(PatchError::Malformed(format!(
+            "Expected a {source_size} byte source ROM; got {} bytes",
+            rom.len()
+        )));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_relative = 0isize;
+    let mut target_relative = 0isize;
+    let out_of_bounds = || PatchError::Malformed("Copy outside of ROM".to_string());
+
+    while reader.offset < body.len() {
+        let data = reader.read_varint()?;
+        let length = (data >> 2) + 1;
+        match data & 0b11 {
+            // SourceRead
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(rom.get(start..start + length).ok_or_else(out_of_bounds)?);
+            }
+            // TargetRead
+            1 => target.extend_from_slice(reader.read_bytes(length)?),
+            // SourceCopy
+            2 => {
+                source_relative += decode_signed(reader.read_varint()?);
+                let start = usize::try_from(source_relative).map_err(|_| out_of_bounds())?;
+                target.extend_from_slice(rom.get(start..start + length).ok_or_else(out_of_bounds)?);
+                source_relative += length as isize;
+            }
+            // TargetCopy. The ranges may overlap so this has to be byte by byte
+            _ => {
+                target_relative += decode_signed(reader.read_varint()?);
+                for _ in 0..length {
+                    let value = *usize::try_from(target_relative)
+                        .ok()
+                        .and_then(|idx| target.get(idx))
+                        .ok_or_else(out_of_bounds)?;
+                    target.push(value);
+                    target_relative += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::Malformed(format!(
+            "Expected a {target_size} byte patched ROM; got {} bytes",
+            target.len()
+        )));
+    }
+    verify_target(&target, target_crc)?;
+    Ok(target)
+}
+
+/// BPS copy offsets store the sign in the lowest bit
+fn decode_signed(value: usize) -> isize {
+    let magnitude = (value >> 1) as isize;
+    if value & 1 == 1 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PatchError {
+    #[error("Unknown patch format (expected IPS, UPS or BPS)")]
+    UnknownFormat,
+    #[error("Patch ends unexpectedly")]
+    Truncated,
+    #[error("Malformed patch ({0})")]
+    Malformed(String),
+    #[error("Checksum mismatch for {what} (expected {expected:#010X}; got {got:#010X})")]
+    Checksum {
+        what: &'static str,
+        expected: u32,
+        got: u32,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::crc32;
+    use crate::patch::{PatchError, apply_patch};
+
+    fn encode_varint(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn append_footer(source: &[u8], target: &[u8], patch: &mut Vec<u8>) {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let patch_crc = crc32(patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+    }
+
+    #[test]
+    fn test_ips() -> Result<(), PatchError> {
+        let rom = vec![0x00; 8];
+        let mut patch = b"PATCH".to_vec();
+        // Regular record at 0x000002
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+        // RLE record extending the ROM
+        patch.extend_from_slice(&[0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+
+        let target = apply_patch(&rom, &patch)?;
+        assert_eq!(
+            target,
+            [
+                0x00, 0x00, 0xAA, 0xBB, 0x00, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC
+            ]
+        );
+
+        // Truncation extension
+        patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+        let target = apply_patch(&rom, &patch)?;
+        assert_eq!(target, [0x00, 0x00, 0xAA, 0xBB]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ups() -> Result<(), PatchError> {
+        let source = b"ABCDEFGH".to_vec();
+        let target = b"ABXDEFGHIJ".to_vec();
+
+        let mut patch = b"UPS1".to_vec();
+        encode_varint(source.len(), &mut patch);
+        encode_varint(target.len(), &mut patch);
+        // Skip 2 bytes, XOR one byte
+        encode_varint(2, &mut patch);
+        patch.extend_from_slice(&[b'C' ^ b'X', 0x00]);
+        // Skip to the appended bytes
+        encode_varint(4, &mut patch);
+        patch.extend_from_slice(&[b'I', b'J', 0x00]);
+        append_footer(&source, &target, &mut patch);
+
+        assert_eq!(apply_patch(&source, &patch)?, target);
+
+        // Wrong source ROM
+        assert!(matches!(
+            apply_patch(b"ABCDEFGX", &patch),
+            Err(PatchError::Checksum { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bps() -> Result<(), PatchError> {
+        let source = b"0123456789".to_vec();
+        let target = b"0123ab8989898".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        encode_varint(source.len(), &mut patch);
+        encode_varint(target.len(), &mut patch);
+        encode_varint(0, &mut patch);
+        // SourceRead 4
+        encode_varint(3 << 2, &mut patch);
+        // TargetRead "ab"
+        encode_varint((1 << 2) | 1, &mut patch);
+        patch.extend_from_slice(b"ab");
+        // SourceCopy 2 from offset 8
+        encode_varint((1 << 2) | 2, &mut patch);
+        encode_varint(8 << 1, &mut patch);
+        // TargetCopy 5 from offset 6 (overlapping)
+        encode_varint((4 << 2) | 3, &mut patch);
+        encode_varint(6 << 1, &mut patch);
+        append_footer(&source, &target, &mut patch);
+
+        assert_eq!(apply_patch(&source, &patch)?, target);
+
+        // Corrupted patch
+        let len = patch.len();
+        patch[len - 13] ^= 0xFF;
+        assert!(matches!(
+            apply_patch(&source, &patch),
+            Err(PatchError::Checksum { what: "patch", .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use crate::ppu::mosaic::BGCNT_MOSAIC;
+use crate::ppu::text::BackgroundControl;
+use crate::ppu::{Layer, Ppu, SCREEN_WIDTH, read_register};
+use crate::system_bus::REG_BG2PA;
+
+/// BG3's affine registers follow BG2's
+const AFFINE_REGISTERS_SIZE: usize = 0x10;
+const REG_PA: usize = 0x0;
+const REG_PB: usize = 0x2;
+const REG_PC: usize = 0x4;
+const REG_PD: usize = 0x6;
+const REG_X: usize = 0x8;
+const REG_Y: usize = 0xC;
+
+/// Pixels outside of the map wrap around instead of being transparent
+const BGCNT_WRAPAROUND: u16 = 1 << 13;
+
+/// The internal copy of BGxX and BGxY in 20.8 fixed point. It is reloaded at
+/// VBlank and when the registers are written, and moves by PB and PD after
+/// every line
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct ReferencePoint {
+    x: i32,
+    y: i32,
+}
+
+fn affine_register(affine_bg: usize, offset: usize) -> usize {
+    REG_BG2PA + affine_bg * AFFINE_REGISTERS_SIZE + offset
+}
+
+fn parameter(io_registers: &[u8], affine_bg: usize, offset: usize) -> i32 {
+    read_register(io_registers, affine_register(affine_bg, offset)) as i16 as i32
+}
+
+/// The 28-bit signed value of BGxX or BGxY
+fn reference(io_registers: &[u8], affine_bg: usize, offset: usize) -> i32 {
+    let offset = affine_register(affine_bg, offset);
+    let low = read_register(io_registers, offset) as u32;
+    let high = read_register(io_registers, offset + 2) as u32;
+    (((high << 16) | low) << 4) as i32 >> 4
+}
+
+impl Ppu {
+    /// Reload the reference point of BG2 (`affine_bg` 0) or BG3 (1) from the
+    /// IO registers
+    pub fn latch_reference_point(&mut self, affine_bg: usize, io_registers: &[u8]) {
+        self.reference_points[affine_bg] = ReferencePoint {
+            x: reference(io_registers, affine_bg, REG_X),
+            y: reference(io_registers, affine_bg, REG_Y),
+        };
+    }
+
+    /// Reload only the X or the Y coordinate, as a write to BGxX or BGxY does.
+    /// The other one keeps moving from where it was
+    pub fn latch_reference_coordinate(&mut self, affine_bg: usize, y: bool, io_registers: &[u8]) {
+        let point = &mut self.reference_points[affine_bg];
+        if y {
+            point.y = reference(io_registers, affine_bg, REG_Y);
+        } else {
+            point.x = reference(io_registers, affine_bg, REG_X);
+        }
+    }
+
+    pub(super) fn latch_reference_points(&mut self, io_registers: &[u8]) {
+        self.latch_reference_point(0, io_registers);
+        self.latch_reference_point(1, io_registers);
+    }
+
+    pub(super) fn advance_reference_points(&mut self, io_registers: &[u8]) {
+        for (affine_bg, point) in self.reference_points.iter_mut().enumerate() {
+            point.x = point
+                .x
+                .wrapping_add(parameter(io_registers, affine_bg, REG_PB));
+            point.y = point
+                .y
+                .wrapping_add(parameter(io_registers, affine_bg, REG_PD));
+        }
+    }
+
+    /// The texture coordinates sampled by each pixel of the current line.
+    /// With mosaic, the line is the first line of the mosaic block
+    pub(super) fn affine_line(
+        &self,
+        bgcnt: u16,
+        affine_bg: usize,
+        io_registers: &[u8],
+    ) -> [(i32, i32); SCREEN_WIDTH] {
+        let point = if bgcnt & BGCNT_MOSAIC != 0 {
+            self.mosaic_reference_points[affine_bg]
+        } else {
+            self.reference_points[affine_bg]
+        };
+        let pa = parameter(io_registers, affine_bg, REG_PA);
+        let pc = parameter(io_registers, affine_bg, REG_PC);
+
+        std::array::from_fn(|x| {
+            let x = x as i32;
+            (
+                point.x.wrapping_add(pa * x) >> 8,
+                point.y.wrapping_add(pc * x) >> 8,
+            )
+        })
+    }
+
+    pub(super) fn render_affine_line(
+        &self,
+        bgcnt: u16,
+        affine_bg: usize,
+        io_registers: &[u8],
+        layer: &mut Layer,
+    ) {
+        let control = BackgroundControl::new(bgcnt);
+        let coordinates = self.affine_line(bgcnt, affine_bg, io_registers);
+        for (pixel, (x, y)) in layer.iter_mut().zip(coordinates) {
+            *pixel = self.affine_pixel(&control, bgcnt & BGCNT_WRAPAROUND != 0, x, y);
+        }
+    }
+
+    /// Affine maps are square with one byte entries and always use 8bpp tiles
+    fn affine_pixel(
+        &self,
+        control: &BackgroundControl,
+        wraparound: bool,
+        x: i32,
+        y: i32,
+    ) -> Option<u16> {
+        let size = 128 << control.size;
+        let (x, y) = if wraparound {
+            (x.rem_euclid(size), y.rem_euclid(size))
+        } else if (0..size).contains(&x) && (0..size).contains(&y) {
+            (x, y)
+        } else {
+            return None;
+        };
+        let (x, y, size) = (x as usize, y as usize, size as usize);
+
+        let entry_address = control.screen_base + (y / 8) * (size / 8) + x / 8;
+        let tile = *self.vram.get(entry_address)? as usize;
+        self.tile_pixel_8bpp(control.char_base, tile, x % 8, y % 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::affine::affine_register;
+    use crate::ppu::{Ppu, SCANLINE_CYCLES, TOTAL_LINES, write_register};
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_BG0CNT, REG_DISPCNT};
+
+    #[test]
+    fn test_affine_backgrounds() {
+        let mut ppu = Ppu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        ppu.palette_ram[2..4].copy_from_slice(&0x001Fu16.to_le_bytes());
+        ppu.palette_ram[4..6].copy_from_slice(&0x03E0u16.to_le_bytes());
+
+        // 8bpp tile 1 with color 1 in its top row and color 2 in the rest. The
+        // 128x128 map at screen block 2 uses it for its first tile only
+        ppu.vram[64..72].fill(0x01);
+        ppu.vram[72..128].fill(0x02);
+        ppu.vram[0x1000] = 0x01;
+
+        // Mode 1 with BG2 scaled 2x horizontally and starting at (-1, 0)
+        write_register(&mut io_registers, REG_DISPCNT, 0x0401);
+        write_register(&mut io_registers, REG_BG0CNT + 4, 0x0200);
+        write_register(&mut io_registers, affine_register(0, 0x0), 0x0080);
+        write_register(&mut io_registers, affine_register(0, 0x6), 0x0100);
+        write_register(&mut io_registers, affine_register(0, 0x8), 0xFF00);
+        write_register(&mut io_registers, affine_register(0, 0xA), 0x0FFF);
+        ppu.latch_reference_point(0, &io_registers);
+
+        // Transparent overflow shows the backdrop
+        ppu.render_line(0, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(0, 0), 0x0000);
+        assert_eq!(ppu.frame_buffer.pixel(1, 0), 0x0000);
+        assert_eq!(ppu.frame_buffer.pixel(2, 0), 0x001F);
+        assert_eq!(ppu.frame_buffer.pixel(17, 0), 0x001F);
+        assert_eq!(ppu.frame_buffer.pixel(18, 0), 0x0000);
+
+        // The reference point moves down by PD each line
+        ppu.advance_reference_points(&io_registers);
+        ppu.render_line(1, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(2, 1), 0x03E0);
+
+        // Past the right edge of the map, which wraps around to the first tile
+        write_register(&mut io_registers, affine_register(0, 0x8), 0x8000);
+        write_register(&mut io_registers, affine_register(0, 0xA), 0x0000);
+        ppu.latch_reference_point(0, &io_registers);
+        ppu.render_line(0, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(0, 0), 0x0000);
+        write_register(&mut io_registers, REG_BG0CNT + 4, 0x2200);
+        ppu.render_line(0, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(0, 0), 0x001F);
+    }
+
+    #[test]
+    fn test_reference_point_writes() {
+        let mut ppu = Ppu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        write_register(&mut io_registers, affine_register(0, 0x2), 0x0010);
+        write_register(&mut io_registers, affine_register(0, 0x6), 0x0100);
+        write_register(&mut io_registers, affine_register(0, 0x8), 0x0200);
+
+        // Latched at VBlank and moved by PB and PD after each visible line
+        ppu.tick(SCANLINE_CYCLES * TOTAL_LINES as u32, &mut io_registers);
+        ppu.tick(SCANLINE_CYCLES * 3, &mut io_registers);
+        assert_eq!(ppu.reference_points[0].x, 0x0230);
+        assert_eq!(ppu.reference_points[0].y, 0x0300);
+
+        // Writing BG2X mid-frame reloads X while Y keeps moving
+        write_register(&mut io_registers, affine_register(0, 0x8), 0x1000);
+        ppu.latch_reference_coordinate(0, false, &io_registers);
+        ppu.tick(SCANLINE_CYCLES, &mut io_registers);
+        assert_eq!(ppu.reference_points[0].x, 0x1010);
+        assert_eq!(ppu.reference_points[0].y, 0x0400);
+
+        // Until the next VBlank, which reloads both
+        ppu.tick(
+            SCANLINE_CYCLES * (TOTAL_LINES as u32 - 4),
+            &mut io_registers,
+        );
+        assert_eq!(ppu.reference_points[0].x, 0x1000);
+        assert_eq!(ppu.reference_points[0].y, 0x0000);
+    }
+}
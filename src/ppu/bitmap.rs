@@ -0,0 +1,101 @@
+use crate::ppu::{Layer, Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Modes 4 and 5 have two frames. DISPCNT bit 4 selects the displayed one
+const DISPCNT_FRAME_SELECT: u16 = 1 << 4;
+const SECOND_FRAME_OFFSET: usize = 0xA000;
+
+/// Mode 5 trades resolution for a second 15-bit frame
+const MODE5_WIDTH: usize = 160;
+const MODE5_HEIGHT: usize = 128;
+
+impl Ppu {
+    /// Render a line of the bitmap in modes 3-5, which is drawn as the affine
+    /// BG2
+    pub(super) fn render_bitmap_line(
+        &self,
+        dispcnt: u16,
+        bgcnt: u16,
+        io_registers: &[u8],
+        layer: &mut Layer,
+    ) {
+        let coordinates = self.affine_line(bgcnt, 0, io_registers);
+        for (pixel, (x, y)) in layer.iter_mut().zip(coordinates) {
+            *pixel = self.bitmap_pixel(dispcnt, x, y);
+        }
+    }
+
+    /// The color at (`x`, `y`) of the bitmap. `None` outside of it
+    pub(super) fn bitmap_pixel(&self, dispcnt: u16, x: i32, y: i32) -> Option<u16> {
+        let frame = if dispcnt & DISPCNT_FRAME_SELECT != 0 {
+            SECOND_FRAME_OFFSET
+        } else {
+            0
+        };
+
+        let (width, height) = match dispcnt & 0b111 {
+            5 => (MODE5_WIDTH, MODE5_HEIGHT),
+            _ => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        };
+        if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+            return None;
+        }
+        let pixel = y as usize * width + x as usize;
+
+        match dispcnt & 0b111 {
+            3 => Some(self.vram_half_word(pixel * 2) & 0x7FFF),
+            // Palette index 0 is transparent
+            4 => match self.vram[frame + pixel] {
+                0 => None,
+                index => Some(self.palette_color(index as usize)),
+            },
+            _ => Some(self.vram_half_word(frame + pixel * 2) & 0x7FFF),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::{Ppu, write_register};
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_BG2PA, REG_DISPCNT};
+
+    /// Render `line` with the identity transformation
+    fn render(ppu: &mut Ppu, dispcnt: u16, line: usize) {
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        write_register(&mut io_registers, REG_DISPCNT, dispcnt);
+        write_register(&mut io_registers, REG_BG2PA, 0x0100);
+        write_register(&mut io_registers, REG_BG2PA + 6, 0x0100);
+        write_register(&mut io_registers, REG_BG2PA + 0xC, (line as u16) << 8);
+        ppu.latch_reference_point(0, &io_registers);
+        ppu.render_line(line, &io_registers);
+    }
+
+    #[test]
+    fn test_bitmap_modes() {
+        let mut ppu = Ppu::new();
+        ppu.palette_ram[0..2].copy_from_slice(&0x1111u16.to_le_bytes());
+        ppu.palette_ram[2..4].copy_from_slice(&0x2222u16.to_le_bytes());
+
+        // Mode 3: Direct color
+        ppu.vram[(240 + 5) * 2..(240 + 5) * 2 + 2].copy_from_slice(&0x7C1Fu16.to_le_bytes());
+        render(&mut ppu, 0x0403, 1);
+        assert_eq!(ppu.frame_buffer.pixel(5, 1), 0x7C1F);
+
+        // Mode 4: Paletted with index 0 showing the backdrop
+        ppu.vram[0xA000 + 240 + 7] = 0x01;
+        render(&mut ppu, 0x0414, 1);
+        assert_eq!(ppu.frame_buffer.pixel(7, 1), 0x2222);
+        assert_eq!(ppu.frame_buffer.pixel(8, 1), 0x1111);
+
+        // Mode 5: Small frame and the backdrop outside of it
+        ppu.vram[0xA000 + 160 * 2..0xA000 + 160 * 2 + 2].copy_from_slice(&0x03E0u16.to_le_bytes());
+        render(&mut ppu, 0x0415, 1);
+        assert_eq!(ppu.frame_buffer.pixel(0, 1), 0x03E0);
+        assert_eq!(ppu.frame_buffer.pixel(200, 1), 0x1111);
+
+        // BG2 disabled and forced blank
+        render(&mut ppu, 0x0003, 1);
+        assert_eq!(ppu.frame_buffer.pixel(5, 1), 0x1111);
+        render(&mut ppu, 0x0483, 1);
+        assert_eq!(ppu.frame_buffer.pixel(5, 1), 0x7FFF);
+    }
+}
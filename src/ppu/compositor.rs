@@ -0,0 +1,232 @@
+use crate::ppu::sprites::{ObjLayer, ObjPixel};
+use crate::ppu::window::{WINDOW_EFFECTS, WINDOW_OBJ};
+use crate::ppu::{Layer, Ppu, SCREEN_WIDTH, read_register};
+use crate::system_bus::{REG_BLDALPHA, REG_BLDCNT, REG_BLDY};
+
+/// Layer numbers as used by the BLDCNT target bits, after BG0-3
+const LAYER_OBJ: usize = 4;
+const LAYER_BACKDROP: usize = 5;
+
+/// Bits 0-5 select the first target layers and bits 8-13 the second
+const BLDCNT_SECOND_TARGET: usize = 8;
+
+/// A rendered background line along with what decides its place in the
+/// layer order
+#[derive(Copy, Clone)]
+pub struct Background {
+    pub index: usize,
+    /// 0 is drawn on top
+    pub priority: u16,
+    pub layer: Layer,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Pixel {
+    layer: usize,
+    color: u16,
+    semi_transparent: bool,
+}
+
+impl From<ObjPixel> for Pixel {
+    fn from(pixel: ObjPixel) -> Self {
+        Self {
+            layer: LAYER_OBJ,
+            color: pixel.color,
+            semi_transparent: pixel.semi_transparent,
+        }
+    }
+}
+
+/// The two topmost visible pixels at a position, which are the ones blended
+#[derive(Default)]
+struct TopPixels {
+    pixels: [Option<Pixel>; 2],
+}
+
+impl TopPixels {
+    fn push(&mut self, pixel: Pixel) {
+        if let Some(slot) = self.pixels.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(pixel);
+        }
+    }
+
+    fn full(&self) -> bool {
+        self.pixels[1].is_some()
+    }
+}
+
+/// The color special effects of BLDCNT, BLDALPHA and BLDY
+#[derive(Debug, Copy, Clone)]
+struct Blend {
+    control: u16,
+    eva: u16,
+    evb: u16,
+    evy: u16,
+}
+
+impl Blend {
+    fn new(io_registers: &[u8]) -> Self {
+        let alpha = read_register(io_registers, REG_BLDALPHA);
+        // Coefficients above 16 act as 16
+        Self {
+            control: read_register(io_registers, REG_BLDCNT),
+            eva: (alpha & 0x1F).min(16),
+            evb: ((alpha >> 8) & 0x1F).min(16),
+            evy: (read_register(io_registers, REG_BLDY) & 0x1F).min(16),
+        }
+    }
+
+    fn first_target(&self, layer: usize) -> bool {
+        self.control & (1 << layer) != 0
+    }
+
+    fn second_target(&self, layer: usize) -> bool {
+        self.control & (1 << (BLDCNT_SECOND_TARGET + layer)) != 0
+    }
+
+    fn apply(&self, top: Pixel, below: Option<Pixel>) -> u16 {
+        let below = below.filter(|below| self.second_target(below.layer));
+
+        // Semi-transparent sprites are blended whatever the mode is, as long
+        // as there is a second target below them
+        if top.semi_transparent
+            && let Some(below) = below
+        {
+            return self.alpha(top.color, below.color);
+        }
+        if !self.first_target(top.layer) {
+            return top.color;
+        }
+
+        match (self.control >> 6) & 0b11 {
+            1 => below.map_or(top.color, |below| self.alpha(top.color, below.color)),
+            2 => map_channels(top.color, |channel| {
+                channel + (((31 - channel) * self.evy) >> 4)
+            }),
+            3 => map_channels(top.color, |channel| channel - ((channel * self.evy) >> 4)),
+            _ => top.color,
+        }
+    }
+
+    fn alpha(&self, top: u16, below: u16) -> u16 {
+        let channel = |color: u16, shift: u16| (color >> shift) & 0x1F;
+        [0, 5, 10].into_iter().fold(0, |color, shift| {
+            let blended = (channel(top, shift) * self.eva + channel(below, shift) * self.evb) >> 4;
+            color | (blended.min(31) << shift)
+        })
+    }
+}
+
+/// Apply `f` to each 5-bit channel of `color`
+fn map_channels(color: u16, f: impl Fn(u16) -> u16) -> u16 {
+    [0, 5, 10].into_iter().fold(0, |result, shift| {
+        result | (f((color >> shift) & 0x1F) << shift)
+    })
+}
+
+impl Ppu {
+    /// Merge the background and sprite lines into the final colors. Lower
+    /// priority values and then lower BG numbers are drawn on top. Sprites
+    /// are drawn on top of BGs with the same priority. `windows` hides the
+    /// layers outside of their windows and decides where effects apply
+    pub(super) fn compose(
+        &self,
+        backgrounds: &[Option<Background>],
+        objects: &ObjLayer,
+        windows: &[u8; SCREEN_WIDTH],
+        io_registers: &[u8],
+    ) -> [u16; SCREEN_WIDTH] {
+        let mut order: Vec<&Background> = backgrounds.iter().flatten().collect();
+        order.sort_by_key(|background| (background.priority, background.index));
+
+        let blend = Blend::new(io_registers);
+        let backdrop = Pixel {
+            layer: LAYER_BACKDROP,
+            color: self.palette_color(0),
+            semi_transparent: false,
+        };
+
+        std::array::from_fn(|x| {
+            let mut top = TopPixels::default();
+            let mut object = objects.pixels[x].filter(|_| windows[x] & WINDOW_OBJ != 0);
+            for background in &order {
+                if top.full() {
+                    break;
+                }
+                let Some(color) = background.layer[x] else {
+                    continue;
+                };
+                if windows[x] & (1 << background.index) == 0 {
+                    continue;
+                }
+
+                if let Some(pixel) = object.take_if(|pixel| pixel.priority <= background.priority) {
+                    top.push(pixel.into());
+                }
+                top.push(Pixel {
+                    layer: background.index,
+                    color,
+                    semi_transparent: false,
+                });
+            }
+            if let Some(pixel) = object {
+                top.push(pixel.into());
+            }
+            top.push(backdrop);
+
+            let [Some(first), second] = top.pixels else {
+                unreachable!("the backdrop is always visible");
+            };
+            if windows[x] & WINDOW_EFFECTS != 0 {
+                blend.apply(first, second)
+            } else {
+                first.color
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::compositor::{Blend, Pixel};
+
+    fn pixel(layer: usize, color: u16, semi_transparent: bool) -> Pixel {
+        Pixel {
+            layer,
+            color,
+            semi_transparent,
+        }
+    }
+
+    #[test]
+    fn test_blending() {
+        let mut blend = Blend {
+            control: 0x0241,
+            eva: 8,
+            evb: 8,
+            evy: 8,
+        };
+        let top = pixel(0, 0x001F, false);
+        let below = Some(pixel(1, 0x7C00, false));
+
+        // Alpha blending needs a first target on top of a second target
+        assert_eq!(blend.apply(top, below), 0x3C0F);
+        assert_eq!(blend.apply(pixel(2, 0x001F, false), below), 0x001F);
+        assert_eq!(blend.apply(top, Some(pixel(2, 0x7C00, false))), 0x001F);
+        // The sum saturates
+        blend.evb = 16;
+        assert_eq!(blend.apply(pixel(0, 0x7FFF, false), below), 0x7DEF);
+
+        // Brightness changes apply to the first target alone
+        blend.control = 0x0081;
+        assert_eq!(blend.apply(pixel(0, 0x0010, false), None), 0x3DF7);
+        blend.control = 0x00C1;
+        assert_eq!(blend.apply(pixel(0, 0x0010, false), None), 0x0008);
+
+        // Semi-transparent sprites are blended even when not a first target
+        blend.control = 0x02C0;
+        blend.evb = 8;
+        assert_eq!(blend.apply(pixel(4, 0x001F, true), below), 0x3C0F);
+        assert_eq!(blend.apply(pixel(4, 0x001F, true), None), 0x001F);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::interrupts::Interrupt;
+use crate::ppu::affine::ReferencePoint;
+use crate::ppu::compositor::Background;
+use crate::ppu::mosaic::{BGCNT_MOSAIC, MosaicSize, VerticalMosaic, horizontal_mosaic};
+use crate::ppu::sprites::ObjLayer;
+use crate::ppu::window::window_line;
+use crate::system_bus::{
+    OAM_SIZE, PALETTE_RAM_SIZE, REG_BG0CNT, REG_BG0HOFS, REG_DISPCNT, REG_DISPSTAT, REG_VCOUNT,
+    VRAM_SIZE,
+};
+
+pub mod affine;
+pub mod bitmap;
+pub mod compositor;
+pub mod mosaic;
+pub mod sprites;
+pub mod text;
+pub mod window;
+
+pub const SCREEN_WIDTH: usize = 240;
+pub const SCREEN_HEIGHT: usize = 160;
+
+const DISPCNT_FORCED_BLANK: u16 = 1 << 7;
+/// Bits 8-11 enable BG0-3
+const DISPCNT_BG_ENABLE: u16 = 1 << 8;
+const DISPCNT_OBJ_ENABLE: u16 = 1 << 12;
+
+/// A scanline of a single layer. `None` is a transparent pixel
+pub type Layer = [Option<u16>; SCREEN_WIDTH];
+
+/// The rendered screen as 15-bit BGR colors, the native format of the GBA
+#[derive(Clone)]
+pub struct FrameBuffer {
+    pixels: Box<[u16; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self {
+            pixels: Box::new([0x0000; SCREEN_WIDTH * SCREEN_HEIGHT]),
+        }
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        self.pixels.as_slice()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * SCREEN_WIDTH + x]
+    }
+
+    fn line_mut(&mut self, y: usize) -> &mut [u16] {
+        &mut self.pixels[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH]
+    }
+
+    /// The screen as 8-bit RGBA, e.g. for uploading to a texture
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|color| rgba8(*color)).collect()
+    }
+}
+
+/// Expand a 15-bit BGR color to 8-bit RGBA
+pub fn rgba8(color: u16) -> [u8; 4] {
+    let expand = |channel: u16| {
+        let channel = (channel & 0x1F) as u8;
+        (channel << 3) | (channel >> 2)
+    };
+    [expand(color), expand(color >> 5), expand(color >> 10), 0xFF]
+}
+
+/// Each dot takes 4 cycles. The HBlank flag is set 46 cycles after the last
+/// visible dot
+const HDRAW_CYCLES: u32 = 1006;
+const SCANLINE_CYCLES: u32 = 1232;
+const TOTAL_LINES: u16 = 228;
+/// The VBlank flag is cleared one line before the end of the frame
+const LAST_VBLANK_LINE: u16 = TOTAL_LINES - 1;
+
+const DISPSTAT_VBLANK: u16 = 1 << 0;
+const DISPSTAT_HBLANK: u16 = 1 << 1;
+const DISPSTAT_VCOUNT: u16 = 1 << 2;
+const DISPSTAT_VBLANK_IRQ: u16 = 1 << 3;
+const DISPSTAT_HBLANK_IRQ: u16 = 1 << 4;
+const DISPSTAT_VCOUNT_IRQ: u16 = 1 << 5;
+/// The status bits of DISPSTAT which cannot be written
+pub const DISPSTAT_READ_ONLY: u16 = DISPSTAT_VBLANK | DISPSTAT_HBLANK | DISPSTAT_VCOUNT;
+
+/// What happened during a `Ppu::tick`
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PpuEvents {
+    /// Interrupts to request. Already filtered by the DISPSTAT enable bits
+    pub interrupts: u16,
+    /// HBlank started on a visible line
+    pub hblank: bool,
+    /// VBlank started
+    pub vblank: bool,
+}
+
+pub struct Ppu {
+    pub palette_ram: [u8; PALETTE_RAM_SIZE],
+    pub vram: Box<[u8; VRAM_SIZE]>,
+    pub oam: [u8; OAM_SIZE],
+    pub frame_buffer: FrameBuffer,
+
+    /// The internal reference points of BG2 and BG3
+    reference_points: [ReferencePoint; 2],
+    /// The reference points at the start of the current vertical mosaic block
+    mosaic_reference_points: [ReferencePoint; 2],
+    bg_mosaic: VerticalMosaic,
+    obj_mosaic: VerticalMosaic,
+    /// Cycles into the current scanline
+    line_cycle: u32,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
+            palette_ram: [0x00; PALETTE_RAM_SIZE],
+            vram: Box::new([0x00; VRAM_SIZE]),
+            oam: [0x00; OAM_SIZE],
+            frame_buffer: FrameBuffer::new(),
+            reference_points: [ReferencePoint::default(); 2],
+            mosaic_reference_points: [ReferencePoint::default(); 2],
+            bg_mosaic: VerticalMosaic::default(),
+            obj_mosaic: VerticalMosaic::default(),
+            line_cycle: 0,
+        }
+    }
+
+    /// Cycles until HBlank starts or the next line does
+    pub fn cycles_to_event(&self) -> u32 {
+        if self.line_cycle < HDRAW_CYCLES {
+            HDRAW_CYCLES - self.line_cycle
+        } else {
+            SCANLINE_CYCLES - self.line_cycle
+        }
+    }
+
+    /// Advance by `cycles` and update VCOUNT and DISPSTAT in `io_registers`.
+    /// Events of the same kind are merged, so callers that need each one
+    /// should tick at most `cycles_to_event` at a time
+    pub fn tick(&mut self, cycles: u32, io_registers: &mut [u8]) -> PpuEvents {
+        let mut events = PpuEvents::default();
+
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let step = remaining.min(self.cycles_to_event());
+            self.line_cycle += step;
+            remaining -= step;
+
+            if self.line_cycle == HDRAW_CYCLES {
+                self.start_hblank(io_registers, &mut events);
+            } else if self.line_cycle == SCANLINE_CYCLES {
+                self.line_cycle = 0;
+                self.next_line(io_registers, &mut events);
+            }
+        }
+
+        events
+    }
+
+    fn start_hblank(&mut self, io_registers: &mut [u8], events: &mut PpuEvents) {
+        let dispstat = read_register(io_registers, REG_DISPSTAT) | DISPSTAT_HBLANK;
+        write_register(io_registers, REG_DISPSTAT, dispstat);
+
+        if dispstat & DISPSTAT_HBLANK_IRQ != 0 {
+            events.interrupts |= Interrupt::HBlank.mask();
+        }
+        // The line is drawn during HDraw. Rendering it at once when HDraw ends
+        // lets writes made during the line take effect on it. HBlank DMAs only
+        // run on visible lines
+        let line = read_register(io_registers, REG_VCOUNT) as usize;
+        if line < SCREEN_HEIGHT {
+            self.render_line(line, io_registers);
+            self.advance_reference_points(io_registers);
+            self.advance_mosaic(line, io_registers);
+            events.hblank = true;
+        }
+    }
+
+    fn render_line(&mut self, line: usize, io_registers: &[u8]) {
+        let dispcnt = read_register(io_registers, REG_DISPCNT);
+        if dispcnt & DISPCNT_FORCED_BLANK != 0 {
+            self.frame_buffer.line_mut(line).fill(0x7FFF);
+            return;
+        }
+
+        let mosaic = MosaicSize::new(io_registers);
+        let mut backgrounds: [Option<Background>; 4] = [None; 4];
+        for (index, background) in backgrounds.iter_mut().enumerate() {
+            if dispcnt & (DISPCNT_BG_ENABLE << index) == 0 {
+                continue;
+            }
+
+            let bgcnt = read_register(io_registers, REG_BG0CNT + index * 2);
+            let mosaic_enabled = bgcnt & BGCNT_MOSAIC != 0;
+            let mut layer = [None; SCREEN_WIDTH];
+            match (dispcnt & 0b111, index) {
+                (0, _) | (1, 0 | 1) => {
+                    let hofs = read_register(io_registers, REG_BG0HOFS + index * 4);
+                    let vofs = read_register(io_registers, REG_BG0HOFS + index * 4 + 2);
+                    let line = if mosaic_enabled {
+                        self.bg_mosaic.line
+                    } else {
+                        line
+                    };
+                    self.render_text_line(bgcnt, hofs, vofs, line, &mut layer);
+                }
+                (1, 2) | (2, 2 | 3) => {
+                    self.render_affine_line(bgcnt, index - 2, io_registers, &mut layer)
+                }
+                (3..=5, 2) => self.render_bitmap_line(dispcnt, bgcnt, io_registers, &mut layer),
+                _ => continue,
+            }
+            if mosaic_enabled {
+                horizontal_mosaic(&mut layer, mosaic.bg_width);
+            }
+            *background = Some(Background {
+                index,
+                priority: bgcnt & 0b11,
+                layer,
+            });
+        }
+
+        let objects = if dispcnt & DISPCNT_OBJ_ENABLE != 0 {
+            self.render_sprite_line(dispcnt, line, &mosaic)
+        } else {
+            ObjLayer::default()
+        };
+
+        let windows = window_line(dispcnt, io_registers, line, &objects);
+
+        let output = self.compose(&backgrounds, &objects, &windows, io_registers);
+        self.frame_buffer.line_mut(line).copy_from_slice(&output);
+    }
+
+    fn palette_color(&self, index: usize) -> u16 {
+        u16::from_le_bytes([self.palette_ram[index * 2], self.palette_ram[index * 2 + 1]]) & 0x7FFF
+    }
+
+    fn vram_half_word(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.vram[offset], self.vram[offset + 1]])
+    }
+
+    fn next_line(&mut self, io_registers: &mut [u8], events: &mut PpuEvents) {
+        let vcount = (read_register(io_registers, REG_VCOUNT) + 1) % TOTAL_LINES;
+        write_register(io_registers, REG_VCOUNT, vcount);
+
+        let mut dispstat = read_register(io_registers, REG_DISPSTAT) & !DISPSTAT_HBLANK;
+        if vcount as usize == SCREEN_HEIGHT {
+            dispstat |= DISPSTAT_VBLANK;
+            events.vblank = true;
+            self.latch_reference_points(io_registers);
+            self.reset_mosaic();
+            if dispstat & DISPSTAT_VBLANK_IRQ != 0 {
+                events.interrupts |= Interrupt::VBlank.mask();
+            }
+        } else if vcount == LAST_VBLANK_LINE {
+            dispstat &= !DISPSTAT_VBLANK;
+        }
+
+        // The line to compare against is in the upper byte
+        if vcount == dispstat >> 8 {
+            dispstat |= DISPSTAT_VCOUNT;
+            if dispstat & DISPSTAT_VCOUNT_IRQ != 0 {
+                events.interrupts |= Interrupt::VCount.mask();
+            }
+        } else {
+            dispstat &= !DISPSTAT_VCOUNT;
+        }
+        write_register(io_registers, REG_DISPSTAT, dispstat);
+    }
+}
+
+fn read_register(io_registers: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([io_registers[offset], io_registers[offset + 1]])
+}
+
+fn write_register(io_registers: &mut [u8], offset: usize, value: u16) {
+    io_registers[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interrupts::Interrupt;
+    use crate::ppu::{HDRAW_CYCLES, Ppu, SCANLINE_CYCLES, read_register, write_register};
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_DISPSTAT, REG_VCOUNT};
+
+    #[test]
+    fn test_ppu_timing() {
+        let mut ppu = Ppu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        // All interrupts enabled and a VCount match on line 1
+        write_register(&mut io_registers, REG_DISPSTAT, 0x0138);
+
+        let events = ppu.tick(HDRAW_CYCLES, &mut io_registers);
+        assert!(events.hblank);
+        assert_eq!(events.interrupts, Interrupt::HBlank.mask());
+        assert_eq!(read_register(&io_registers, REG_DISPSTAT) & 0b111, 0b010);
+
+        let events = ppu.tick(SCANLINE_CYCLES - HDRAW_CYCLES, &mut io_registers);
+        assert_eq!(events.interrupts, Interrupt::VCount.mask());
+        assert_eq!(read_register(&io_registers, REG_VCOUNT), 1);
+        assert_eq!(read_register(&io_registers, REG_DISPSTAT) & 0b111, 0b100);
+
+        let events = ppu.tick(SCANLINE_CYCLES * 159, &mut io_registers);
+        assert!(events.vblank);
+        assert_ne!(events.interrupts & Interrupt::VBlank.mask(), 0);
+        assert_eq!(read_register(&io_registers, REG_VCOUNT), 160);
+        assert_eq!(read_register(&io_registers, REG_DISPSTAT) & 0b111, 0b001);
+
+        // No HBlank DMA during VBlank
+        let events = ppu.tick(HDRAW_CYCLES, &mut io_registers);
+        assert!(!events.hblank);
+
+        // The VBlank flag is cleared on the last line and the frame wraps
+        ppu.tick(SCANLINE_CYCLES * 67 - HDRAW_CYCLES, &mut io_registers);
+        assert_eq!(read_register(&io_registers, REG_VCOUNT), 227);
+        assert_eq!(read_register(&io_registers, REG_DISPSTAT) & 0b111, 0b000);
+        ppu.tick(SCANLINE_CYCLES, &mut io_registers);
+        assert_eq!(read_register(&io_registers, REG_VCOUNT), 0);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::ppu::{Ppu, read_register};
+use crate::system_bus::REG_MOSAIC;
+
+pub const BGCNT_MOSAIC: u16 = 1 << 6;
+
+/// The block sizes in MOSAIC, in pixels
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MosaicSize {
+    pub bg_width: usize,
+    pub bg_height: usize,
+    pub obj_width: usize,
+    pub obj_height: usize,
+}
+
+impl MosaicSize {
+    pub fn new(io_registers: &[u8]) -> Self {
+        let mosaic = read_register(io_registers, REG_MOSAIC) as usize;
+        Self {
+            bg_width: (mosaic & 0xF) + 1,
+            bg_height: ((mosaic >> 4) & 0xF) + 1,
+            obj_width: ((mosaic >> 8) & 0xF) + 1,
+            obj_height: ((mosaic >> 12) & 0xF) + 1,
+        }
+    }
+}
+
+/// The vertical mosaic counter. It only restarts at the end of a block or at
+/// VBlank, so changing the size mid-frame does not realign the blocks
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct VerticalMosaic {
+    /// The line the current block started on, which all of its lines show
+    pub line: usize,
+    counter: usize,
+}
+
+impl VerticalMosaic {
+    /// Count `line` as drawn. Returns whether the next line starts a block
+    fn next_line(&mut self, line: usize, height: usize) -> bool {
+        self.counter += 1;
+        if self.counter >= height {
+            self.counter = 0;
+            self.line = line + 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Repeat the first pixel of each block of `width` pixels across the block
+pub(super) fn horizontal_mosaic<T: Copy>(pixels: &mut [T], width: usize) {
+    for x in 0..pixels.len() {
+        pixels[x] = pixels[x - x % width];
+    }
+}
+
+impl Ppu {
+    pub(super) fn reset_mosaic(&mut self) {
+        self.bg_mosaic = VerticalMosaic::default();
+        self.obj_mosaic = VerticalMosaic::default();
+        self.mosaic_reference_points = self.reference_points;
+    }
+
+    /// Move the vertical mosaic counters past `line`. Affine BGs keep the
+    /// reference point of the first line of a block
+    pub(super) fn advance_mosaic(&mut self, line: usize, io_registers: &[u8]) {
+        let size = MosaicSize::new(io_registers);
+        if self.bg_mosaic.next_line(line, size.bg_height) {
+            self.mosaic_reference_points = self.reference_points;
+        }
+        self.obj_mosaic.next_line(line, size.obj_height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::mosaic::{VerticalMosaic, horizontal_mosaic};
+    use crate::ppu::{Ppu, write_register};
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_BG0CNT, REG_BG2PA, REG_DISPCNT, REG_MOSAIC};
+
+    #[test]
+    fn test_vertical_mosaic() {
+        let mut mosaic = VerticalMosaic::default();
+        let lines: Vec<usize> = (0..8)
+            .map(|line| {
+                let block = mosaic.line;
+                // The size shrinks in the middle of the second block
+                mosaic.next_line(line, if line < 4 { 3 } else { 2 });
+                block
+            })
+            .collect();
+        assert_eq!(lines, [0, 0, 0, 3, 3, 5, 5, 7]);
+    }
+
+    #[test]
+    fn test_mosaic() {
+        let mut pixels = [0, 1, 2, 3, 4, 5, 6];
+        horizontal_mosaic(&mut pixels, 3);
+        assert_eq!(pixels, [0, 0, 0, 3, 3, 3, 6]);
+
+        // Mode 3 with BG2 mosaic in 4x2 blocks
+        let mut ppu = Ppu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        write_register(&mut io_registers, REG_DISPCNT, 0x0403);
+        write_register(&mut io_registers, REG_BG0CNT + 4, 0x0040);
+        write_register(&mut io_registers, REG_MOSAIC, 0x0013);
+        write_register(&mut io_registers, REG_BG2PA, 0x0100);
+        write_register(&mut io_registers, REG_BG2PA + 6, 0x0100);
+        ppu.latch_reference_point(0, &io_registers);
+        ppu.reset_mosaic();
+        for (i, pixel) in ppu.vram.chunks_exact_mut(2).take(240 * 2).enumerate() {
+            pixel.copy_from_slice(&(i as u16).to_le_bytes());
+        }
+
+        for line in 0..2 {
+            ppu.render_line(line, &io_registers);
+            ppu.advance_reference_points(&io_registers);
+            ppu.advance_mosaic(line, &io_registers);
+        }
+        assert_eq!(ppu.frame_buffer.pixel(3, 0), 0);
+        assert_eq!(ppu.frame_buffer.pixel(5, 0), 4);
+        assert_eq!(ppu.frame_buffer.pixel(5, 1), 4);
+    }
+}
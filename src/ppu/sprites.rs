@@ -0,0 +1,394 @@
+use crate::ppu::mosaic::MosaicSize;
+use crate::ppu::{Ppu, SCREEN_WIDTH};
+
+/// OBJ tiles start after the BG part of VRAM
+const OBJ_VRAM_START: usize = 0x10000;
+/// In the bitmap modes the frame buffer covers the first half of OBJ VRAM
+const BITMAP_OBJ_FIRST_TILE: usize = 512;
+/// The OBJ colors follow the 256 BG colors
+const OBJ_PALETTE: usize = 256;
+const OBJ_COUNT: usize = 128;
+
+const DISPCNT_HBLANK_FREE: u16 = 1 << 5;
+const DISPCNT_OBJ_1D: u16 = 1 << 6;
+
+/// Cycles available for evaluating sprites on a line. Less of the line is
+/// available when OAM can be accessed during HBlank
+const LINE_CYCLES: u32 = 1210;
+const LINE_CYCLES_HBLANK_FREE: u32 = 954;
+
+const ATTR0_AFFINE: u16 = 1 << 8;
+/// Double size for affine sprites and disable for the others
+const ATTR0_DOUBLE_SIZE: u16 = 1 << 9;
+const ATTR0_MOSAIC: u16 = 1 << 12;
+const ATTR0_8BPP: u16 = 1 << 13;
+const ATTR1_HFLIP: u16 = 1 << 12;
+const ATTR1_VFLIP: u16 = 1 << 13;
+
+/// Width and height in pixels by shape and size
+const SPRITE_SIZES: [[(i32, i32); 4]; 3] = [
+    [(8, 8), (16, 16), (32, 32), (64, 64)],
+    [(16, 8), (32, 8), (32, 16), (64, 32)],
+    [(8, 16), (8, 32), (16, 32), (32, 64)],
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ObjMode {
+    Normal,
+    SemiTransparent,
+    Window,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ObjPixel {
+    pub color: u16,
+    /// 0 is drawn on top, compared with the BG priorities
+    pub priority: u16,
+    /// Blended with the layer below regardless of the blend mode
+    pub semi_transparent: bool,
+}
+
+/// The sprites of a line merged into a single layer
+#[derive(Debug, Copy, Clone)]
+pub struct ObjLayer {
+    pub pixels: [Option<ObjPixel>; SCREEN_WIDTH],
+    /// Opaque pixels of OBJ window sprites
+    pub window: [bool; SCREEN_WIDTH],
+}
+
+impl Default for ObjLayer {
+    fn default() -> Self {
+        Self {
+            pixels: [None; SCREEN_WIDTH],
+            window: [false; SCREEN_WIDTH],
+        }
+    }
+}
+
+/// The attributes of an OAM entry
+struct Sprite {
+    attr0: u16,
+    attr1: u16,
+    attr2: u16,
+}
+
+impl Sprite {
+    fn affine(&self) -> bool {
+        self.attr0 & ATTR0_AFFINE != 0
+    }
+
+    fn mosaic(&self) -> bool {
+        self.attr0 & ATTR0_MOSAIC != 0
+    }
+
+    fn mode(&self) -> Option<ObjMode> {
+        match (self.attr0 >> 10) & 0b11 {
+            0 => Some(ObjMode::Normal),
+            1 => Some(ObjMode::SemiTransparent),
+            2 => Some(ObjMode::Window),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> Option<(i32, i32)> {
+        let shape = (self.attr0 >> 14) as usize;
+        SPRITE_SIZES
+            .get(shape)
+            .map(|sizes| sizes[(self.attr1 >> 14) as usize])
+    }
+
+    /// The size of the area the sprite is drawn in
+    fn bounds(&self, (width, height): (i32, i32)) -> (i32, i32) {
+        if self.affine() && self.attr0 & ATTR0_DOUBLE_SIZE != 0 {
+            (width * 2, height * 2)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// The 9-bit X coordinate wraps around to the left of the screen
+    fn x(&self) -> i32 {
+        match (self.attr1 & 0x1FF) as i32 {
+            x if x >= SCREEN_WIDTH as i32 => x - 512,
+            x => x,
+        }
+    }
+}
+
+impl Ppu {
+    fn oam_half_word(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.oam[offset], self.oam[offset + 1]])
+    }
+
+    fn sprite(&self, index: usize) -> Sprite {
+        Sprite {
+            attr0: self.oam_half_word(index * 8),
+            attr1: self.oam_half_word(index * 8 + 2),
+            attr2: self.oam_half_word(index * 8 + 4),
+        }
+    }
+
+    /// PA, PB, PC and PD of `group` are stored in the fourth attribute of
+    /// four consecutive OAM entries
+    fn sprite_matrix(&self, group: usize) -> [i32; 4] {
+        std::array::from_fn(|i| self.oam_half_word(group * 32 + i * 8 + 6) as i16 as i32)
+    }
+
+    /// Render the sprites on `line` in OAM order until the cycle budget of
+    /// the line runs out
+    pub(super) fn render_sprite_line(
+        &self,
+        dispcnt: u16,
+        line: usize,
+        mosaic: &MosaicSize,
+    ) -> ObjLayer {
+        let mut layer = ObjLayer::default();
+        let mut cycles = if dispcnt & DISPCNT_HBLANK_FREE != 0 {
+            LINE_CYCLES_HBLANK_FREE
+        } else {
+            LINE_CYCLES
+        };
+
+        for index in 0..OBJ_COUNT {
+            let sprite = self.sprite(index);
+            if !sprite.affine() && sprite.attr0 & ATTR0_DOUBLE_SIZE != 0 {
+                continue;
+            }
+            // Mode 3 is prohibited
+            let Some(size) = sprite.size().filter(|_| sprite.mode().is_some()) else {
+                continue;
+            };
+            let (bounds_width, bounds_height) = sprite.bounds(size);
+
+            // The 8-bit Y coordinate wraps around to the top of the screen
+            let y = (line as i32 - (sprite.attr0 & 0xFF) as i32) & 0xFF;
+            if y >= bounds_height {
+                continue;
+            }
+
+            // Mosaic sprites show the line the vertical mosaic block started
+            // on, or their top line if the block started above them
+            let y = if sprite.mosaic() {
+                (y - (line - self.obj_mosaic.line) as i32).max(0)
+            } else {
+                y
+            };
+
+            let cost = if sprite.affine() {
+                10 + bounds_width as u32 * 2
+            } else {
+                bounds_width as u32
+            };
+            if cost > cycles {
+                break;
+            }
+            cycles -= cost;
+
+            let mosaic_width = if sprite.mosaic() { mosaic.obj_width } else { 1 };
+            self.render_sprite(dispcnt, &sprite, size, y, mosaic_width, &mut layer);
+        }
+
+        layer
+    }
+
+    fn render_sprite(
+        &self,
+        dispcnt: u16,
+        sprite: &Sprite,
+        (width, height): (i32, i32),
+        y: i32,
+        mosaic_width: usize,
+        layer: &mut ObjLayer,
+    ) {
+        let (bounds_width, bounds_height) = sprite.bounds((width, height));
+        let [pa, pb, pc, pd] = if sprite.affine() {
+            self.sprite_matrix(((sprite.attr1 >> 9) & 0x1F) as usize)
+        } else {
+            [0x100, 0, 0, 0x100]
+        };
+        let flip = |bit| !sprite.affine() && sprite.attr1 & bit != 0;
+        let (hflip, vflip) = (flip(ATTR1_HFLIP), flip(ATTR1_VFLIP));
+        let priority = (sprite.attr2 >> 10) & 0b11;
+
+        // Coordinates relative to the center of the sprite, which affine
+        // sprites are transformed around
+        let dy = y - bounds_height / 2;
+        let left = sprite.x();
+        for x in left.max(0)..(left + bounds_width).min(SCREEN_WIDTH as i32) {
+            // Horizontal mosaic blocks are aligned to the screen
+            let sample_x = (x - x % mosaic_width as i32).max(left);
+            let dx = sample_x - left - bounds_width / 2;
+
+            let texture_x = ((pa * dx + pb * dy) >> 8) + width / 2;
+            let texture_y = ((pc * dx + pd * dy) >> 8) + height / 2;
+            if !(0..width).contains(&texture_x) || !(0..height).contains(&texture_y) {
+                continue;
+            }
+            let texture_x = if hflip {
+                width - 1 - texture_x
+            } else {
+                texture_x
+            };
+            let texture_y = if vflip {
+                height - 1 - texture_y
+            } else {
+                texture_y
+            };
+            let Some(color) = self.sprite_pixel(dispcnt, sprite, width, texture_x, texture_y)
+            else {
+                continue;
+            };
+
+            let x = x as usize;
+            match sprite.mode() {
+                Some(ObjMode::Window) => layer.window[x] = true,
+                mode => {
+                    // Lower OAM entries win between sprites of equal priority
+                    if layer.pixels[x].is_none_or(|pixel| priority < pixel.priority) {
+                        layer.pixels[x] = Some(ObjPixel {
+                            color,
+                            priority,
+                            semi_transparent: mode == Some(ObjMode::SemiTransparent),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// The color of the pixel at (`x`, `y`) inside the sprite
+    fn sprite_pixel(
+        &self,
+        dispcnt: u16,
+        sprite: &Sprite,
+        width: i32,
+        x: i32,
+        y: i32,
+    ) -> Option<u16> {
+        let eight_bpp = sprite.attr0 & ATTR0_8BPP != 0;
+        let (x, y, width) = (x as usize, y as usize, width as usize);
+
+        // Tiles are counted in 32 byte units, so 8bpp tiles take up two
+        let tile_units = if eight_bpp { 2 } else { 1 };
+        let row_stride = if dispcnt & DISPCNT_OBJ_1D != 0 {
+            width / 8 * tile_units
+        } else {
+            32
+        };
+        let tile = (sprite.attr2 & 0x3FF) as usize + (y / 8) * row_stride + (x / 8) * tile_units;
+        if dispcnt & 0b111 >= 3 && tile < BITMAP_OBJ_FIRST_TILE {
+            return None;
+        }
+
+        let tile_address = OBJ_VRAM_START + (tile % 1024) * 32;
+        let (x, y) = (x % 8, y % 8);
+        if eight_bpp {
+            match self.vram[tile_address + y * 8 + x] {
+                0 => None,
+                index => Some(self.palette_color(OBJ_PALETTE + index as usize)),
+            }
+        } else {
+            let palette_bank = (sprite.attr2 >> 12) as usize;
+            match (self.vram[tile_address + y * 4 + x / 2] >> ((x & 1) * 4)) & 0xF {
+                0 => None,
+                index => Some(self.palette_color(OBJ_PALETTE + palette_bank * 16 + index as usize)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::mosaic::MosaicSize;
+    use crate::ppu::sprites::ObjPixel;
+    use crate::ppu::{Ppu, write_register};
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_MOSAIC};
+
+    fn set_sprite(ppu: &mut Ppu, index: usize, attributes: [u16; 3]) {
+        for (i, attribute) in attributes.iter().enumerate() {
+            let offset = index * 8 + i * 2;
+            ppu.oam[offset..offset + 2].copy_from_slice(&attribute.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut ppu = Ppu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        let mosaic = MosaicSize::new(&io_registers);
+        ppu.palette_ram[0x202..0x204].copy_from_slice(&0x001Fu16.to_le_bytes());
+        ppu.palette_ram[0x204..0x206].copy_from_slice(&0x03E0u16.to_le_bytes());
+        // Hide all the sprites
+        for index in 0..128 {
+            set_sprite(&mut ppu, index, [0x0200, 0x0000, 0x0000]);
+        }
+
+        // 4bpp tiles 1 and 2, color 1 on the first pixel of tile 1
+        ppu.vram[0x10020] = 0x01;
+        ppu.vram[0x10040..0x10060].fill(0x22);
+
+        // A 16x8 sprite at (10, 20) with priority 1 using tiles 1 and 2
+        set_sprite(&mut ppu, 0, [0x4014, 0x000A, 0x0401]);
+        let layer = ppu.render_sprite_line(0x0040, 20, &mosaic);
+        let pixel = |color| {
+            Some(ObjPixel {
+                color,
+                priority: 1,
+                semi_transparent: false,
+            })
+        };
+        assert_eq!(layer.pixels[9], None);
+        assert_eq!(layer.pixels[10], pixel(0x001F));
+        assert_eq!(layer.pixels[11], None);
+        assert_eq!(layer.pixels[18], pixel(0x03E0));
+        assert_eq!(layer.pixels[26], None);
+        assert!(ppu.render_sprite_line(0x0040, 28, &mosaic).pixels[10].is_none());
+
+        // Flipped horizontally
+        set_sprite(&mut ppu, 0, [0x4014, 0x100A, 0x0401]);
+        let layer = ppu.render_sprite_line(0x0040, 20, &mosaic);
+        assert_eq!(layer.pixels[25], pixel(0x001F));
+        assert_eq!(layer.pixels[10], pixel(0x03E0));
+
+        // Affine with double size and the identity matrix: drawn in the middle
+        // of its 32x16 area
+        let matrix = [0x0100u16, 0x0000, 0x0000, 0x0100];
+        for (i, parameter) in matrix.iter().enumerate() {
+            let offset = i * 8 + 6;
+            ppu.oam[offset..offset + 2].copy_from_slice(&parameter.to_le_bytes());
+        }
+        set_sprite(&mut ppu, 0, [0x4314, 0x000A, 0x0401]);
+        let layer = ppu.render_sprite_line(0x0040, 24, &mosaic);
+        assert_eq!(layer.pixels[18], pixel(0x001F));
+        assert_eq!(layer.pixels[26], pixel(0x03E0));
+
+        // Horizontal mosaic in blocks of 4 pixels aligned to the screen
+        set_sprite(&mut ppu, 0, [0x5014, 0x000A, 0x0401]);
+        write_register(&mut io_registers, REG_MOSAIC, 0x0300);
+        let layer = ppu.render_sprite_line(0x0040, 20, &MosaicSize::new(&io_registers));
+        assert_eq!(layer.pixels[11], pixel(0x001F));
+        assert_eq!(layer.pixels[12], None);
+
+        // OBJ window sprites only mark the window
+        set_sprite(&mut ppu, 0, [0x4814, 0x000A, 0x0401]);
+        let layer = ppu.render_sprite_line(0x0040, 20, &mosaic);
+        assert!(layer.pixels[10].is_none());
+        assert!(layer.window[10]);
+
+        // Sprites past the cycle budget are dropped. 64x64 sprites take 64
+        // cycles, so only 18 fit in a line. Sprites off the side of the
+        // screen still take their time
+        ppu.vram[0x10000..0x10800].fill(0x11);
+        for index in 0..18 {
+            set_sprite(&mut ppu, index, [0x0000, 0xC000 | 240, 0x0000]);
+        }
+        set_sprite(&mut ppu, 14, [0x0000, 0xC000, 0x0000]);
+        set_sprite(&mut ppu, 18, [0x0000, 0xC000 | 100, 0x0000]);
+        let layer = ppu.render_sprite_line(0x0040, 0, &mosaic);
+        assert!(layer.pixels[0].is_some());
+        assert!(layer.pixels[100].is_none());
+        // Fewer fit when HBlank is free
+        let layer = ppu.render_sprite_line(0x0060, 0, &mosaic);
+        assert!(layer.pixels[0].is_none());
+    }
+}
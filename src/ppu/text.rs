@@ -0,0 +1,181 @@
+use crate::ppu::{Layer, Ppu};
+
+/// Tile data can only be read from the BG part of VRAM
+const BG_VRAM_END: usize = 0x10000;
+const CHAR_BLOCK_SIZE: usize = 0x4000;
+const SCREEN_BLOCK_SIZE: usize = 0x800;
+
+const BGCNT_8BPP: u16 = 1 << 7;
+
+/// The layout of a BG control register which is shared by text and affine
+/// backgrounds
+pub(super) struct BackgroundControl {
+    pub char_base: usize,
+    pub screen_base: usize,
+    pub eight_bpp: bool,
+    pub size: u16,
+}
+
+impl BackgroundControl {
+    pub fn new(bgcnt: u16) -> Self {
+        Self {
+            char_base: ((bgcnt as usize >> 2) & 0b11) * CHAR_BLOCK_SIZE,
+            screen_base: ((bgcnt as usize >> 8) & 0x1F) * SCREEN_BLOCK_SIZE,
+            eight_bpp: bgcnt & BGCNT_8BPP != 0,
+            size: bgcnt >> 14,
+        }
+    }
+}
+
+impl Ppu {
+    pub(super) fn render_text_line(
+        &self,
+        bgcnt: u16,
+        hofs: u16,
+        vofs: u16,
+        line: usize,
+        layer: &mut Layer,
+    ) {
+        let control = BackgroundControl::new(bgcnt);
+        for (x, pixel) in layer.iter_mut().enumerate() {
+            *pixel = self.text_pixel(&control, x + hofs as usize, line + vofs as usize);
+        }
+    }
+
+    /// The color at (`x`, `y`) of the background map. The map wraps around
+    fn text_pixel(&self, control: &BackgroundControl, x: usize, y: usize) -> Option<u16> {
+        let (width, height) = match control.size {
+            0 => (256, 256),
+            1 => (512, 256),
+            2 => (256, 512),
+            _ => (512, 512),
+        };
+        let (x, y) = (x % width, y % height);
+
+        // The map is made of 32x32 tile screen blocks
+        let block = (x / 256) + (y / 256) * (width / 256);
+        let entry_address = control.screen_base
+            + block * SCREEN_BLOCK_SIZE
+            + (((y % 256) / 8) * 32 + (x % 256) / 8) * 2;
+        if entry_address >= BG_VRAM_END {
+            return None;
+        }
+        let entry = self.vram_half_word(entry_address) as usize;
+
+        let tile = entry & 0x3FF;
+        let tile_x = if entry & (1 << 10) != 0 {
+            7 - x % 8
+        } else {
+            x % 8
+        };
+        let tile_y = if entry & (1 << 11) != 0 {
+            7 - y % 8
+        } else {
+            y % 8
+        };
+        let palette_bank = entry >> 12;
+
+        if control.eight_bpp {
+            self.tile_pixel_8bpp(control.char_base, tile, tile_x, tile_y)
+        } else {
+            self.tile_pixel_4bpp(control.char_base, tile, tile_x, tile_y, palette_bank)
+        }
+    }
+
+    /// Palette index 0 is transparent in every bank
+    pub(super) fn tile_pixel_4bpp(
+        &self,
+        char_base: usize,
+        tile: usize,
+        x: usize,
+        y: usize,
+        palette_bank: usize,
+    ) -> Option<u16> {
+        let address = char_base + tile * 32 + y * 4 + x / 2;
+        if address >= BG_VRAM_END {
+            return None;
+        }
+
+        match (self.vram[address] >> ((x & 1) * 4)) & 0xF {
+            0 => None,
+            index => Some(self.palette_color(palette_bank * 16 + index as usize)),
+        }
+    }
+
+    pub(super) fn tile_pixel_8bpp(
+        &self,
+        char_base: usize,
+        tile: usize,
+        x: usize,
+        y: usize,
+    ) -> Option<u16> {
+        let address = char_base + tile * 64 + y * 8 + x;
+        if address >= BG_VRAM_END {
+            return None;
+        }
+
+        match self.vram[address] {
+            0 => None,
+            index => Some(self.palette_color(index as usize)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::{Ppu, write_register};
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_BG0CNT, REG_BG0HOFS, REG_DISPCNT};
+
+    #[test]
+    fn test_text_backgrounds() {
+        let mut ppu = Ppu::new();
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        for (index, color) in [0x0000u16, 0x001F, 0x03E0].iter().enumerate() {
+            ppu.palette_ram[index * 2..index * 2 + 2].copy_from_slice(&color.to_le_bytes());
+        }
+        // Bank 1, colors 1 and 2
+        ppu.palette_ram[0x22..0x24].copy_from_slice(&0x7C00u16.to_le_bytes());
+        ppu.palette_ram[0x24..0x26].copy_from_slice(&0x7FFFu16.to_le_bytes());
+
+        // 4bpp tile 1: the top-left pixel uses color 1, the rest of the first
+        // row color 2
+        ppu.vram[32] = 0x21;
+        ppu.vram[33..36].fill(0x22);
+        // BG0: tile 1 at the top-left of screen block 8
+        ppu.vram[0x4000..0x4002].copy_from_slice(&0x0001u16.to_le_bytes());
+        // BG1: tile 1 with palette bank 1 under the one of BG0 and flipped
+        // horizontally next to it at screen block 9
+        ppu.vram[0x4800..0x4802].copy_from_slice(&0x1001u16.to_le_bytes());
+        ppu.vram[0x4800 + 2..0x4800 + 4].copy_from_slice(&0x1401u16.to_le_bytes());
+
+        // BG0 in front by priority
+        write_register(&mut io_registers, REG_DISPCNT, 0x0300);
+        write_register(&mut io_registers, REG_BG0CNT, 0x0800);
+        write_register(&mut io_registers, REG_BG0CNT + 2, 0x0901);
+        ppu.render_line(0, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(0, 0), 0x001F);
+        assert_eq!(ppu.frame_buffer.pixel(1, 0), 0x03E0);
+        // BG1 shows through the transparent pixels of BG0
+        assert_eq!(ppu.frame_buffer.pixel(15, 0), 0x7C00);
+
+        // BG1 in front by priority
+        write_register(&mut io_registers, REG_BG0CNT, 0x0802);
+        ppu.render_line(0, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(0, 0), 0x7C00);
+        assert_eq!(ppu.frame_buffer.pixel(1, 0), 0x7FFF);
+        assert_eq!(ppu.frame_buffer.pixel(15, 0), 0x7C00);
+
+        // The lower background wins at the same priority
+        write_register(&mut io_registers, REG_BG0CNT, 0x0801);
+        ppu.render_line(0, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(0, 0), 0x001F);
+        assert_eq!(ppu.frame_buffer.pixel(1, 0), 0x03E0);
+
+        // Scrolling wraps around the 256x256 map
+        write_register(&mut io_registers, REG_BG0HOFS, 255);
+        write_register(&mut io_registers, REG_DISPCNT, 0x0100);
+        ppu.render_line(0, &io_registers);
+        assert_eq!(ppu.frame_buffer.pixel(1, 0), 0x001F);
+        assert_eq!(ppu.frame_buffer.pixel(0, 0), 0x0000);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::ppu::sprites::ObjLayer;
+use crate::ppu::{SCREEN_WIDTH, read_register};
+use crate::system_bus::{REG_WIN0H, REG_WIN0V, REG_WININ, REG_WINOUT};
+
+/// Bits 13-15 enable WIN0, WIN1 and the OBJ window
+const DISPCNT_WIN0: u16 = 1 << 13;
+const DISPCNT_WIN1: u16 = 1 << 14;
+const DISPCNT_OBJ_WINDOW: u16 = 1 << 15;
+
+/// Bits 0-3 of a window control enable BG0-3
+pub const WINDOW_OBJ: u8 = 1 << 4;
+pub const WINDOW_EFFECTS: u8 = 1 << 5;
+/// Everything is shown when no window is enabled
+const WINDOW_ALL: u8 = 0x3F;
+
+/// Whether `position` is in the window from `start` up to `end`. Windows with
+/// the start after the end wrap around the screen
+fn inside(position: u16, start: u16, end: u16) -> bool {
+    if start <= end {
+        (start..end).contains(&position)
+    } else {
+        position >= start || position < end
+    }
+}
+
+/// The layers and effects enabled for each pixel of `line`
+pub(super) fn window_line(
+    dispcnt: u16,
+    io_registers: &[u8],
+    line: usize,
+    objects: &ObjLayer,
+) -> [u8; SCREEN_WIDTH] {
+    if dispcnt & (DISPCNT_WIN0 | DISPCNT_WIN1 | DISPCNT_OBJ_WINDOW) == 0 {
+        return [WINDOW_ALL; SCREEN_WIDTH];
+    }
+
+    let [win0_control, win1_control] = read_register(io_registers, REG_WININ).to_le_bytes();
+    let [outside_control, obj_window_control] =
+        read_register(io_registers, REG_WINOUT).to_le_bytes();
+
+    // The right and bottom edges are in the low byte and are exclusive
+    let windows = [(DISPCNT_WIN0, win0_control), (DISPCNT_WIN1, win1_control)]
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (enable, _))| dispcnt & enable != 0)
+        .filter_map(|(window, (_, control))| {
+            let horizontal = read_register(io_registers, REG_WIN0H + window * 2);
+            let vertical = read_register(io_registers, REG_WIN0V + window * 2);
+            inside(line as u16, vertical >> 8, vertical & 0xFF).then_some((
+                horizontal >> 8,
+                horizontal & 0xFF,
+                control,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    std::array::from_fn(|x| {
+        // WIN0 has priority over WIN1, which has priority over the OBJ window
+        windows
+            .iter()
+            .find(|(left, right, _)| inside(x as u16, *left, *right))
+            .map(|(_, _, control)| *control)
+            .or((dispcnt & DISPCNT_OBJ_WINDOW != 0 && objects.window[x])
+                .then_some(obj_window_control))
+            .unwrap_or(outside_control)
+            & WINDOW_ALL
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::sprites::ObjLayer;
+    use crate::ppu::window::window_line;
+    use crate::ppu::write_register;
+    use crate::system_bus::{IO_REGISTERS_SIZE, REG_WIN0H, REG_WIN0V, REG_WININ, REG_WINOUT};
+
+    #[test]
+    fn test_windows() {
+        let mut io_registers = [0x00; IO_REGISTERS_SIZE];
+        let mut objects = ObjLayer::default();
+        objects.window[100] = true;
+        objects.window[200] = true;
+
+        // WIN0 from (10, 20) to (50, 60) and WIN1 wrapping around from x 200
+        // to 20 and y 150 to 30
+        write_register(&mut io_registers, REG_WIN0H, 0x0A32);
+        write_register(&mut io_registers, REG_WIN0V, 0x143C);
+        write_register(&mut io_registers, REG_WIN0H + 2, 0xC814);
+        write_register(&mut io_registers, REG_WIN0V + 2, 0x961E);
+        write_register(&mut io_registers, REG_WININ, 0x0201);
+        write_register(&mut io_registers, REG_WINOUT, 0x2408);
+
+        // No windows enabled
+        assert_eq!(window_line(0x0000, &io_registers, 25, &objects)[0], 0x3F);
+
+        let windows = window_line(0xE000, &io_registers, 25, &objects);
+        assert_eq!(windows[9], 0x02);
+        assert_eq!(windows[10], 0x01);
+        assert_eq!(windows[49], 0x01);
+        assert_eq!(windows[50], 0x08);
+        assert_eq!(windows[100], 0x24);
+        assert_eq!(windows[200], 0x02);
+
+        // Line 60 is on the exclusive bottom edge of WIN0 and outside of WIN1
+        let windows = window_line(0xE000, &io_registers, 60, &objects);
+        assert_eq!(windows[10], 0x08);
+        assert_eq!(windows[200], 0x24);
+
+        // The OBJ window needs its own enable bit
+        let windows = window_line(0x6000, &io_registers, 60, &objects);
+        assert_eq!(windows[100], 0x08);
+    }
+}
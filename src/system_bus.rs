@@ -1,5 +1,15 @@
 #[allow(dead_code)]
+use crate::apu::{
+    Apu, FIFO_REGISTER_SIZE, REG_FIFO_A, REG_SOUND1CNT_L, REG_WAVE_RAM, WAVE_RAM_SIZE,
+};
+use std::collections::VecDeque;
+
+use crate::bios::BIOS_SIZE;
+use crate::dma::{CONTROL_ENABLE, DMA_CHANNEL_SIZE, Dma, DmaTiming, DmaTransfer, REG_DMA0SAD};
 use crate::gamepak::Gamepak;
+use crate::interrupts::{INTERRUPT_MASK, Interrupt};
+use crate::ppu::{DISPSTAT_READ_ONLY, Ppu};
+use crate::timers::{REG_TM0CNT_L, TIMERS_SIZE, Timers};
 
 pub const ACCESS_NONSEQ: u8 = 0;
 pub const ACCESS_SEQ: u8 = 1;
@@ -15,6 +25,117 @@ pub const ON_CHIP_WRAM_START: usize = 0x3000000;
 pub const ON_CHIP_WRAM_END: usize = 0x3007FFF;
 pub const ON_CHIP_WRAM_SIZE: usize = ON_CHIP_WRAM_END - ON_CHIP_WRAM_START + 1;
 
+pub const IO_REGISTERS_START: usize = 0x4000000;
+pub const IO_REGISTERS_END: usize = 0x40003FF;
+pub const IO_REGISTERS_SIZE: usize = IO_REGISTERS_END - IO_REGISTERS_START + 1;
+
+pub const PALETTE_RAM_START: usize = 0x5000000;
+pub const PALETTE_RAM_END: usize = 0x50003FF;
+pub const PALETTE_RAM_SIZE: usize = PALETTE_RAM_END - PALETTE_RAM_START + 1;
+
+pub const VRAM_START: usize = 0x6000000;
+pub const VRAM_END: usize = 0x6017FFF;
+pub const VRAM_SIZE: usize = VRAM_END - VRAM_START + 1;
+
+pub const OAM_START: usize = 0x7000000;
+pub const OAM_END: usize = 0x70003FF;
+pub const OAM_SIZE: usize = OAM_END - OAM_START + 1;
+
+/// Offsets of IO registers from `IO_REGISTERS_START`
+pub const REG_DISPCNT: usize = 0x000;
+pub const REG_DISPSTAT: usize = 0x004;
+pub const REG_VCOUNT: usize = 0x006;
+pub const REG_BG0CNT: usize = 0x008;
+pub const REG_BG0HOFS: usize = 0x010;
+pub const REG_BG2PA: usize = 0x020;
+/// The reference points of BG2 and BG3, BGxX followed by BGxY
+const REG_BG2X: usize = 0x028;
+const REG_BG3X: usize = 0x038;
+/// WIN1H and WIN1V follow WIN0H and WIN0V
+pub const REG_WIN0H: usize = 0x040;
+pub const REG_WIN0V: usize = 0x044;
+pub const REG_WININ: usize = 0x048;
+pub const REG_WINOUT: usize = 0x04A;
+pub const REG_MOSAIC: usize = 0x04C;
+pub const REG_BLDCNT: usize = 0x050;
+pub const REG_BLDALPHA: usize = 0x052;
+pub const REG_BLDY: usize = 0x054;
+pub const REG_SOUNDBIAS: usize = 0x088;
+pub const REG_KEYINPUT: usize = 0x130;
+pub const REG_RCNT: usize = 0x134;
+pub const REG_IE: usize = 0x200;
+pub const REG_IF: usize = 0x202;
+pub const REG_WAITCNT: usize = 0x204;
+pub const REG_IME: usize = 0x208;
+pub const REG_POSTFLG: usize = 0x300;
+pub const REG_HALTCNT: usize = 0x301;
+
+/// The bits of the IO register half word at `offset` that can be read back.
+/// Write-only and unused registers have none and read as open bus
+fn io_read_mask(offset: usize) -> u16 {
+    match offset & !1 {
+        REG_DISPCNT | 0x002 => 0xFFFF,
+        REG_DISPSTAT => 0xFF3F,
+        REG_VCOUNT => 0x00FF,
+        // BG0CNT and BG1CNT have no display area overflow bit
+        0x008 | 0x00A => 0xDFFF,
+        0x00C | 0x00E => 0xFFFF,
+        REG_WININ | REG_WINOUT => 0x3F3F,
+        REG_BLDCNT => 0x3FFF,
+        REG_BLDALPHA => 0x1F1F,
+        // Already masked by the APU, including the unused half words
+        REG_SOUND1CNT_L..REG_FIFO_A => 0xFFFF,
+        REG_DMA0SAD..0x0E0 if (offset - REG_DMA0SAD) % DMA_CHANNEL_SIZE == 10 => {
+            // Only DMA3 has the Game Pak DRQ bit
+            if offset >= REG_DMA0SAD + DMA_CHANNEL_SIZE * 3 {
+                0xFFE0
+            } else {
+                0xF7E0
+            }
+        }
+        REG_TM0CNT_L..0x110 if offset & 2 == 0 => 0xFFFF,
+        REG_TM0CNT_L..0x110 => 0x00C7,
+        // SIODATA32, SIOMULTI, SIOCNT and SIODATA8
+        0x120..0x12C => 0xFFFF,
+        REG_KEYINPUT => 0x03FF,
+        // KEYCNT
+        0x132 => 0xC3FF,
+        REG_RCNT => 0xC1FF,
+        // JOYCNT, JOY_RECV, JOY_TRANS and JOYSTAT
+        0x140 => 0x0047,
+        0x150..0x158 => 0xFFFF,
+        0x158 => 0x003A,
+        REG_IE | REG_IF => INTERRUPT_MASK,
+        // The Game Pak type flag is always 0 on a GBA
+        REG_WAITCNT => 0x5FFF,
+        REG_IME => 0x0001,
+        // HALTCNT is write-only
+        REG_POSTFLG => 0x00FF,
+        _ => 0x0000,
+    }
+}
+
+/// The ROM is mirrored in the three wait state regions
+pub const GAMEPAK_ROM_START: usize = 0x8000000;
+pub const GAMEPAK_ROM_END: usize = 0xDFFFFFF;
+pub const GAMEPAK_ROM_MAX_SIZE: usize = 0x2000000;
+
+pub const GAMEPAK_SRAM_START: usize = 0xE000000;
+pub const GAMEPAK_SRAM_END: usize = 0xFFFFFFF;
+
+/// The memory areas backed by plain byte arrays on the bus
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum MemoryRegion {
+    Bios,
+    OnBoardWram,
+    OnChipWram,
+    IoRegisters,
+    PaletteRam,
+    Vram,
+    Oam,
+    GamepakRom,
+}
+
 pub trait SystemBus {
     fn idle(&mut self);
 
@@ -26,51 +147,642 @@ pub trait SystemBus {
 
     fn read_byte(&mut self, address: u32, access: u8) -> u8;
     fn write_byte(&mut self, address: u32, data: u8, access: u8);
+
+    /// LDR and SWP: The aligned word is read and rotated so the addressed
+    /// byte ends up in the lowest bits
+    fn read_word_rotated(&mut self, address: u32, access: u8) -> u32 {
+        self.read_word(address, access)
+            .rotate_right((address & 3) * 8)
+    }
+
+    /// LDRH: The aligned half word is read and misaligned reads are rotated
+    /// by 8 bits like `read_word_rotated`
+    fn read_half_word_rotated(&mut self, address: u32, access: u8) -> u32 {
+        (self.read_half_word(address, access) as u32).rotate_right((address & 1) * 8)
+    }
+
+    /// LDRSH: Misaligned reads sign-extend the addressed byte (the upper half
+    /// of the aligned half word) instead of the half word
+    fn read_half_word_signed(&mut self, address: u32, access: u8) -> u32 {
+        let value = self.read_half_word(address, access);
+        if address & 1 == 1 {
+            (value >> 8) as i8 as u32
+        } else {
+            value as i16 as u32
+        }
+    }
+
+    /// LDRSB
+    fn read_byte_signed(&mut self, address: u32, access: u8) -> u32 {
+        self.read_byte(address, access) as i8 as u32
+    }
+}
+
+/// A DMA started while the system was ticking. These are serviced once the
+/// tick is over so that transfers never run inside one another
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum DmaRequest {
+    Timing(DmaTiming),
+    /// Sound FIFO A or B needs a refill
+    SoundFifo(usize),
 }
 
 pub struct Bus {
-    gamepak: Gamepak,
+    /// `None` when running a program without a cartridge i.e. multiboot
+    gamepak: Option<Gamepak>,
     bios: Vec<u8>,
+    /// The CPU is executing code in the BIOS. Only then can the BIOS be read
     bios_active: bool,
+    /// The last opcode fetched from the BIOS. Returned for reads of the BIOS
+    /// from outside it
+    bios_latch: u32,
+
+    /// The last two opcode fetches, i.e. the CPU pipeline. Reads of unmapped
+    /// memory return what the last fetch left on the bus
+    last_fetch: u32,
+    last_fetch_address: u32,
+    previous_fetch: u32,
+    thumb_fetch: bool,
+
+    on_board_wram: Box<[u8; ON_BOARD_WRAM_SIZE]>,
+    on_chip_wram: Box<[u8; ON_CHIP_WRAM_SIZE]>,
+    io_registers: [u8; IO_REGISTERS_SIZE],
+    /// Owns palette RAM, VRAM and OAM
+    pub ppu: Ppu,
+    pub apu: Apu,
+    dma: Dma,
+    dma_requests: VecDeque<DmaRequest>,
+    timers: Timers,
 
-    on_board_wram: [u8; ON_BOARD_WRAM_SIZE],
-    on_chip_wram: [u8; ON_CHIP_WRAM_SIZE],
+    /// Set by writing HALTCNT. The CPU is paused until an enabled interrupt
+    /// is requested
+    halted: bool,
+    /// Cycles since power on
+    cycles: u64,
+    frames: u64,
 }
 
 impl Bus {
-    pub fn new(gamepak: Gamepak, bios: Vec<u8>) -> Self {
-        Self {
+    pub fn new(gamepak: Option<Gamepak>, bios: Vec<u8>) -> Self {
+        let mut bus = Self {
             gamepak,
             bios,
             bios_active: true,
-            on_board_wram: [0x00; ON_BOARD_WRAM_SIZE],
-            on_chip_wram: [0x00; ON_CHIP_WRAM_SIZE],
+            bios_latch: 0x00000000,
+            last_fetch: 0x00000000,
+            last_fetch_address: 0x00000000,
+            previous_fetch: 0x00000000,
+            thumb_fetch: false,
+            on_board_wram: Box::new([0x00; ON_BOARD_WRAM_SIZE]),
+            on_chip_wram: Box::new([0x00; ON_CHIP_WRAM_SIZE]),
+            io_registers: [0x00; IO_REGISTERS_SIZE],
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            dma: Dma::new(),
+            dma_requests: VecDeque::new(),
+            timers: Timers::new(),
+            halted: false,
+            cycles: 0,
+            frames: 0,
+        };
+        // All keys released
+        bus.set_io_register(REG_KEYINPUT, 0x03FF);
+        // The affine backgrounds start untransformed
+        for offset in [REG_BG2PA, REG_BG2PA + 6, REG_BG2PA + 0x10, REG_BG2PA + 0x16] {
+            bus.set_io_register(offset, 0x0100);
         }
+
+        bus
     }
 
-    pub fn toggle_bios(&mut self) {
-        self.bios_active = !self.bios_active;
-        if self.bios_active {
-            log::info!("Enabled BIOS");
+    /// Set up the IO registers the way the BIOS leaves them after booting
+    pub fn skip_bios(&mut self) {
+        self.set_io_register(REG_SOUNDBIAS, 0x0200);
+        self.set_io_register(REG_RCNT, 0x8000);
+        self.io_registers[REG_POSTFLG] = 0x01;
+
+        // The opcode after the jump out of the BIOS at the end of booting
+        self.bios_active = false;
+        self.bios_latch = 0xE129F000;
+    }
+
+    fn io_register(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.io_registers[offset], self.io_registers[offset + 1]])
+    }
+
+    fn set_io_register(&mut self, offset: usize, value: u16) {
+        self.io_registers[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Handle CPU and DMA writes to the IO registers with side effects
+    fn write_io_registers(&mut self, offset: usize, bytes: &[u8]) {
+        let dma_enabled = self.dma_enabled();
+
+        for (i, byte) in bytes.iter().enumerate() {
+            match offset + i {
+                REG_DISPSTAT => {
+                    let read_only = DISPSTAT_READ_ONLY as u8;
+                    self.io_registers[REG_DISPSTAT] =
+                        (self.io_registers[REG_DISPSTAT] & read_only) | (byte & !read_only);
+                }
+                offset if (REG_VCOUNT..REG_VCOUNT + 2).contains(&offset) => {}
+                // Writing 1 to a bit in IF acknowledges the interrupt
+                offset if (REG_IF..REG_IF + 2).contains(&offset) => {
+                    self.io_registers[offset] &= !byte;
+                }
+                offset
+                    if (REG_SOUND1CNT_L..REG_SOUNDBIAS).contains(&offset)
+                        || (REG_WAVE_RAM..REG_WAVE_RAM + WAVE_RAM_SIZE).contains(&offset) =>
+                {
+                    self.apu.write_byte(offset, *byte, &mut self.io_registers);
+                }
+                offset if (REG_FIFO_A..REG_FIFO_A + FIFO_REGISTER_SIZE * 2).contains(&offset) => {
+                    self.apu
+                        .write_fifo((offset - REG_FIFO_A) / FIFO_REGISTER_SIZE, *byte);
+                }
+                offset if (REG_TM0CNT_L..REG_TM0CNT_L + TIMERS_SIZE).contains(&offset) => {
+                    self.timers.write_byte(offset - REG_TM0CNT_L, *byte);
+                }
+                offset if (REG_KEYINPUT..REG_KEYINPUT + 2).contains(&offset) => {}
+                // Stop mode (bit 7) is treated like halt since nothing that
+                // wakes the system from it is emulated
+                REG_HALTCNT => self.halted = true,
+                offset => self.io_registers[offset] = *byte,
+            }
+        }
+
+        // Writing BGxX or BGxY reloads that coordinate only. It takes effect
+        // on the next line
+        for (affine_bg, start) in [REG_BG2X, REG_BG3X].into_iter().enumerate() {
+            for (y, start) in [(false, start), (true, start + 4)] {
+                if offset < start + 4 && start < offset + bytes.len() {
+                    self.ppu
+                        .latch_reference_coordinate(affine_bg, y, &self.io_registers);
+                }
+            }
+        }
+
+        self.update_timer_registers();
+
+        for (channel, was_enabled) in dma_enabled.into_iter().enumerate() {
+            if !was_enabled && self.dma_enabled()[channel] {
+                self.enable_dma(channel);
+            }
+        }
+    }
+
+    fn dma_register(channel: usize, offset: usize) -> usize {
+        REG_DMA0SAD + channel * DMA_CHANNEL_SIZE + offset
+    }
+
+    fn dma_control(&self, channel: usize) -> u16 {
+        self.io_register(Self::dma_register(channel, 10))
+    }
+
+    fn dma_enabled(&self) -> [bool; 4] {
+        [0, 1, 2, 3].map(|channel| self.dma_control(channel) & CONTROL_ENABLE != 0)
+    }
+
+    fn dma_address(&self, channel: usize, offset: usize) -> u32 {
+        let low = self.io_register(Self::dma_register(channel, offset)) as u32;
+        let high = self.io_register(Self::dma_register(channel, offset + 2)) as u32;
+        (high << 16) | low
+    }
+
+    fn enable_dma(&mut self, channel: usize) {
+        let source = self.dma_address(channel, 0);
+        let destination = self.dma_address(channel, 4);
+        let count = self.io_register(Self::dma_register(channel, 8));
+        self.dma.enable(channel, source, destination, count);
+
+        if Dma::timing(self.dma_control(channel)) == DmaTiming::Immediate {
+            self.run_dma(channel);
+        }
+    }
+
+    /// Start the enabled DMAs waiting for `timing`
+    pub fn trigger_dma(&mut self, timing: DmaTiming) {
+        for channel in 0..4 {
+            let control = self.dma_control(channel);
+            if control & CONTROL_ENABLE != 0 && Dma::timing(control) == timing {
+                self.run_dma(channel);
+            }
+        }
+    }
+
+    /// DMA1 and DMA2 with special timing refill the sound FIFO they point at
+    fn request_sound_dma(&mut self, fifo: usize) {
+        let fifo_address = (IO_REGISTERS_START + REG_FIFO_A + fifo * FIFO_REGISTER_SIZE) as u32;
+        for channel in 1..=2 {
+            let control = self.dma_control(channel);
+            if control & CONTROL_ENABLE != 0
+                && Dma::timing(control) == DmaTiming::Special
+                && self.dma.channels[channel].destination == fifo_address
+            {
+                let transfer = self.dma.sound_transfer(channel, control);
+                self.run_transfer(transfer, control);
+            }
+        }
+    }
+
+    /// Carry out the whole transfer of `channel` while the CPU is paused
+    fn run_dma(&mut self, channel: usize) {
+        let control = self.dma_control(channel);
+        let transfer = self.dma.transfer(channel, control);
+        self.run_transfer(transfer, control);
+    }
+
+    fn run_transfer(&mut self, transfer: DmaTransfer, control: u16) {
+        let channel = transfer.channel;
+        let (mut source, mut destination) = (transfer.source, transfer.destination);
+        let mut cycles = 2;
+        for _ in 0..transfer.count {
+            if transfer.word {
+                let value = u32::from_le_bytes(self.read_at::<4>(source, ACCESS_DMA));
+                self.write_to::<4>(destination, value, ACCESS_DMA);
+            } else {
+                let value = u16::from_le_bytes(self.read_at::<2>(source, ACCESS_DMA));
+                self.write_to::<2>(destination, value as u32, ACCESS_DMA);
+            }
+            let width = transfer.unit() as usize;
+            cycles += self.access_cycles(source, width, ACCESS_SEQ)
+                + self.access_cycles(destination, width, ACCESS_SEQ);
+            (source, destination) = transfer.step(source, destination);
+        }
+
+        let reload = (
+            self.dma_address(channel, 4),
+            self.io_register(Self::dma_register(channel, 8)),
+        );
+        let (control, interrupt) =
+            self.dma
+                .finish(&transfer, (source, destination), control, reload);
+        self.set_io_register(Self::dma_register(channel, 10), control);
+        if let Some(interrupt) = interrupt {
+            self.request_interrupt(interrupt);
+        }
+
+        self.step(cycles);
+    }
+
+    /// Cycles taken by an access of `width` bytes. GamePak and SRAM wait
+    /// states are configured in WAITCNT
+    fn access_cycles(&self, address: u32, width: usize, access: u8) -> u32 {
+        const NON_SEQUENTIAL: [u32; 4] = [4, 3, 2, 8];
+        let waitcnt = self.io_register(REG_WAITCNT) as u32;
+
+        match address >> 24 {
+            0x02 if width == 4 => 6,
+            0x02 => 3,
+            0x05 | 0x06 if width == 4 => 2,
+            0x08..=0x0D => {
+                let wait_state = (address >> 25) - 4;
+                let non_sequential = NON_SEQUENTIAL[(waitcnt >> (2 + wait_state * 3)) as usize & 3];
+                let sequential = match (wait_state, waitcnt & (1 << (4 + wait_state * 3)) != 0) {
+                    (_, true) => 1,
+                    (0, false) => 2,
+                    (1, false) => 4,
+                    _ => 8,
+                };
+
+                let first = 1 + if access & ACCESS_SEQ != 0 {
+                    sequential
+                } else {
+                    non_sequential
+                };
+                // 32-bit accesses are split into two sequential 16-bit ones
+                if width == 4 {
+                    first + 1 + sequential
+                } else {
+                    first
+                }
+            }
+            0x0E | 0x0F => 1 + NON_SEQUENTIAL[waitcnt as usize & 3],
+            _ => 1,
+        }
+    }
+
+    /// Advance the rest of the system by `cycles`, then run the DMAs started
+    /// meanwhile. Those can start more DMAs in turn
+    fn tick(&mut self, cycles: u32) {
+        self.step(cycles);
+        while let Some(request) = self.dma_requests.pop_front() {
+            match request {
+                DmaRequest::Timing(timing) => self.trigger_dma(timing),
+                DmaRequest::SoundFifo(fifo) => self.request_sound_dma(fifo),
+            }
+        }
+    }
+
+    /// Advance the rest of the system by `cycles`, queueing the DMAs started
+    fn step(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+
+        // One event at a time so that none is lost when a long transfer
+        // spans several lines
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let ppu_cycles = remaining.min(self.ppu.cycles_to_event());
+            remaining -= ppu_cycles;
+
+            let events = self.ppu.tick(ppu_cycles, &mut self.io_registers);
+            if events.interrupts != 0 {
+                let flags = self.io_register(REG_IF) | events.interrupts;
+                self.set_io_register(REG_IF, flags);
+            }
+            if events.vblank {
+                self.frames += 1;
+                self.dma_requests
+                    .push_back(DmaRequest::Timing(DmaTiming::VBlank));
+            }
+            if events.hblank {
+                self.dma_requests
+                    .push_back(DmaRequest::Timing(DmaTiming::HBlank));
+            }
+        }
+        self.tick_timers(cycles);
+        self.apu.tick(cycles, &mut self.io_registers);
+    }
+
+    /// Timers 0 and 1 also clock the sound FIFOs
+    fn tick_timers(&mut self, cycles: u32) {
+        let overflows = self.timers.tick(cycles);
+        let interrupts = self.timers.interrupts(overflows);
+        if interrupts != 0 {
+            let flags = self.io_register(REG_IF) | interrupts;
+            self.set_io_register(REG_IF, flags);
+        }
+        self.update_timer_registers();
+
+        for (timer, overflows) in overflows.into_iter().take(2).enumerate() {
+            if overflows == 0 {
+                continue;
+            }
+            let refill = self.apu.timer_overflow(timer, overflows);
+            for fifo in (0..2).filter(|fifo| refill[*fifo]) {
+                self.dma_requests.push_back(DmaRequest::SoundFifo(fifo));
+            }
+        }
+    }
+
+    fn update_timer_registers(&mut self) {
+        self.timers
+            .copy_registers(&mut self.io_registers[REG_TM0CNT_L..REG_TM0CNT_L + TIMERS_SIZE]);
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The number of VBlanks so far, i.e. the frames completed
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Update KEYINPUT, where pressed buttons read as 0. Bits 0-9 of
+    /// `pressed` are A, B, Select, Start, Right, Left, Up, Down, R and L
+    pub fn set_pressed_keys(&mut self, pressed: u16) {
+        self.set_io_register(REG_KEYINPUT, !pressed & 0x03FF);
+    }
+
+    /// The scanline being drawn
+    pub fn vcount(&self) -> u16 {
+        self.io_register(REG_VCOUNT)
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let flags = self.io_register(REG_IF) | interrupt.mask();
+        self.set_io_register(REG_IF, flags);
+    }
+
+    /// An enabled interrupt has been requested. This wakes the CPU from halt
+    /// even when IME is cleared
+    pub fn interrupt_pending(&self) -> bool {
+        self.io_register(REG_IE) & self.io_register(REG_IF) & INTERRUPT_MASK != 0
+    }
+
+    /// The IRQ line to the CPU
+    pub fn irq_line(&self) -> bool {
+        self.io_register(REG_IME) & 1 == 1 && self.interrupt_pending()
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn wake(&mut self) {
+        self.halted = false;
+    }
+
+    /// Copy `data` into memory starting at `address` without any of the side
+    /// effects of a CPU write. Used by loaders to place programs in RAM
+    pub fn load(&mut self, address: u32, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let address = address as usize + i;
+            match self.region(address) {
+                Some((region, offset)) if region != MemoryRegion::Bios => {
+                    self.region_memory(region)[offset] = *byte;
+                }
+                _ => log::warn!("Cannot load data at {address:#010X}"),
+            }
+        }
+    }
+
+    /// Map an address to the backing region and the offset into it (after
+    /// mirroring)
+    fn region(&self, address: usize) -> Option<(MemoryRegion, usize)> {
+        match address {
+            0x00000000..BIOS_SIZE => Some((MemoryRegion::Bios, address)),
+            0x02000000..0x03000000 => Some((
+                MemoryRegion::OnBoardWram,
+                address & (ON_BOARD_WRAM_SIZE - 1),
+            )),
+            0x03000000..0x04000000 => {
+                Some((MemoryRegion::OnChipWram, address & (ON_CHIP_WRAM_SIZE - 1)))
+            }
+            IO_REGISTERS_START..=IO_REGISTERS_END => {
+                Some((MemoryRegion::IoRegisters, address - IO_REGISTERS_START))
+            }
+            0x05000000..0x06000000 => {
+                Some((MemoryRegion::PaletteRam, address & (PALETTE_RAM_SIZE - 1)))
+            }
+            0x06000000..0x07000000 => {
+                // 96KB mirrored in 128KB steps with the upper 32KB mirroring
+                // the OBJ tiles at 0x06010000-0x06017FFF
+                let offset = address & 0x1FFFF;
+                let offset = if offset >= VRAM_SIZE {
+                    offset - 0x8000
+                } else {
+                    offset
+                };
+                Some((MemoryRegion::Vram, offset))
+            }
+            0x07000000..0x08000000 => Some((MemoryRegion::Oam, address & (OAM_SIZE - 1))),
+            GAMEPAK_ROM_START..=GAMEPAK_ROM_END if let Some(gamepak) = self.gamepak.as_ref() => {
+                let offset = address & (GAMEPAK_ROM_MAX_SIZE - 1);
+                if offset < gamepak.rom.len() {
+                    Some((MemoryRegion::GamepakRom, offset))
+                } else if gamepak.config.rom_mirroring {
+                    // The ROM size is always a power of two
+                    Some((MemoryRegion::GamepakRom, offset & (gamepak.rom.len() - 1)))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn region_memory(&mut self, region: MemoryRegion) -> &mut [u8] {
+        match region {
+            MemoryRegion::Bios => &mut self.bios,
+            MemoryRegion::OnBoardWram => self.on_board_wram.as_mut_slice(),
+            MemoryRegion::OnChipWram => self.on_chip_wram.as_mut_slice(),
+            MemoryRegion::IoRegisters => &mut self.io_registers,
+            MemoryRegion::PaletteRam => &mut self.ppu.palette_ram,
+            MemoryRegion::Vram => self.ppu.vram.as_mut_slice(),
+            MemoryRegion::Oam => &mut self.ppu.oam,
+            MemoryRegion::GamepakRom => &mut self.gamepak.as_mut().unwrap().rom,
+        }
+    }
+
+    /// The end of the BG part of VRAM. Bitmap modes (3-5) use the first
+    /// 16KB of OBJ VRAM as a second frame
+    fn vram_bg_end(&self) -> usize {
+        if self.io_registers[0] & 0b111 >= 3 {
+            0x14000
         } else {
-            log::info!("Disabled BIOS");
+            0x10000
         }
     }
 
-    fn write_to<const N: usize>(&mut self, address: u32, data: u32, _access: u8) {}
+    fn write_to<const N: usize>(&mut self, address: u32, data: u32, _access: u8) {
+        let Some((region, offset)) = self.region(address as usize) else {
+            return;
+        };
+
+        let bytes = data.to_le_bytes();
+        match region {
+            MemoryRegion::Bios | MemoryRegion::GamepakRom => {}
+            // 8-bit writes to palette RAM and BG VRAM write the byte to both
+            // halves of the half word. They are ignored for OBJ VRAM and OAM
+            MemoryRegion::PaletteRam if N == 1 => {
+                let offset = offset & !1;
+                self.ppu.palette_ram[offset..offset + 2].copy_from_slice(&[bytes[0], bytes[0]]);
+            }
+            MemoryRegion::Vram if N == 1 => {
+                if offset < self.vram_bg_end() {
+                    let offset = offset & !1;
+                    self.ppu.vram[offset..offset + 2].copy_from_slice(&[bytes[0], bytes[0]]);
+                }
+            }
+            MemoryRegion::Oam if N == 1 => {}
+            MemoryRegion::IoRegisters => self.write_io_registers(offset, &bytes[..N]),
+            _ => self.region_memory(region)[offset..offset + N].copy_from_slice(&bytes[..N]),
+        }
+    }
 
-    fn read_at<const N: usize>(&mut self, address: u32, _access: u8) -> [u8; N] {
+    /// The BIOS is protected from reads by code outside of it. These return
+    /// the last fetched BIOS opcode instead
+    fn read_bios<const N: usize>(&mut self, offset: usize, access: u8) -> [u8; N] {
         let mut bytes = [0x00; N];
-        let address = address as usize;
+        if self.bios_active {
+            bytes.copy_from_slice(&self.bios[offset..offset + N]);
+            if access & ACCESS_CODE == ACCESS_CODE {
+                let offset = offset & !3;
+                self.bios_latch =
+                    u32::from_le_bytes(self.bios[offset..offset + 4].try_into().unwrap());
+            }
+        } else {
+            let latch = self.bios_latch.to_le_bytes();
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = latch[(offset + i) & 3];
+            }
+        }
 
-        match address {
-            0x00000000..0x00004000 if self.bios_active => {
-                bytes[..N].copy_from_slice(&self.bios[address..address + N]);
+        bytes
+    }
+
+    /// The value seen when reading unmapped memory. In ARM state this is the
+    /// last fetched opcode. In Thumb state the bus is 32-bit wide and the
+    /// upper half depends on the memory the code runs from
+    fn open_bus(&mut self) -> u32 {
+        if !self.thumb_fetch {
+            return self.last_fetch;
+        }
+
+        let (current, previous) = (self.last_fetch, self.previous_fetch);
+        let aligned = self.last_fetch_address & 2 == 0;
+        let (low, high) = match self.last_fetch_address >> 24 {
+            // BIOS and OAM have a 32-bit bus so the following half word is
+            // read along with an aligned opcode
+            0x00 | 0x07 if aligned => {
+                let next = self.last_fetch_address as usize + 2;
+                let next = match self.region(next) {
+                    Some((region, offset)) => {
+                        let memory = self.region_memory(region);
+                        u16::from_le_bytes([memory[offset], memory[offset + 1]]) as u32
+                    }
+                    None => current,
+                };
+                (current, next)
+            }
+            0x00 | 0x07 => (previous, current),
+            // IWRAM only drives the half of the bus which is being read
+            0x03 if aligned => (current, previous),
+            0x03 => (previous, current),
+            _ => (current, current),
+        };
+
+        (high << 16) | low
+    }
+
+    /// Unused bits of IO registers read as 0 while write-only and unused
+    /// registers read as open bus
+    fn read_io_registers<const N: usize>(&mut self, offset: usize) -> [u8; N] {
+        let open_bus = self.open_bus().to_le_bytes();
+        std::array::from_fn(|i| {
+            let offset = offset + i;
+            match io_read_mask(offset) {
+                0x0000 => open_bus[offset & 3],
+                mask => self.io_registers[offset] & mask.to_le_bytes()[offset & 1],
             }
-            _ if N == 4 => bytes[..4].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]),
-            _ if N == 2 => bytes[..2].copy_from_slice(&[0xFF, 0xFF]),
-            _ if N == 1 => bytes[..1].copy_from_slice(&[0xFF]),
-            _ => {}
+        })
+    }
+
+    fn read_at<const N: usize>(&mut self, address: u32, access: u8) -> [u8; N] {
+        let mut bytes = [0xFF; N];
+        if access & ACCESS_CODE == ACCESS_CODE {
+            self.bios_active = (address as usize) < BIOS_SIZE;
+        }
+
+        if let Some((MemoryRegion::Bios, offset)) = self.region(address as usize) {
+            bytes = self.read_bios(offset, access);
+        } else if let Some((MemoryRegion::IoRegisters, offset)) = self.region(address as usize) {
+            bytes = self.read_io_registers(offset);
+        } else if let Some((region, offset)) = self.region(address as usize) {
+            bytes.copy_from_slice(&self.region_memory(region)[offset..offset + N]);
+        } else if (GAMEPAK_ROM_START..=GAMEPAK_ROM_END).contains(&(address as usize)) {
+            // Reads past the end of the ROM return the lower bits of the
+            // half word address left on the shared address/data lines
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                let address = address as usize + i;
+                *byte = ((address >> 1) >> ((address & 1) * 8)) as u8;
+            }
+        } else if !(GAMEPAK_SRAM_START..=GAMEPAK_SRAM_END).contains(&(address as usize)) {
+            let open_bus = self.open_bus().to_le_bytes();
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = open_bus[(address as usize + i) & 3];
+            }
+        }
+
+        if access & ACCESS_CODE == ACCESS_CODE {
+            let mut value = [0x00; 4];
+            value[..N].copy_from_slice(&bytes);
+            self.previous_fetch = self.last_fetch;
+            self.last_fetch = u32::from_le_bytes(value);
+            self.last_fetch_address = address;
+            self.thumb_fetch = N == 2;
         }
 
         bytes
@@ -78,37 +790,54 @@ impl Bus {
 }
 
 impl SystemBus for Bus {
-    fn idle(&mut self) {}
+    fn idle(&mut self) {
+        self.tick(1);
+    }
 
     fn read_word(&mut self, address: u32, access: u8) -> u32 {
+        self.tick(self.access_cycles(address, 4, access));
         u32::from_le_bytes(self.read_at::<4>(address & !3, access))
     }
 
     fn write_word(&mut self, address: u32, data: u32, access: u8) {
+        self.tick(self.access_cycles(address, 4, access));
         self.write_to::<4>(address & !3, data, access);
     }
 
     fn read_half_word(&mut self, address: u32, access: u8) -> u16 {
+        self.tick(self.access_cycles(address, 2, access));
         u16::from_le_bytes(self.read_at::<2>(address & !1, access))
     }
 
     fn write_half_word(&mut self, address: u32, data: u16, access: u8) {
+        self.tick(self.access_cycles(address, 2, access));
         self.write_to::<2>(address & !1, data as u32, access);
     }
 
     fn read_byte(&mut self, address: u32, access: u8) -> u8 {
+        self.tick(self.access_cycles(address, 1, access));
         self.read_at::<1>(address, access)[0]
     }
 
     fn write_byte(&mut self, address: u32, data: u8, access: u8) {
+        self.tick(self.access_cycles(address, 1, access));
         self.write_to::<1>(address, data as u32, access);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::apu::{REG_FIFO_A, REG_SOUNDCNT_H, REG_SOUNDCNT_X};
+    use crate::dma::{DMA_CHANNEL_SIZE, REG_DMA0SAD};
+    use crate::game_db::CartridgeConfig;
     use crate::gamepak::{GamePakHeader, Gamepak};
-    use crate::system_bus::Bus;
+    use crate::interrupts::Interrupt;
+    use crate::system_bus::{
+        ACCESS_CODE, ACCESS_NONSEQ, Bus, IO_REGISTERS_START, REG_BG0HOFS, REG_DISPSTAT,
+        REG_HALTCNT, REG_IE, REG_IF, REG_IME, REG_KEYINPUT, REG_POSTFLG, REG_SOUNDBIAS, REG_VCOUNT,
+        SystemBus,
+    };
+    use crate::timers::REG_TM0CNT_L;
 
     fn test_gamepak() -> Gamepak {
         let header = GamePakHeader {
@@ -116,16 +845,368 @@ mod tests {
             game_code: "TEST".to_string(),
             maker_code: "RA".to_string(),
         };
-        let rom = vec![0x00; 0x4000];
-        Gamepak { header, rom }
+        let mut rom = vec![0x00; 0x4000];
+        rom[0x0..0x4].copy_from_slice(&[0x2E, 0x00, 0x00, 0xEA]);
+        Gamepak {
+            header,
+            rom,
+            config: CartridgeConfig::default(),
+        }
     }
 
     const BIOS: &[u8] = include_bytes!("../roms/gba_bios.bin");
 
     #[test]
     fn test_bus_startup() {
-        let bus = Bus::new(test_gamepak(), BIOS.to_vec());
+        let bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+
+        assert!(bus.bios_active);
+    }
+
+    #[test]
+    fn test_bus_skip_bios() {
+        let mut bus = Bus::new(Some(test_gamepak()), crate::bios::builtin_bios());
+        let keyinput = (IO_REGISTERS_START + REG_KEYINPUT) as u32;
+        assert_eq!(bus.read_half_word(keyinput, ACCESS_NONSEQ), 0x03FF);
+
+        bus.skip_bios();
+        assert!(!bus.bios_active);
+        let postflg = (IO_REGISTERS_START + REG_POSTFLG) as u32;
+        assert_eq!(bus.read_byte(postflg, ACCESS_NONSEQ), 0x01);
 
+        // The vectors can still be fetched for exceptions
+        assert_eq!(bus.read_word(0x18, ACCESS_CODE), 0xEA000042);
         assert!(bus.bios_active);
     }
+
+    #[test]
+    fn test_pressed_keys() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        let keyinput = (IO_REGISTERS_START + REG_KEYINPUT) as u32;
+
+        // A and Up
+        bus.set_pressed_keys(0x0041);
+        assert_eq!(bus.read_half_word(keyinput, ACCESS_NONSEQ), 0x03BE);
+        // Writes are ignored
+        bus.write_half_word(keyinput, 0x0000, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(keyinput, ACCESS_NONSEQ), 0x03BE);
+        bus.set_pressed_keys(0x0000);
+        assert_eq!(bus.read_half_word(keyinput, ACCESS_NONSEQ), 0x03FF);
+    }
+
+    #[test]
+    fn test_bios_protection() {
+        let mut bus = Bus::new(Some(test_gamepak()), crate::bios::builtin_bios());
+
+        // Readable while executing in the BIOS
+        assert_eq!(bus.read_word(0x00, ACCESS_CODE), 0xE3A0F408);
+        assert_eq!(bus.read_word(0x1C, ACCESS_NONSEQ), 0xE25EF004);
+
+        // Reads from outside return the last fetched BIOS opcode
+        bus.read_word(0x08000000, ACCESS_CODE);
+        assert!(!bus.bios_active);
+        assert_eq!(bus.read_word(0x1C, ACCESS_NONSEQ), 0xE3A0F408);
+        assert_eq!(bus.read_half_word(0x1E, ACCESS_NONSEQ), 0xE3A0);
+        assert_eq!(bus.read_byte(0x1D, ACCESS_NONSEQ), 0xF4);
+
+        bus.skip_bios();
+        assert_eq!(bus.read_word(0x00, ACCESS_NONSEQ), 0xE129F000);
+    }
+
+    #[test]
+    fn test_interrupts() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        let register = |offset: usize| (IO_REGISTERS_START + offset) as u32;
+
+        bus.write_byte(register(REG_HALTCNT), 0x00, ACCESS_NONSEQ);
+        assert!(bus.halted());
+
+        bus.request_interrupt(Interrupt::VBlank);
+        bus.request_interrupt(Interrupt::Timer0);
+        assert!(!bus.interrupt_pending());
+        bus.write_half_word(register(REG_IE), 0x0001, ACCESS_NONSEQ);
+        assert!(bus.interrupt_pending());
+        assert!(!bus.irq_line());
+        bus.write_half_word(register(REG_IME), 0x0001, ACCESS_NONSEQ);
+        assert!(bus.irq_line());
+
+        // Writing 1 acknowledges the interrupt
+        bus.write_half_word(register(REG_IF), 0x0001, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(register(REG_IF), ACCESS_NONSEQ), 0x0008);
+        assert!(!bus.irq_line());
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+
+        // ARM: The last fetched opcode
+        bus.read_word(0x08000000, ACCESS_CODE);
+        assert_eq!(bus.read_word(0x00004000, ACCESS_NONSEQ), 0xEA00002E);
+        assert_eq!(bus.read_half_word(0x10000002, ACCESS_NONSEQ), 0xEA00);
+        assert_eq!(bus.read_byte(0x04000400, ACCESS_NONSEQ), 0x2E);
+
+        // Thumb in ROM: The last fetched opcode in both halves
+        bus.read_half_word(0x08000000, ACCESS_CODE);
+        bus.read_half_word(0x08000002, ACCESS_CODE);
+        assert_eq!(bus.read_word(0x00004000, ACCESS_NONSEQ), 0xEA00EA00);
+
+        // Thumb in IWRAM: The half word of the last fetch
+        bus.write_word(0x03000000, 0x22221111, ACCESS_NONSEQ);
+        bus.write_word(0x03000004, 0x44443333, ACCESS_NONSEQ);
+        bus.read_half_word(0x03000002, ACCESS_CODE);
+        bus.read_half_word(0x03000004, ACCESS_CODE);
+        assert_eq!(bus.read_word(0x00004000, ACCESS_NONSEQ), 0x22223333);
+        bus.read_half_word(0x03000006, ACCESS_CODE);
+        assert_eq!(bus.read_word(0x00004000, ACCESS_NONSEQ), 0x44443333);
+
+        // Thumb in OAM: The 32-bit word around the last fetch
+        bus.write_word(0x07000000, 0x66665555, ACCESS_NONSEQ);
+        bus.read_half_word(0x07000000, ACCESS_CODE);
+        assert_eq!(bus.read_word(0x00004000, ACCESS_NONSEQ), 0x66665555);
+
+        // Save memory is not open bus
+        assert_eq!(bus.read_byte(0x0E000000, ACCESS_NONSEQ), 0xFF);
+    }
+
+    #[test]
+    fn test_io_open_bus() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        let register = |offset: usize| (IO_REGISTERS_START + offset) as u32;
+        bus.read_word(0x08000000, ACCESS_CODE);
+
+        // Write-only registers read as open bus
+        bus.write_half_word(register(REG_BG0HOFS), 0x0123, ACCESS_NONSEQ);
+        assert_eq!(
+            bus.read_half_word(register(REG_BG0HOFS), ACCESS_NONSEQ),
+            0x002E
+        );
+        bus.write_word(register(REG_DMA0SAD), 0x02000000, ACCESS_NONSEQ);
+        assert_eq!(
+            bus.read_word(register(REG_DMA0SAD), ACCESS_NONSEQ),
+            0xEA00002E
+        );
+
+        // Unused registers read as open bus in either half of a word
+        assert_eq!(bus.read_word(register(0x0E0), ACCESS_NONSEQ), 0xEA00002E);
+        assert_eq!(bus.read_word(register(REG_IME), ACCESS_NONSEQ), 0xEA000000);
+
+        // Unused bits of readable registers read as 0
+        bus.write_half_word(register(REG_DISPSTAT), 0xFFFF, ACCESS_NONSEQ);
+        assert_eq!(
+            bus.read_half_word(register(REG_DISPSTAT), ACCESS_NONSEQ) & 0xFFC0,
+            0xFF00
+        );
+    }
+
+    #[test]
+    fn test_misaligned_loads() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        bus.write_word(0x02000000, 0x80FF7F01, ACCESS_NONSEQ);
+
+        assert_eq!(bus.read_word(0x02000001, ACCESS_NONSEQ), 0x80FF7F01);
+        assert_eq!(bus.read_word_rotated(0x02000000, ACCESS_NONSEQ), 0x80FF7F01);
+        assert_eq!(bus.read_word_rotated(0x02000001, ACCESS_NONSEQ), 0x0180FF7F);
+        assert_eq!(bus.read_word_rotated(0x02000003, ACCESS_NONSEQ), 0xFF7F0180);
+
+        assert_eq!(
+            bus.read_half_word_rotated(0x02000002, ACCESS_NONSEQ),
+            0x000080FF
+        );
+        assert_eq!(
+            bus.read_half_word_rotated(0x02000003, ACCESS_NONSEQ),
+            0xFF000080
+        );
+
+        assert_eq!(
+            bus.read_half_word_signed(0x02000000, ACCESS_NONSEQ),
+            0x00007F01
+        );
+        assert_eq!(
+            bus.read_half_word_signed(0x02000002, ACCESS_NONSEQ),
+            0xFFFF80FF
+        );
+        assert_eq!(
+            bus.read_half_word_signed(0x02000001, ACCESS_NONSEQ),
+            0x0000007F
+        );
+        assert_eq!(
+            bus.read_half_word_signed(0x02000003, ACCESS_NONSEQ),
+            0xFFFFFF80
+        );
+
+        assert_eq!(bus.read_byte_signed(0x02000002, ACCESS_NONSEQ), 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn test_dma() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        let register = |offset: usize| (IO_REGISTERS_START + offset) as u32;
+        bus.write_word(register(REG_IE), 0x0800, ACCESS_NONSEQ);
+
+        // Immediate 32-bit copy of 2 words from ROM with an IRQ. Only DMA3
+        // can read the ROM
+        bus.write_word(register(0x0D4), 0x08000000, ACCESS_NONSEQ);
+        bus.write_word(register(0x0D8), 0x02000000, ACCESS_NONSEQ);
+        bus.write_word(register(0x0DC), 0xC400_0002, ACCESS_NONSEQ);
+        assert_eq!(bus.read_word(0x02000000, ACCESS_NONSEQ), 0xEA00002E);
+        assert_eq!(bus.read_word(0x02000004, ACCESS_NONSEQ), 0x00000000);
+        assert_eq!(
+            bus.read_half_word(register(0x0DE), ACCESS_NONSEQ) & 0x8000,
+            0
+        );
+        assert!(bus.interrupt_pending());
+
+        // A repeating HBlank DMA with a fixed source runs on each visible line
+        bus.write_word(0x02000100, 0x1234, ACCESS_NONSEQ);
+        bus.write_word(register(0x0BC), 0x02000100, ACCESS_NONSEQ);
+        bus.write_word(register(0x0C0), 0x02000200, ACCESS_NONSEQ);
+        bus.write_word(register(0x0C4), 0xA300_0001, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(0x02000200, ACCESS_NONSEQ), 0x0000);
+        while bus.read_half_word(register(REG_VCOUNT), ACCESS_NONSEQ) != 2 {
+            bus.idle();
+        }
+        assert_eq!(bus.read_half_word(0x02000200, ACCESS_NONSEQ), 0x1234);
+        assert_eq!(bus.read_half_word(0x02000202, ACCESS_NONSEQ), 0x1234);
+        assert_eq!(bus.read_half_word(0x02000204, ACCESS_NONSEQ), 0x0000);
+    }
+
+    #[test]
+    fn test_hblank_dma_per_line() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        let register = |offset: usize| (IO_REGISTERS_START + offset) as u32;
+
+        // DMA0 copies a half word on every HBlank
+        bus.write_word(register(REG_DMA0SAD), 0x02000000, ACCESS_NONSEQ);
+        bus.write_word(register(REG_DMA0SAD + 4), 0x02001000, ACCESS_NONSEQ);
+        bus.write_word(register(REG_DMA0SAD + 8), 0xA200_0001, ACCESS_NONSEQ);
+
+        // A single tick over three lines still starts it three times
+        bus.tick(1232 * 3);
+        assert_eq!(bus.dma.channels[0].source, 0x02000006);
+        assert_eq!(bus.vcount(), 3);
+    }
+
+    #[test]
+    fn test_direct_sound_dma() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        let register = |offset: usize| (IO_REGISTERS_START + offset) as u32;
+        for (i, sample) in (0x00..0x40).step_by(4).enumerate() {
+            let word = u32::from_le_bytes([sample, sample + 1, sample + 2, sample + 3]);
+            bus.write_word(0x02000000 + i as u32 * 4, word, ACCESS_NONSEQ);
+        }
+
+        // FIFO A plays on both sides with timer 0 and is refilled by DMA1.
+        // The count and width of sound DMAs are ignored
+        bus.write_half_word(register(REG_SOUNDBIAS), 0x0200, ACCESS_NONSEQ);
+        bus.write_half_word(register(REG_SOUNDCNT_X), 0x0080, ACCESS_NONSEQ);
+        bus.write_half_word(register(REG_SOUNDCNT_H), 0x0B04, ACCESS_NONSEQ);
+        let dma1 = REG_DMA0SAD + DMA_CHANNEL_SIZE;
+        bus.write_word(register(dma1), 0x02000000, ACCESS_NONSEQ);
+        bus.write_word(register(dma1 + 4), register(REG_FIFO_A), ACCESS_NONSEQ);
+        bus.write_word(register(dma1 + 8), 0xB600_0001, ACCESS_NONSEQ);
+        // Timer 0 overflows every 512 cycles with an IRQ
+        bus.write_word(register(REG_IE), 0x0008, ACCESS_NONSEQ);
+        bus.write_word(register(REG_TM0CNT_L), 0x00C0_FE00, ACCESS_NONSEQ);
+
+        // The first overflow finds the FIFO empty and fills it with 16 bytes
+        while !bus.interrupt_pending() {
+            bus.idle();
+        }
+        assert_eq!(bus.dma.channels[1].source, 0x02000010);
+        assert!(bus.read_half_word(register(REG_TM0CNT_L), ACCESS_NONSEQ) >= 0xFE00);
+        bus.apu.drain_runs();
+
+        // Each overflow plays the next sample. Half a FIFO or less left
+        // triggers a refill
+        for _ in 0..512 * 3 {
+            bus.idle();
+        }
+        assert!(
+            bus.apu
+                .drain_runs()
+                .into_iter()
+                .flat_map(|(_, run)| run)
+                .any(|sample| sample == [4 * 64; 2])
+        );
+        assert_eq!(bus.dma.channels[1].source, 0x02000020);
+    }
+
+    #[test]
+    fn test_video_timing() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+        let register = |offset: usize| (IO_REGISTERS_START + offset) as u32;
+
+        // The status bits and VCOUNT are read only
+        bus.write_half_word(register(REG_DISPSTAT), 0xFF0F, ACCESS_NONSEQ);
+        bus.write_half_word(register(REG_VCOUNT), 0x0050, ACCESS_NONSEQ);
+        assert_eq!(
+            bus.read_half_word(register(REG_DISPSTAT), ACCESS_NONSEQ),
+            0xFF08
+        );
+        assert_eq!(
+            bus.read_half_word(register(REG_VCOUNT), ACCESS_NONSEQ),
+            0x0000
+        );
+
+        while bus.read_half_word(register(REG_VCOUNT), ACCESS_NONSEQ) != 160 {
+            bus.idle();
+        }
+        assert_eq!(bus.read_half_word(register(REG_IF), ACCESS_NONSEQ), 0x0001);
+        assert_eq!(bus.cycles() / 1232, 160);
+        assert_eq!(bus.frames(), 1);
+    }
+
+    #[test]
+    fn test_ram_mirroring() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+
+        bus.write_word(0x02000010, 0xDEADBEEF, ACCESS_NONSEQ);
+        assert_eq!(bus.read_word(0x02040010, ACCESS_NONSEQ), 0xDEADBEEF);
+        assert_eq!(bus.read_half_word(0x02FC0012, ACCESS_NONSEQ), 0xDEAD);
+
+        bus.write_byte(0x03007FFF, 0x12, ACCESS_NONSEQ);
+        assert_eq!(bus.read_byte(0x03FFFFFF, ACCESS_NONSEQ), 0x12);
+
+        // The upper 32KB of the 128KB VRAM mirror maps onto the OBJ tiles
+        bus.write_half_word(0x06010000, 0x1234, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(0x06018000, ACCESS_NONSEQ), 0x1234);
+    }
+
+    #[test]
+    fn test_gamepak_rom() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+
+        // Mirrored in all three wait state regions and read only
+        assert_eq!(bus.read_word(0x08000000, ACCESS_NONSEQ), 0xEA00002E);
+        assert_eq!(bus.read_word(0x0A000000, ACCESS_NONSEQ), 0xEA00002E);
+        bus.write_word(0x0C000000, 0x12345678, ACCESS_NONSEQ);
+        assert_eq!(bus.read_word(0x0C000000, ACCESS_NONSEQ), 0xEA00002E);
+
+        // Out of bounds reads return the half word address
+        assert_eq!(bus.read_half_word(0x08004000, ACCESS_NONSEQ), 0x2000);
+        assert_eq!(bus.read_word(0x08004004, ACCESS_NONSEQ), 0x20032002);
+        assert_eq!(bus.read_byte(0x08004005, ACCESS_NONSEQ), 0x20);
+
+        // ...unless the ROM is mirrored
+        bus.gamepak.as_mut().unwrap().config.rom_mirroring = true;
+        assert_eq!(bus.read_word(0x08004000, ACCESS_NONSEQ), 0xEA00002E);
+    }
+
+    #[test]
+    fn test_byte_writes() {
+        let mut bus = Bus::new(Some(test_gamepak()), BIOS.to_vec());
+
+        bus.write_byte(0x05000001, 0xAB, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(0x05000000, ACCESS_NONSEQ), 0xABAB);
+
+        bus.write_byte(0x06000000, 0xCD, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(0x06000000, ACCESS_NONSEQ), 0xCDCD);
+
+        // Ignored for OBJ VRAM and OAM
+        bus.write_byte(0x06010000, 0xCD, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(0x06010000, ACCESS_NONSEQ), 0x0000);
+        bus.write_byte(0x07000000, 0xEF, ACCESS_NONSEQ);
+        assert_eq!(bus.read_half_word(0x07000000, ACCESS_NONSEQ), 0x0000);
+    }
 }
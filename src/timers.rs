@@ -0,0 +1,182 @@
+use crate::interrupts::Interrupt;
+
+/// Offset of the first timer's registers from `IO_REGISTERS_START`. Each timer
+/// has CNT_L and CNT_H in 4 bytes
+pub const REG_TM0CNT_L: usize = 0x100;
+pub const TIMER_SIZE: usize = 4;
+pub const TIMERS_SIZE: usize = TIMER_SIZE * 4;
+
+const CONTROL_CASCADE: u16 = 1 << 2;
+const CONTROL_IRQ: u16 = 1 << 6;
+const CONTROL_ENABLE: u16 = 1 << 7;
+/// The bits of CNT_H which exist
+const CONTROL_MASK: u16 = 0x00C7;
+
+/// Cycles per count for each prescaler selection
+const PRESCALERS: [u32; 4] = [1, 64, 256, 1024];
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Timer {
+    /// Loaded into the counter when the timer starts or overflows
+    pub reload: u16,
+    pub counter: u16,
+    pub control: u16,
+    /// Cycles not counted yet because they are less than the prescaler
+    cycles: u32,
+}
+
+impl Timer {
+    fn enabled(&self) -> bool {
+        self.control & CONTROL_ENABLE != 0
+    }
+
+    /// Add `counts` to the counter. Returns the number of overflows
+    fn count(&mut self, counts: u32) -> u32 {
+        let until_overflow = 0x10000 - self.counter as u32;
+        if counts < until_overflow {
+            self.counter += counts as u16;
+            return 0;
+        }
+
+        // Every overflow restarts the count from the reload value
+        let period = 0x10000 - self.reload as u32;
+        let counts = counts - until_overflow;
+        self.counter = self.reload + (counts % period) as u16;
+        1 + counts / period
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Timers {
+    pub timers: [Timer; 4],
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle a write to the byte at `offset` from TM0CNT_L. CNT_L sets the
+    /// reload value while reads of it return the counter
+    pub fn write_byte(&mut self, offset: usize, value: u8) {
+        let timer = &mut self.timers[offset / TIMER_SIZE];
+        match offset % TIMER_SIZE {
+            0 => timer.reload = (timer.reload & 0xFF00) | value as u16,
+            1 => timer.reload = (timer.reload & 0x00FF) | ((value as u16) << 8),
+            2 => {
+                let was_enabled = timer.enabled();
+                timer.control = value as u16 & CONTROL_MASK;
+                if !was_enabled && timer.enabled() {
+                    timer.counter = timer.reload;
+                    timer.cycles = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Copy the counters and controls to the IO registers starting at
+    /// TM0CNT_L
+    pub fn copy_registers(&self, registers: &mut [u8]) {
+        for (timer, registers) in self
+            .timers
+            .iter()
+            .zip(registers.chunks_exact_mut(TIMER_SIZE))
+        {
+            registers[..2].copy_from_slice(&timer.counter.to_le_bytes());
+            registers[2..].copy_from_slice(&timer.control.to_le_bytes());
+        }
+    }
+
+    /// Run the timers for `cycles`. Returns how many times each one
+    /// overflowed. Cascading timers count the overflows of the one before
+    pub fn tick(&mut self, cycles: u32) -> [u32; 4] {
+        let mut overflows = [0; 4];
+        for index in 0..4 {
+            let previous_overflows = if index > 0 { overflows[index - 1] } else { 0 };
+            let timer = &mut self.timers[index];
+            if !timer.enabled() {
+                continue;
+            }
+
+            let counts = if index > 0 && timer.control & CONTROL_CASCADE != 0 {
+                previous_overflows
+            } else {
+                let prescaler = PRESCALERS[timer.control as usize & 3];
+                timer.cycles += cycles;
+                let counts = timer.cycles / prescaler;
+                timer.cycles %= prescaler;
+                counts
+            };
+            overflows[index] = timer.count(counts);
+        }
+
+        overflows
+    }
+
+    /// The IF bits of the interrupts requested by `overflows` as returned
+    /// from `tick`
+    pub fn interrupts(&self, overflows: [u32; 4]) -> u16 {
+        let interrupts = [
+            Interrupt::Timer0,
+            Interrupt::Timer1,
+            Interrupt::Timer2,
+            Interrupt::Timer3,
+        ];
+        (0..4)
+            .filter(|index| overflows[*index] > 0 && self.timers[*index].control & CONTROL_IRQ != 0)
+            .fold(0, |flags, index| flags | interrupts[index].mask())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interrupts::Interrupt;
+    use crate::timers::Timers;
+
+    fn write(timers: &mut Timers, offset: usize, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        timers.write_byte(offset, low);
+        timers.write_byte(offset + 1, high);
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut timers = Timers::new();
+
+        // Timer 0 counts every 64 cycles from 0xFFF0 and timer 1 counts its
+        // overflows
+        write(&mut timers, 0, 0xFFF0);
+        write(&mut timers, 2, 0x0081);
+        write(&mut timers, 4, 0xFFFE);
+        write(&mut timers, 6, 0x00C4);
+        assert_eq!(timers.timers[0].counter, 0xFFF0);
+
+        assert_eq!(timers.tick(63), [0, 0, 0, 0]);
+        assert_eq!(timers.tick(1), [0, 0, 0, 0]);
+        assert_eq!(timers.timers[0].counter, 0xFFF1);
+
+        // 16 counts for each overflow
+        assert_eq!(timers.tick(64 * 15), [1, 0, 0, 0]);
+        assert_eq!(timers.timers[0].counter, 0xFFF0);
+        assert_eq!(timers.tick(64 * 32), [2, 1, 0, 0]);
+        assert_eq!(timers.timers[1].counter, 0xFFFF);
+
+        assert_eq!(timers.interrupts([2, 1, 0, 0]), Interrupt::Timer1.mask());
+
+        // Writing the reload value does not change a running counter
+        write(&mut timers, 0, 0x0000);
+        assert_eq!(timers.timers[0].counter, 0xFFF0);
+        // Restarting the timer reloads it
+        write(&mut timers, 2, 0x0000);
+        write(&mut timers, 2, 0x0080);
+        assert_eq!(timers.timers[0].counter, 0x0000);
+
+        let mut registers = [0x00; 16];
+        timers.copy_registers(&mut registers);
+        assert_eq!(
+            registers[..8],
+            [0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF, 0xC4, 0x00]
+        );
+    }
+}
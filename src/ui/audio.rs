@@ -0,0 +1,286 @@
+use crate::ui::emulator::{Command, Emulator};
+use eframe::egui::{self, Ui};
+use gba::apu::resampler::ResampleQuality;
+use gba::apu::sink::{AudioSink, NullSink, WavSink};
+#[cfg(feature = "host-audio")]
+use {
+    gba::apu::resampler::{Resampler, dynamic_rate},
+    std::collections::VecDeque,
+    std::sync::atomic::{AtomicU32, Ordering},
+    std::sync::{Arc, Mutex},
+};
+
+/// Recordings are resampled to the rate most players expect
+const RECORDING_SAMPLE_RATE: u32 = 48000;
+/// Seconds of audio the device buffer holds. The rate control keeps it half
+/// full
+#[cfg(feature = "host-audio")]
+const BUFFER_DURATION: f64 = 0.1;
+/// The most the rate control changes the pitch by
+#[cfg(feature = "host-audio")]
+const MAX_RATE_DEVIATION: f64 = 0.005;
+
+/// Where the audio of the emulator goes
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct AudioSettings {
+    pub enabled: bool,
+    /// From 0 to 1
+    pub volume: f32,
+    pub quality: ResampleQuality,
+
+    #[cfg(feature = "host-audio")]
+    #[serde(skip)]
+    output: Option<HostAudio>,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            volume: 0.5,
+            quality: ResampleQuality::default(),
+            #[cfg(feature = "host-audio")]
+            output: None,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// (Re)open the audio device and send the emulator its sink
+    pub fn apply(&mut self, emulator: &Emulator) {
+        let sink = if self.enabled {
+            self.open_output()
+        } else {
+            self.close_output();
+            Box::new(NullSink)
+        };
+        emulator.send(Command::SetAudioSink(sink));
+    }
+
+    #[cfg(feature = "host-audio")]
+    fn open_output(&mut self) -> Box<dyn AudioSink> {
+        // Only one stream at a time
+        self.close_output();
+        match HostAudio::open(self.volume, self.quality) {
+            Ok((output, sink)) => {
+                self.output = Some(output);
+                Box::new(sink)
+            }
+            Err(err) => {
+                log::error!("Cannot open the audio device: {err}");
+                Box::new(NullSink)
+            }
+        }
+    }
+
+    /// Built without a host audio backend
+    #[cfg(not(feature = "host-audio"))]
+    fn open_output(&mut self) -> Box<dyn AudioSink> {
+        Box::new(NullSink)
+    }
+
+    fn close_output(&mut self) {
+        #[cfg(feature = "host-audio")]
+        {
+            self.output = None;
+        }
+    }
+
+    fn set_volume(&self) {
+        #[cfg(feature = "host-audio")]
+        if let Some(output) = self.output.as_ref() {
+            output.set_volume(self.volume);
+        }
+    }
+
+    pub fn show_menu(&mut self, ui: &mut Ui, emulator: Option<&Emulator>) {
+        let mut changed = ui.checkbox(&mut self.enabled, "Enabled").changed();
+        if !cfg!(feature = "host-audio") {
+            ui.label("Built without audio output");
+        }
+        if ui
+            .add(egui::Slider::new(&mut self.volume, 0.0..=1.0).text("Volume"))
+            .changed()
+        {
+            self.set_volume();
+        }
+
+        ui.label("Resampling");
+        for (quality, name) in [
+            (ResampleQuality::Nearest, "Nearest"),
+            (ResampleQuality::Linear, "Linear"),
+            (ResampleQuality::Cubic, "Cubic"),
+        ] {
+            changed |= ui.radio_value(&mut self.quality, quality, name).changed();
+        }
+        let Some(emulator) = emulator else {
+            return;
+        };
+        if changed {
+            self.apply(emulator);
+        }
+
+        ui.separator();
+        let (loaded, recording) = {
+            let status = emulator.status();
+            (status.loaded, status.recording)
+        };
+        if recording {
+            if ui.button("Stop recording").clicked() {
+                emulator.send(Command::SetRecording(None));
+            }
+        } else if ui
+            .add_enabled(loaded, egui::Button::new("Record WAV…"))
+            .clicked()
+            && let Some(path) = rfd::FileDialog::new()
+                .add_filter("WAV", &["wav"])
+                .set_file_name("recording.wav")
+                .save_file()
+        {
+            match WavSink::create(&path, RECORDING_SAMPLE_RATE, self.quality) {
+                Ok(sink) => emulator.send(Command::SetRecording(Some(Box::new(sink)))),
+                Err(err) => log::error!("{err}"),
+            }
+        }
+    }
+}
+
+/// Samples queued for the device along with the volume to play them at
+#[cfg(feature = "host-audio")]
+struct DeviceBuffer {
+    samples: Mutex<VecDeque<[f32; 2]>>,
+    capacity: usize,
+    /// The bits of an `f32`
+    volume: AtomicU32,
+}
+
+/// A stream on the default output device. It plays until dropped
+#[cfg(feature = "host-audio")]
+struct HostAudio {
+    _stream: cpal::Stream,
+    buffer: Arc<DeviceBuffer>,
+}
+
+#[cfg(feature = "host-audio")]
+impl HostAudio {
+    /// Start the stream. The returned sink feeds it from the emulation thread
+    fn open(volume: f32, quality: ResampleQuality) -> anyhow::Result<(Self, HostSink), String> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("No audio output device")?;
+        let config = device
+            .default_output_config()
+            .map_err(|err| err.to_string())?;
+        let sample_rate = config.sample_rate();
+        let buffer = Arc::new(DeviceBuffer {
+            samples: Mutex::new(VecDeque::new()),
+            capacity: (sample_rate as f64 * BUFFER_DURATION) as usize,
+            volume: AtomicU32::new(volume.to_bits()),
+        });
+
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => {
+                Self::build_stream::<f32>(&device, &config.config(), buffer.clone())
+            }
+            cpal::SampleFormat::I16 => {
+                Self::build_stream::<i16>(&device, &config.config(), buffer.clone())
+            }
+            cpal::SampleFormat::U16 => {
+                Self::build_stream::<u16>(&device, &config.config(), buffer.clone())
+            }
+            format => Err(format!("Unsupported sample format {format:?}")),
+        }?;
+        stream.play().map_err(|err| err.to_string())?;
+
+        let sink = HostSink {
+            buffer: buffer.clone(),
+            sample_rate,
+            resampler: Resampler::new(quality),
+            resampled: Vec::new(),
+        };
+        Ok((
+            Self {
+                _stream: stream,
+                buffer,
+            },
+            sink,
+        ))
+    }
+
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        buffer: Arc<DeviceBuffer>,
+    ) -> anyhow::Result<cpal::Stream, String>
+    where
+        T: cpal::SizedSample + cpal::FromSample<f32>,
+    {
+        use cpal::traits::DeviceTrait;
+
+        let channels = config.channels as usize;
+        device
+            .build_output_stream(
+                config,
+                move |data: &mut [T], _| {
+                    let volume = f32::from_bits(buffer.volume.load(Ordering::Relaxed));
+                    let mut samples = buffer.samples.lock().unwrap();
+                    // Silence when the emulator falls behind
+                    for frame in data.chunks_mut(channels) {
+                        let [left, right] = samples.pop_front().unwrap_or_default();
+                        for (channel, output) in frame.iter_mut().enumerate() {
+                            let sample = match (channels, channel) {
+                                (1, _) => (left + right) / 2.0,
+                                (_, 0) => left,
+                                (_, 1) => right,
+                                _ => 0.0,
+                            };
+                            *output = T::from_sample(sample * volume);
+                        }
+                    }
+                },
+                |err| log::error!("Audio stream error: {err}"),
+                None,
+            )
+            .map_err(|err| err.to_string())
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.buffer
+            .volume
+            .store(volume.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Resamples the audio to the rate of the device. The rate is nudged to
+/// keep the device buffer half full, which keeps the audio in sync with the
+/// frames as long as the emulation runs at full speed
+#[cfg(feature = "host-audio")]
+struct HostSink {
+    buffer: Arc<DeviceBuffer>,
+    sample_rate: u32,
+    resampler: Resampler,
+    resampled: Vec<[f32; 2]>,
+}
+
+#[cfg(feature = "host-audio")]
+impl AudioSink for HostSink {
+    fn push(&mut self, samples: &[[i16; 2]], sample_rate: u32) {
+        let queued = self.buffer.samples.lock().unwrap().len();
+        let fill = queued as f64 / self.buffer.capacity as f64;
+        let ratio = dynamic_rate(
+            self.sample_rate as f64 / sample_rate as f64,
+            fill,
+            MAX_RATE_DEVIATION,
+        );
+        self.resampled.clear();
+        self.resampler.process(samples, ratio, &mut self.resampled);
+
+        // The buffer overflows when running faster than the device plays.
+        // The extra audio is dropped
+        let mut queued = self.buffer.samples.lock().unwrap();
+        let free = self.buffer.capacity.saturating_sub(queued.len());
+        queued.extend(self.resampled.iter().take(free));
+    }
+}
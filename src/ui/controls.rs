@@ -0,0 +1,115 @@
+use crate::ui::emulator::{Command, Emulator, EmulatorStatus};
+use eframe::egui::{self, Align2, Color32, FontId, Key, Rect, Ui};
+
+const FRAME_RATE: f32 = 59.7275;
+const FAST_FORWARD_KEY: Key = Key::Tab;
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 8.0;
+
+/// Running, pausing and the speed of the emulation
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct RunControls {
+    /// Multiple of the native frame rate to run at
+    pub speed: f32,
+    #[serde(skip)]
+    fast_forward: bool,
+}
+
+impl Default for RunControls {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            fast_forward: false,
+        }
+    }
+}
+
+impl RunControls {
+    /// Send the settings to a newly started emulator
+    pub fn apply(&self, emulator: &Emulator) {
+        emulator.send(Command::SetSpeed(self.speed.clamp(MIN_SPEED, MAX_SPEED)));
+    }
+
+    /// Fast-forward while the hold key is down
+    pub fn handle_input(&mut self, ui: &Ui, emulator: &Emulator) {
+        let fast_forward = ui.input(|input| input.key_down(FAST_FORWARD_KEY));
+        if fast_forward != self.fast_forward {
+            self.fast_forward = fast_forward;
+            emulator.send(Command::FastForward(fast_forward));
+        }
+    }
+
+    pub fn show_toolbar(&mut self, ui: &mut Ui, emulator: &Emulator) {
+        let paused = emulator.status().paused;
+        ui.horizontal(|ui| {
+            let (label, command) = if paused {
+                ("Run", Command::Run)
+            } else {
+                ("Pause", Command::Pause)
+            };
+            if ui.button(label).clicked() {
+                emulator.send(command);
+            }
+            if ui.button("Reset").clicked() {
+                emulator.send(Command::Reset);
+            }
+
+            ui.add_enabled_ui(paused, |ui| {
+                if ui.button("Step frame").clicked() {
+                    emulator.send(Command::StepFrame);
+                }
+                if ui.button("Step scanline").clicked() {
+                    emulator.send(Command::StepScanline);
+                }
+            });
+
+            ui.separator();
+            let speed = ui.add(
+                egui::Slider::new(&mut self.speed, MIN_SPEED..=MAX_SPEED)
+                    .logarithmic(true)
+                    .custom_formatter(|speed, _| format!("{:.0}%", speed * 100.0))
+                    .custom_parser(|text| {
+                        let text = text.trim().trim_end_matches('%');
+                        text.parse::<f64>().ok().map(|percent| percent / 100.0)
+                    })
+                    .text("Speed"),
+            );
+            let reset_speed = ui.button("100%").clicked();
+            if reset_speed {
+                self.speed = 1.0;
+            }
+            if speed.changed() || reset_speed {
+                self.apply(emulator);
+            }
+        });
+    }
+
+    /// Draw the frame rate and emulation speed in the corner of `rect`
+    pub fn show_overlay(ui: &Ui, rect: Rect, status: &EmulatorStatus) {
+        let mut text = format!(
+            "{:.1} FPS ({:.0}%)",
+            status.fps,
+            status.fps / FRAME_RATE * 100.0
+        );
+        if let Some(address) = status.breakpoint {
+            text.push_str(&format!(" Breakpoint at {address:#010X}"));
+        } else if status.paused {
+            text.push_str(" Paused");
+        } else if status.fast_forward {
+            text.push_str(" Fast-forward");
+        }
+
+        let position = rect.left_top() + egui::vec2(6.0, 6.0);
+        let font = FontId::monospace(12.0);
+        let painter = ui.painter();
+        painter.text(
+            position + egui::vec2(1.0, 1.0),
+            Align2::LEFT_TOP,
+            &text,
+            font.clone(),
+            Color32::BLACK,
+        );
+        painter.text(position, Align2::LEFT_TOP, text, font, Color32::WHITE);
+    }
+}
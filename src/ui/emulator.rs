@@ -0,0 +1,431 @@
+use eframe::egui::Context;
+use gba::apu::sink::{AudioSink, NullSink};
+use gba::cpu::OpcodeTraceLog;
+use gba::elf::SymbolTable;
+use gba::gba::Gba;
+use gba::ppu::FrameBuffer;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, mpsc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// A frame is 228 lines of 1232 cycles at 16.78 MHz, which is about 59.73 Hz
+const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+/// Frames run at most in a row to catch up after the thread fell behind
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// Everything needed to (re)start a game
+#[derive(Debug, Clone)]
+pub struct LoadRequest {
+    pub rom_path: PathBuf,
+    pub bios_path: Option<PathBuf>,
+    pub skip_bios: bool,
+    pub hle_bios: bool,
+}
+
+impl LoadRequest {
+    fn load(&self) -> anyhow::Result<Gba, String> {
+        let rom = self.rom_path.as_path();
+        let bios = self.bios_path.as_deref();
+        let mut gba = match rom.extension().and_then(|extension| extension.to_str()) {
+            Some("mb") => Gba::new_multiboot(rom, bios)?,
+            Some("elf") => Gba::new_elf(rom, bios)?,
+            // Without a BIOS the GamePak is always booted directly
+            _ => {
+                let mut gba = Gba::new(rom, bios)?;
+                if self.skip_bios && bios.is_some() {
+                    gba.skip_bios();
+                }
+                gba
+            }
+        };
+        if self.hle_bios {
+            gba.set_hle_bios(true);
+        }
+        gba.start();
+        Ok(gba)
+    }
+}
+
+/// Requests from the UI to the emulation thread
+pub enum Command {
+    Load(LoadRequest),
+    Run,
+    Pause,
+    Reset,
+    StepInstruction,
+    StepFrame,
+    StepScanline,
+    /// Multiple of the native frame rate to run at
+    SetSpeed(f32),
+    /// Run as fast as possible while enabled
+    FastForward(bool),
+    /// The buttons held down, in KEYINPUT order
+    Input(u16),
+    SetBreakpoints(Vec<u32>),
+    /// Where the audio is played
+    SetAudioSink(Box<dyn AudioSink>),
+    /// Also push the audio into this sink until it is replaced or removed
+    SetRecording(Option<Box<dyn AudioSink>>),
+    Quit,
+}
+
+/// What the UI shows about the emulation. Published by the emulation thread
+#[derive(Debug, Clone, Default)]
+pub struct EmulatorStatus {
+    pub loaded: bool,
+    /// Why the last load failed. A game already running keeps running
+    pub load_error: Option<String>,
+    pub paused: bool,
+    pub fast_forward: bool,
+    pub fps: f32,
+    /// Set when execution stopped at a breakpoint
+    pub breakpoint: Option<u32>,
+    /// Whether the audio is being recorded
+    pub recording: bool,
+    pub traces: Vec<OpcodeTraceLog>,
+    /// Shared so copying the status out of the lock is cheap
+    pub symbols: Arc<SymbolTable>,
+}
+
+/// The UI side of the emulation thread
+pub struct Emulator {
+    commands: Sender<Command>,
+    frames: triple_buffer::Output<FrameBuffer>,
+    status: Arc<Mutex<EmulatorStatus>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Emulator {
+    /// Start the emulation thread. `ctx` is repainted whenever a new frame is
+    /// ready
+    pub fn spawn(ctx: Context) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let (frames_input, frames) = triple_buffer::triple_buffer(&FrameBuffer::new());
+        let status = Arc::new(Mutex::new(EmulatorStatus::default()));
+
+        let mut thread = EmulationThread::new(receiver, frames_input, status.clone(), ctx);
+        let thread = std::thread::Builder::new()
+            .name("emulation".to_string())
+            .spawn(move || thread.run())
+            .expect("failed to spawn the emulation thread");
+
+        Self {
+            commands,
+            frames,
+            status,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        // The thread only stops after `Quit`
+        let _ = self.commands.send(command);
+    }
+
+    /// The most recently completed frame
+    pub fn frame(&mut self) -> &FrameBuffer {
+        self.frames.read()
+    }
+
+    pub fn status(&self) -> MutexGuard<'_, EmulatorStatus> {
+        self.status.lock().unwrap()
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.send(Command::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The frame rate measured over the last second
+#[derive(Debug)]
+struct FrameStats {
+    start: Instant,
+    frames: u32,
+    fps: f32,
+}
+
+impl FrameStats {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            frames: 0,
+            fps: 0.0,
+        }
+    }
+
+    fn count(&mut self, frames: u32) {
+        self.frames += frames;
+        let elapsed = self.start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.frames = 0;
+            self.start = Instant::now();
+        }
+    }
+}
+
+/// The sinks the audio of the game goes to
+struct AudioOutput {
+    sink: Box<dyn AudioSink>,
+    recording: Option<Box<dyn AudioSink>>,
+}
+
+impl AudioSink for AudioOutput {
+    fn push(&mut self, samples: &[[i16; 2]], sample_rate: u32) {
+        self.sink.push(samples, sample_rate);
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(samples, sample_rate);
+        }
+    }
+}
+
+struct EmulationThread {
+    commands: Receiver<Command>,
+    frames: triple_buffer::Input<FrameBuffer>,
+    status: Arc<Mutex<EmulatorStatus>>,
+    ctx: Context,
+
+    gba: Option<Gba>,
+    /// Kept to reset the game
+    load_request: Option<LoadRequest>,
+    paused: bool,
+    speed: f32,
+    fast_forward: bool,
+    /// Kept across resets
+    pressed_keys: u16,
+    breakpoints: HashSet<u32>,
+    breakpoint: Option<u32>,
+    audio: AudioOutput,
+    /// When the next frame is due
+    next_frame: Option<Instant>,
+    stats: FrameStats,
+}
+
+impl EmulationThread {
+    fn new(
+        commands: Receiver<Command>,
+        frames: triple_buffer::Input<FrameBuffer>,
+        status: Arc<Mutex<EmulatorStatus>>,
+        ctx: Context,
+    ) -> Self {
+        Self {
+            commands,
+            frames,
+            status,
+            ctx,
+            gba: None,
+            load_request: None,
+            paused: false,
+            speed: 1.0,
+            fast_forward: false,
+            pressed_keys: 0,
+            breakpoints: HashSet::new(),
+            breakpoint: None,
+            audio: AudioOutput {
+                sink: Box::new(NullSink),
+                recording: None,
+            },
+            next_frame: None,
+            stats: FrameStats::new(),
+        }
+    }
+
+    fn run(&mut self) {
+        loop {
+            let running = self.gba.is_some() && !self.paused;
+            // Sleep until the next frame is due or a command arrives
+            let timeout = match (running, self.fast_forward, self.next_frame) {
+                (false, _, _) => None,
+                (true, true, _) | (true, false, None) => Some(Duration::ZERO),
+                (true, false, Some(next_frame)) => {
+                    Some(next_frame.saturating_duration_since(Instant::now()))
+                }
+            };
+
+            let command = match timeout {
+                None => self.commands.recv().ok(),
+                Some(timeout) => match self.commands.recv_timeout(timeout) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.run_due_frames();
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => None,
+                },
+            };
+            match command {
+                None | Some(Command::Quit) => return,
+                Some(command) => self.handle(command),
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Load(request) => {
+                self.load_request = Some(request);
+                self.reset();
+            }
+            Command::Reset => self.reset(),
+            Command::Run => {
+                self.paused = false;
+                self.breakpoint = None;
+                self.next_frame = None;
+            }
+            Command::Pause => self.paused = true,
+            Command::StepInstruction => {
+                if let Some(gba) = self.gba.as_mut() {
+                    gba.step();
+                }
+                self.publish();
+            }
+            Command::StepFrame => {
+                if let Some(gba) = self.gba.as_mut() {
+                    gba.run_until_vblank();
+                }
+                self.publish();
+            }
+            Command::StepScanline => {
+                if let Some(gba) = self.gba.as_mut() {
+                    gba.run_scanline();
+                }
+                self.publish();
+            }
+            Command::SetSpeed(speed) => {
+                self.speed = speed;
+                self.next_frame = None;
+            }
+            Command::FastForward(enabled) => {
+                self.fast_forward = enabled;
+                self.next_frame = None;
+            }
+            Command::Input(pressed) => {
+                self.pressed_keys = pressed;
+                if let Some(gba) = self.gba.as_mut() {
+                    gba.set_pressed_keys(pressed);
+                }
+            }
+            Command::SetBreakpoints(breakpoints) => {
+                self.breakpoints = breakpoints.into_iter().collect();
+            }
+            Command::SetAudioSink(sink) => self.audio.sink = sink,
+            // Dropping the previous recording completes it
+            Command::SetRecording(recording) => self.audio.recording = recording,
+            Command::Quit => {}
+        }
+        self.update_status();
+    }
+
+    fn reset(&mut self) {
+        let Some(request) = self.load_request.as_ref() else {
+            return;
+        };
+        match request.load() {
+            Ok(mut gba) => {
+                gba.set_pressed_keys(self.pressed_keys);
+                let mut status = self.status.lock().unwrap();
+                status.symbols = Arc::new(gba.symbols.clone());
+                status.load_error = None;
+                drop(status);
+                self.gba = Some(gba);
+                self.breakpoint = None;
+                self.next_frame = None;
+            }
+            Err(err) => {
+                log::error!("Cannot load {}: {err}", request.rom_path.display());
+                self.status.lock().unwrap().load_error = Some(err);
+            }
+        }
+        self.publish();
+    }
+
+    /// Run the frames that are due, or one frame when fast-forwarding
+    fn run_due_frames(&mut self) {
+        let now = Instant::now();
+        let frame_duration = FRAME_DURATION.div_f32(self.speed);
+        let mut frames = 0;
+
+        if self.fast_forward {
+            self.run_frame();
+            frames += 1;
+        } else {
+            let next_frame = *self.next_frame.get_or_insert(now);
+            let mut next_frame = next_frame;
+            while next_frame <= now && !self.paused {
+                if frames == MAX_CATCH_UP_FRAMES {
+                    // Too far behind to catch up. Drop the missed frames
+                    next_frame = now + frame_duration;
+                    break;
+                }
+                self.run_frame();
+                next_frame += frame_duration;
+                frames += 1;
+            }
+            self.next_frame = Some(next_frame);
+        }
+
+        self.stats.count(frames);
+        self.publish();
+    }
+
+    /// Run until the next VBlank or a breakpoint. The audio is pushed after
+    /// every frame since several can run before the next `publish`
+    fn run_frame(&mut self) {
+        let Some(gba) = self.gba.as_mut() else {
+            return;
+        };
+        if self.breakpoints.is_empty() {
+            gba.run_until_vblank();
+            gba.push_audio(&mut self.audio);
+            return;
+        }
+
+        let frames = gba.frames();
+        // Always run the first opcode so running again from a breakpoint
+        // does not stop at it right away
+        gba.step();
+        while gba.frames() == frames {
+            let address = gba.cpu.next_address();
+            if self.breakpoints.contains(&address) {
+                self.breakpoint = Some(address);
+                self.paused = true;
+                break;
+            }
+            gba.step();
+        }
+        gba.push_audio(&mut self.audio);
+    }
+
+    /// Hand the frame, audio and status over to the UI
+    fn publish(&mut self) {
+        if let Some(gba) = self.gba.as_mut() {
+            gba.push_audio(&mut self.audio);
+            self.frames.input_buffer().clone_from(gba.frame_buffer());
+            self.frames.publish();
+        }
+        self.update_status();
+        self.ctx.request_repaint();
+    }
+
+    fn update_status(&mut self) {
+        let mut status = self.status.lock().unwrap();
+        status.loaded = self.gba.is_some();
+        status.paused = self.paused;
+        status.fast_forward = self.fast_forward;
+        status.fps = if self.paused { 0.0 } else { self.stats.fps };
+        status.breakpoint = self.breakpoint;
+        status.recording = self.audio.recording.is_some();
+        if let Some(gba) = self.gba.as_ref() {
+            status.traces.clear();
+            status.traces.extend(gba.cpu.opcode_traces.iter().cloned());
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::ui::emulator::{Command, Emulator};
+use eframe::egui::{self, Context, InputState, Key, Ui};
+use std::collections::HashSet;
+
+/// The GBA buttons, in KEYINPUT bit order
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+    R,
+    L,
+}
+
+impl Button {
+    pub const ALL: [Button; 10] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::R,
+        Button::L,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Button::A => "A",
+            Button::B => "B",
+            Button::Select => "Select",
+            Button::Start => "Start",
+            Button::Right => "Right",
+            Button::Left => "Left",
+            Button::Up => "Up",
+            Button::Down => "Down",
+            Button::R => "R",
+            Button::L => "L",
+        }
+    }
+
+    fn mask(self) -> u16 {
+        1 << self as u16
+    }
+
+    fn default_key(self) -> Key {
+        match self {
+            Button::A => Key::X,
+            Button::B => Key::Z,
+            Button::Select => Key::Backspace,
+            Button::Start => Key::Enter,
+            Button::Right => Key::ArrowRight,
+            Button::Left => Key::ArrowLeft,
+            Button::Up => Key::ArrowUp,
+            Button::Down => Key::ArrowDown,
+            Button::R => Key::S,
+            Button::L => Key::A,
+        }
+    }
+}
+
+/// Whether `key` went down this frame. Unlike `InputState::key_pressed` this
+/// ignores the repeats of a held key
+pub fn key_pressed_once(input: &InputState, key: Key) -> bool {
+    input.events.iter().any(|event| {
+        matches!(
+            event,
+            egui::Event::Key {
+                key: event_key,
+                pressed: true,
+                repeat: false,
+                ..
+            } if *event_key == key
+        )
+    })
+}
+
+/// How the bound key presses the button
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum ButtonMode {
+    /// Pressed while the key is down
+    #[default]
+    Normal,
+    /// Pressed and released repeatedly while the key is down
+    Turbo,
+    /// Each key press toggles the button
+    Hold,
+}
+
+impl ButtonMode {
+    fn name(self) -> &'static str {
+        match self {
+            ButtonMode::Normal => "Normal",
+            ButtonMode::Turbo => "Turbo",
+            ButtonMode::Hold => "Hold",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Binding {
+    pub key: Option<Key>,
+    pub mode: ButtonMode,
+}
+
+/// Maps the keyboard to the GBA buttons. egui does not report gamepads, so
+/// only keys can be bound
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct InputMapping {
+    /// Indexed by `Button`
+    pub bindings: [Binding; 10],
+    /// Presses per second of turbo buttons
+    pub turbo_rate: f32,
+
+    #[serde(skip)]
+    pub settings_open: bool,
+    /// The button waiting for a key to bind
+    #[serde(skip)]
+    rebinding: Option<Button>,
+    /// Buttons toggled on in hold mode
+    #[serde(skip)]
+    held: u16,
+    /// What was last sent to the emulator
+    #[serde(skip)]
+    pressed: u16,
+}
+
+impl Default for InputMapping {
+    fn default() -> Self {
+        Self {
+            bindings: Button::ALL.map(|button| Binding {
+                key: Some(button.default_key()),
+                mode: ButtonMode::Normal,
+            }),
+            turbo_rate: 10.0,
+            settings_open: false,
+            rebinding: None,
+            held: 0,
+            pressed: 0,
+        }
+    }
+}
+
+impl InputMapping {
+    /// Send the buttons pressed to the emulator when they change. Turbo
+    /// relies on the UI repainting for every emulated frame
+    pub fn handle_input(&mut self, ui: &Ui, emulator: &Emulator) {
+        // Keys typed into text fields or bound in the settings do not reach
+        // the game
+        let pressed = if ui.ctx().egui_wants_keyboard_input() || self.rebinding.is_some() {
+            0
+        } else {
+            let (keys_down, time) = ui.input(|input| (input.keys_down.clone(), input.time));
+            let keys_pressed = ui.input(|input| {
+                Button::ALL
+                    .into_iter()
+                    .filter(|button| {
+                        self.bindings[*button as usize]
+                            .key
+                            .is_some_and(|key| key_pressed_once(input, key))
+                    })
+                    .fold(0, |mask, button| mask | button.mask())
+            });
+            self.pressed_buttons(&keys_down, keys_pressed, time)
+        };
+
+        if pressed != self.pressed {
+            self.pressed = pressed;
+            emulator.send(Command::Input(pressed));
+        }
+    }
+
+    /// The buttons pressed given the keys down, the buttons whose keys were
+    /// pressed this frame and the time in seconds
+    fn pressed_buttons(&mut self, keys_down: &HashSet<Key>, keys_pressed: u16, time: f64) -> u16 {
+        let turbo_on = ((time * self.turbo_rate as f64 * 2.0) as u64).is_multiple_of(2);
+        Button::ALL.into_iter().fold(0, |mask, button| {
+            let binding = self.bindings[button as usize];
+            let down = binding.key.is_some_and(|key| keys_down.contains(&key));
+            let pressed = match binding.mode {
+                ButtonMode::Normal => down,
+                ButtonMode::Turbo => down && turbo_on,
+                ButtonMode::Hold => {
+                    if keys_pressed & button.mask() != 0 {
+                        self.held ^= button.mask();
+                    }
+                    self.held & button.mask() != 0
+                }
+            };
+            if pressed { mask | button.mask() } else { mask }
+        })
+    }
+
+    pub fn show_settings(&mut self, ctx: &Context) {
+        let mut open = self.settings_open;
+        egui::Window::new("Input")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                self.capture_binding(ui);
+
+                egui::Grid::new("input-bindings")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for button in Button::ALL {
+                            self.binding_row(ui, button);
+                            ui.end_row();
+                        }
+                    });
+
+                ui.separator();
+                ui.add(egui::Slider::new(&mut self.turbo_rate, 2.0..=30.0).text("Turbo presses/s"));
+                if ui.button("Restore defaults").clicked() {
+                    *self = Self {
+                        settings_open: true,
+                        ..Self::default()
+                    };
+                }
+            });
+        self.settings_open = open;
+        if !open {
+            self.rebinding = None;
+        }
+    }
+
+    fn binding_row(&mut self, ui: &mut Ui, button: Button) {
+        let binding = &mut self.bindings[button as usize];
+        ui.label(button.name());
+
+        let text = if self.rebinding == Some(button) {
+            "Press a key...".to_string()
+        } else {
+            binding.key.map_or("Unbound", |key| key.name()).to_string()
+        };
+        let response = ui.add(egui::Button::new(text).min_size(egui::vec2(100.0, 0.0)));
+        if response.clicked() {
+            self.rebinding = Some(button);
+        }
+        if response.secondary_clicked() {
+            binding.key = None;
+        }
+        response.on_hover_text("Click to bind a key, right click to unbind");
+
+        egui::ComboBox::from_id_salt(("input-mode", button as usize))
+            .selected_text(binding.mode.name())
+            .show_ui(ui, |ui| {
+                for mode in [ButtonMode::Normal, ButtonMode::Turbo, ButtonMode::Hold] {
+                    ui.selectable_value(&mut binding.mode, mode, mode.name());
+                }
+            });
+        if binding.mode != ButtonMode::Hold {
+            self.held &= !button.mask();
+        }
+    }
+
+    /// Bind the next key pressed to the button waiting for one. Escape
+    /// cancels
+    fn capture_binding(&mut self, ui: &Ui) {
+        let Some(button) = self.rebinding else {
+            return;
+        };
+        let key = ui.input(|input| {
+            input.events.iter().find_map(|event| match event {
+                egui::Event::Key {
+                    key, pressed: true, ..
+                } => Some(*key),
+                _ => None,
+            })
+        });
+        match key {
+            Some(Key::Escape) => self.rebinding = None,
+            Some(key) => {
+                // A key presses a single button
+                for binding in &mut self.bindings {
+                    if binding.key == Some(key) {
+                        binding.key = None;
+                    }
+                }
+                self.bindings[button as usize].key = Some(key);
+                self.rebinding = None;
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::input::{Button, ButtonMode, InputMapping};
+    use eframe::egui::Key;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_pressed_buttons() {
+        let mut mapping = InputMapping::default();
+        mapping.bindings[Button::B as usize].mode = ButtonMode::Turbo;
+        mapping.bindings[Button::Start as usize].mode = ButtonMode::Hold;
+        let a = Button::A.mask();
+        let b = Button::B.mask();
+        let start = Button::Start.mask();
+
+        // Turbo buttons are pressed in the first half of each period
+        let keys_down = HashSet::from([Key::X, Key::Z]);
+        assert_eq!(mapping.pressed_buttons(&keys_down, 0, 0.0), a | b);
+        assert_eq!(mapping.pressed_buttons(&keys_down, 0, 0.075), a);
+        assert_eq!(mapping.pressed_buttons(&HashSet::new(), 0, 0.1), 0);
+
+        // Hold buttons toggle on each press and ignore the key being down
+        let keys_down = HashSet::from([Key::Enter]);
+        assert_eq!(mapping.pressed_buttons(&keys_down, start, 0.0), start);
+        assert_eq!(mapping.pressed_buttons(&keys_down, 0, 0.1), start);
+        assert_eq!(mapping.pressed_buttons(&HashSet::new(), 0, 0.2), start);
+        assert_eq!(mapping.pressed_buttons(&keys_down, start, 0.3), 0);
+    }
+}
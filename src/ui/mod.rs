@@ -1,12 +1,22 @@
+use crate::ui::audio::AudioSettings;
+use crate::ui::controls::RunControls;
 use crate::ui::disasm::{condition_text, opcode_disassembly};
-use eframe::egui::{Color32, Context, Ui};
+use crate::ui::emulator::{Command, Emulator, LoadRequest};
+use crate::ui::input::{InputMapping, key_pressed_once};
+use crate::ui::screen::ScreenView;
+use eframe::egui::{Color32, Context, Key, Ui};
 use eframe::{CreationContext, Frame, egui};
 use egui_extras::{Column, TableBuilder, TableRow};
 use gba::cpu::{ExecutedOpcode, OpcodeTraceLog};
-use gba::gba::Gba;
+use gba::elf::SymbolTable;
 use std::path::PathBuf;
 
+mod audio;
+mod controls;
 mod disasm;
+mod emulator;
+mod input;
+mod screen;
 
 const COLOR_ERROR: Color32 = Color32::LIGHT_RED;
 const COLOR_DECODED_INSTR_ADDR: Color32 = Color32::LIGHT_GREEN;
@@ -20,12 +30,19 @@ const COLOR_REGISTER: Color32 = Color32::LIGHT_BLUE;
 pub struct GbaApp {
     trace_opcode_viewer: TraceOpcodeViewer,
     disassemby_view: DisassemblyView,
+    screen_view: ScreenView,
+    run_controls: RunControls,
+    input_mapping: InputMapping,
+    audio_settings: AudioSettings,
+    /// Started along with the first game
     #[serde(skip)]
-    gba: Option<Gba>,
+    emulator: Option<Emulator>,
 
     #[serde(skip)]
     rom_path: Option<PathBuf>,
     bios_path: Option<PathBuf>,
+    skip_bios: bool,
+    hle_bios: bool,
 }
 
 impl GbaApp {
@@ -37,41 +54,89 @@ impl GbaApp {
         Self {
             trace_opcode_viewer: TraceOpcodeViewer::new(),
             disassemby_view: DisassemblyView::new(),
-            gba: None,
+            screen_view: ScreenView::default(),
+            run_controls: RunControls::default(),
+            input_mapping: InputMapping::default(),
+            audio_settings: AudioSettings::default(),
+            emulator: None,
             rom_path: None,
             bios_path: None,
+            skip_bios: false,
+            hle_bios: false,
         }
     }
 
     pub fn render_ui(&mut self, ui: &mut Ui, frame: &mut Frame) {
-        egui::Panel::top("main-menu").show_inside(ui, |ui| {
-            self.show_main_menu(ui, frame);
-        });
+        if ui.input(|input| key_pressed_once(input, Key::F11)) {
+            Self::toggle_fullscreen(ui.ctx());
+        }
+        let fullscreen = ui.input(|input| input.viewport().fullscreen.unwrap_or(false));
 
-        if let Some(gba) = self.gba.as_mut() {
-            egui::Panel::left("trace").show_inside(ui, |ui| {
-                self.trace_opcode_viewer.render_ui(ui, gba);
+        if !fullscreen {
+            egui::Panel::top("main-menu").show_inside(ui, |ui| {
+                self.show_main_menu(ui, frame);
+            });
+        }
+        self.input_mapping.show_settings(ui.ctx());
+
+        let Some(emulator) = self.emulator.as_mut() else {
+            return;
+        };
+        let (loaded, load_error) = {
+            let status = emulator.status();
+            (status.loaded, status.load_error.clone())
+        };
+        if let Some(error) = load_error {
+            egui::Panel::bottom("load-error").show_inside(ui, |ui| {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Cannot load: {error}"));
             });
-            egui::CentralPanel::default().show_inside(ui, |ui| {
-                ui.label("TODO");
+        }
+        if !loaded {
+            return;
+        }
+        self.run_controls.handle_input(ui, emulator);
+        self.input_mapping.handle_input(ui, emulator);
+
+        if !fullscreen {
+            egui::Panel::top("run-controls").show_inside(ui, |ui| {
+                self.run_controls.show_toolbar(ui, emulator);
+            });
+            egui::Panel::left("trace").show_inside(ui, |ui| {
+                self.trace_opcode_viewer.render_ui(ui, emulator);
             });
             egui::Panel::right("disassemby").show_inside(ui, |ui| {
-                self.disassemby_view.render_ui(ui, gba);
+                self.disassemby_view.render_ui(ui, emulator);
             });
         }
+        egui::CentralPanel::default().show_inside(ui, |ui| {
+            self.screen_view.render_ui(ui, emulator.frame());
+            let status = emulator.status().clone();
+            RunControls::show_overlay(ui, ui.max_rect(), &status);
+        });
     }
 
-    fn begin_rom_if_possible(&mut self) {
-        if let Some(rom) = self.rom_path.as_ref()
-            && let Some(bios) = self.bios_path.as_ref()
-        {
-            match Gba::new(rom, bios) {
-                Ok(mut gba) => {
-                    gba.start();
-                    self.gba = Some(gba);
-                }
-                Err(err) => eprintln!("{}", err),
-            }
+    fn toggle_fullscreen(ctx: &Context) {
+        let fullscreen = ctx.input(|input| input.viewport().fullscreen.unwrap_or(false));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(!fullscreen));
+    }
+
+    fn begin_rom_if_possible(&mut self, ctx: &Context) {
+        if let Some(rom_path) = self.rom_path.clone() {
+            let emulator = self.emulator.get_or_insert_with(|| {
+                let emulator = Emulator::spawn(ctx.clone());
+                emulator.send(Command::SetBreakpoints(
+                    self.trace_opcode_viewer.breakpoints.clone(),
+                ));
+                self.audio_settings.apply(&emulator);
+                emulator
+            });
+            self.run_controls.apply(emulator);
+            emulator.send(Command::Load(LoadRequest {
+                rom_path,
+                bios_path: self.bios_path.clone(),
+                skip_bios: self.skip_bios,
+                hle_bios: self.hle_bios,
+            }));
         }
     }
 
@@ -82,24 +147,47 @@ impl GbaApp {
                     && let Some(rom_path) = rfd::FileDialog::new().pick_file()
                 {
                     self.rom_path = Some(rom_path);
-                    self.begin_rom_if_possible();
+                    self.begin_rom_if_possible(ui.ctx());
                 }
                 ui.separator();
                 if ui.button("Select BIOS").clicked()
                     && let Some(bios_path) = rfd::FileDialog::new().pick_file()
                 {
                     self.bios_path = Some(bios_path);
-                    self.begin_rom_if_possible();
+                    self.begin_rom_if_possible(ui.ctx());
                 }
                 if let Some(bios) = self.bios_path.as_ref() {
                     ui.label(bios.to_str().unwrap().to_string());
+                    if ui.button("Use built-in BIOS").clicked() {
+                        self.bios_path = None;
+                    }
                 }
+                ui.checkbox(&mut self.skip_bios, "Skip BIOS intro");
+                ui.checkbox(&mut self.hle_bios, "Emulate BIOS calls");
                 ui.separator();
                 if ui.button("Quit").clicked() {
                     ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
                 }
             });
 
+            ui.menu_button("View", |ui| {
+                self.screen_view.show_menu(ui);
+                ui.separator();
+                if ui.button("Fullscreen (F11)").clicked() {
+                    Self::toggle_fullscreen(ui.ctx());
+                }
+            });
+
+            ui.menu_button("Settings", |ui| {
+                if ui.button("Input").clicked() {
+                    self.input_mapping.settings_open = true;
+                }
+            });
+
+            ui.menu_button("Audio", |ui| {
+                self.audio_settings.show_menu(ui, self.emulator.as_ref());
+            });
+
             ui.menu_button("Debug", |ui| {
                 if ui.button("Trace").clicked() {}
                 if ui.button("Disassembly").clicked() {}
@@ -121,20 +209,34 @@ impl eframe::App for GbaApp {
 }
 
 #[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
-struct TraceOpcodeViewer {}
+struct TraceOpcodeViewer {
+    /// Addresses to pause at before executing them
+    breakpoints: Vec<u32>,
+    #[serde(skip)]
+    breakpoint_input: String,
+}
 
 impl TraceOpcodeViewer {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            breakpoints: Vec::new(),
+            breakpoint_input: String::new(),
+        }
     }
 
-    pub fn render_ui(&mut self, ui: &mut Ui, gba: &mut Gba) {
+    pub fn render_ui(&mut self, ui: &mut Ui, emulator: &Emulator) {
         if ui.button("Step").clicked() {
-            gba.step();
+            emulator.send(Command::StepInstruction);
         }
         ui.separator();
+        self.breakpoints_ui(ui, emulator);
+        ui.separator();
 
-        let opcodes = &gba.cpu.opcode_traces;
+        // Copied out so the emulation thread is not blocked while drawing
+        let (opcodes, symbols) = {
+            let status = emulator.status();
+            (status.traces.clone(), status.symbols.clone())
+        };
 
         TableBuilder::new(ui)
             .auto_shrink(false)
@@ -144,10 +246,10 @@ impl TraceOpcodeViewer {
             .column(Column::auto().at_least(20.0).resizable(false))
             .column(Column::remainder().at_least(100.0))
             .body(|mut body| {
-                for opcode in opcodes {
+                for opcode in &opcodes {
                     body.row(20.0, |mut row| match opcode {
                         OpcodeTraceLog::Decoded(opcode) => {
-                            Self::decoded_opcode_row(&mut row, opcode);
+                            Self::decoded_opcode_row(&mut row, opcode, &symbols);
                         }
                         OpcodeTraceLog::NotDecoded(execute_address, execute_opcode) => {
                             Self::not_decoded_opcode_row(
@@ -161,6 +263,40 @@ impl TraceOpcodeViewer {
             });
     }
 
+    fn breakpoints_ui(&mut self, ui: &mut Ui, emulator: &Emulator) {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.breakpoint_input)
+                    .hint_text("Address")
+                    .desired_width(80.0),
+            );
+            let address = self.breakpoint_input.trim().trim_start_matches("0x");
+            if ui.button("Add breakpoint").clicked()
+                && let Ok(address) = u32::from_str_radix(address, 16)
+                && !self.breakpoints.contains(&address)
+            {
+                self.breakpoints.push(address);
+                self.breakpoint_input.clear();
+                changed = true;
+            }
+        });
+
+        self.breakpoints.retain(|address| {
+            ui.horizontal(|ui| {
+                ui.label(format!("{:#010X}", address));
+                let remove = ui.small_button("x").clicked();
+                changed |= remove;
+                !remove
+            })
+            .inner
+        });
+
+        if changed {
+            emulator.send(Command::SetBreakpoints(self.breakpoints.clone()));
+        }
+    }
+
     fn not_decoded_opcode_row(ui: &mut TableRow, execute_address: u32, execute_opcode: u32) {
         ui.col(|ui: &mut Ui| {
             ui.colored_label(
@@ -176,9 +312,17 @@ impl TraceOpcodeViewer {
         });
     }
 
-    fn decoded_opcode_row(ui: &mut TableRow, opcode: &ExecutedOpcode) {
+    fn decoded_opcode_row(ui: &mut TableRow, opcode: &ExecutedOpcode, symbols: &SymbolTable) {
         ui.col(|ui: &mut Ui| {
-            ui.colored_label(COLOR_DECODED_INSTR_ADDR, format!("{:#08X}", opcode.address));
+            let label =
+                ui.colored_label(COLOR_DECODED_INSTR_ADDR, format!("{:#08X}", opcode.address));
+            if let Some(symbol) = symbols.symbol_at(opcode.address) {
+                label.on_hover_text(format!(
+                    "{}+{:#X}",
+                    symbol.name,
+                    opcode.address - symbol.address
+                ));
+            }
         });
         ui.col(|ui: &mut Ui| {
             ui.colored_label(
@@ -202,5 +346,5 @@ impl DisassemblyView {
         Self {}
     }
 
-    pub fn render_ui(&mut self, _ui: &mut Ui, _gba: &mut Gba) {}
+    pub fn render_ui(&mut self, _ui: &mut Ui, _emulator: &Emulator) {}
 }
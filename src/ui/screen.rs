@@ -0,0 +1,78 @@
+use eframe::egui::{self, Color32, ColorImage, TextureHandle, TextureOptions, Ui, Vec2};
+use gba::ppu::{FrameBuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Scaling {
+    /// The largest whole multiple of the native resolution that fits
+    #[default]
+    Integer,
+    /// Fill the panel while keeping the aspect ratio
+    Fit,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Filter {
+    #[default]
+    Nearest,
+    Linear,
+}
+
+impl Filter {
+    fn texture_options(self) -> TextureOptions {
+        match self {
+            Filter::Nearest => TextureOptions::NEAREST,
+            Filter::Linear => TextureOptions::LINEAR,
+        }
+    }
+}
+
+/// Shows the emulated screen scaled to the available space
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct ScreenView {
+    pub scaling: Scaling,
+    pub filter: Filter,
+    #[serde(skip)]
+    texture: Option<TextureHandle>,
+}
+
+impl ScreenView {
+    pub fn render_ui(&mut self, ui: &mut Ui, frame_buffer: &FrameBuffer) {
+        let image = ColorImage::from_rgba_unmultiplied(
+            [SCREEN_WIDTH, SCREEN_HEIGHT],
+            &frame_buffer.to_rgba8(),
+        );
+        let options = self.filter.texture_options();
+        let texture = match self.texture.as_mut() {
+            Some(texture) => {
+                texture.set(image, options);
+                texture
+            }
+            None => self
+                .texture
+                .insert(ui.ctx().load_texture("screen", image, options)),
+        };
+
+        let native = Vec2::new(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32);
+        let available = ui.available_size();
+        let scale = (available.x / native.x).min(available.y / native.y);
+        let scale = match self.scaling {
+            Scaling::Integer if scale >= 1.0 => scale.floor(),
+            _ => scale,
+        };
+
+        ui.painter().rect_filled(ui.max_rect(), 0.0, Color32::BLACK);
+        ui.centered_and_justified(|ui| {
+            ui.add(egui::Image::from_texture((texture.id(), native * scale)));
+        });
+    }
+
+    pub fn show_menu(&mut self, ui: &mut Ui) {
+        ui.label("Scaling");
+        ui.radio_value(&mut self.scaling, Scaling::Integer, "Integer");
+        ui.radio_value(&mut self.scaling, Scaling::Fit, "Fit to window");
+        ui.separator();
+        ui.label("Filtering");
+        ui.radio_value(&mut self.filter, Filter::Nearest, "Nearest");
+        ui.radio_value(&mut self.filter, Filter::Linear, "Linear");
+    }
+}